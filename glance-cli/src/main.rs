@@ -1,10 +1,25 @@
-use std::path::PathBuf;
+use std::{
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    time::SystemTime,
+};
 
 use anyhow::{anyhow, Result};
-use clap::{Parser, Subcommand, ValueEnum};
+use chrono::{Local, NaiveDate, TimeDelta};
+use clap::{Args as ClapArgs, Parser, Subcommand, ValueEnum};
 use directories::ProjectDirs;
-use glance_lib::index::{AddDirectoryConfig, Index as GlanceIndex};
+use glance_lib::index::{
+    dedupe::{DuplicateAction, KeepPolicy, ResolveDuplicatesConfig},
+    event::EventConfig,
+    media::{Flag, LabelQuery, Media, MediaFilter, SortKey},
+    memories::MemoriesConfig,
+    place::{Place as GlancePlace, TripConfig},
+    rules::RuleSet,
+    thumbnail::{ThumbnailCache, DEFAULT_SIZE},
+    AddDirectoryConfig, Index as GlanceIndex,
+};
 use glance_util::canonicalized_path_buf::CanonicalizedPathBuf;
+use slog::info;
 use sloggers::{
     terminal::TerminalLoggerBuilder,
     types::{Severity, SourceLocation},
@@ -22,6 +37,9 @@ struct Args {
     /// Path to save the media db index
     #[arg(long, default_value = default_main_index_path())]
     index: PathBuf,
+    /// Directory to store thumbnails in
+    #[arg(long, default_value = default_thumbnail_cache_path())]
+    thumbnail_cache: PathBuf,
     /// Disable hashing of files when storing in index
     ///
     /// This drasticly speeds up the initial indexing
@@ -40,6 +58,23 @@ struct Args {
     /// Disable using the exiftool cli fallback when the library fails
     #[arg(long)]
     disable_exiftool: bool,
+    /// Compute a perceptual hash of images for near duplicate detection
+    ///
+    /// This requires decoding every image so it slows down indexing
+    #[arg(long)]
+    perceptual_hash: bool,
+    /// Disable reading ratings from xmp sidecars and embedded xmp
+    #[arg(long)]
+    disable_xmp: bool,
+    /// Write ratings and flags to xmp sidecars when setting them
+    #[arg(long)]
+    write_xmp: bool,
+    /// TOML file of rules whose labels are added to newly indexed media
+    #[arg(long)]
+    rules: Option<PathBuf>,
+    /// Label newly indexed media with the places they were taken in
+    #[arg(long)]
+    label_places: bool,
     /// Log level
     #[arg(long)]
     log_level: Option<Severity>,
@@ -68,7 +103,68 @@ enum Command {
     StandardizeNaming(StandardizeNaming),
     /// Print stats on the media
     #[command()]
-    Stats,
+    Stats(Stats),
+    /// Print clusters of media that look alike as JSON
+    ///
+    /// Only media indexed with `--perceptual-hash` are considered
+    #[command()]
+    NearDuplicates(NearDuplicates),
+    /// Reduce each set of duplicate media to a single copy
+    ///
+    /// Labels from every copy are merged onto the kept copy. The resolutions are printed as JSON
+    /// and a journal is written that can be used to undo them.
+    #[command()]
+    Dedupe(Dedupe),
+    /// Undo a dedupe using its journal
+    #[command()]
+    UndoDedupe(UndoDedupe),
+    /// Generate missing thumbnails for all media in the index
+    #[command()]
+    Thumbnails(Thumbnails),
+    /// Print indexed media as JSON
+    #[command()]
+    Info(Info),
+    /// Set the star rating of media
+    #[command()]
+    Rate(Rate),
+    /// Mark media as picked or rejected
+    #[command()]
+    Flag(SetFlag),
+    /// Set the title, caption or notes of media
+    #[command()]
+    Describe(Describe),
+    /// Print or link the media matching a query and filters
+    #[command()]
+    Search(Search),
+    /// Print saved searches as JSON
+    #[command()]
+    SavedSearches(SavedSearches),
+    /// Manage albums of media in an explicit order
+    #[command()]
+    Album(Album),
+    /// Group media into events by when and where they were taken
+    #[command()]
+    Event(Event),
+    /// Manage named places like Home that media can be labeled and filtered by
+    #[command()]
+    Place(Place),
+    /// Print the periods spent away from home as JSON
+    #[command()]
+    Trips(Trips),
+    /// Print the media taken on this day in earlier years as JSON, grouped by year
+    #[command()]
+    Memories(Memories),
+    /// Manage hierarchical labels like `places/portugal/lisbon` across all media
+    #[command()]
+    Label(Label),
+    /// Print the people named in face regions as JSON
+    #[command()]
+    People(People),
+    /// Add the labels of rules to media already in the index
+    ///
+    /// The labels added are printed as JSON.
+    #[command()]
+    ApplyRules(ApplyRules),
 }
 
 #[derive(Debug, Parser)]
@@ -111,6 +207,452 @@ struct StandardizeNaming {
     naming: Standardization,
 }
 
+#[derive(Debug, Parser)]
+struct Stats {
+    #[command(flatten)]
+    filter: FilterArgs,
+}
+
+/// Filters for selecting media from the index
+#[derive(Debug, ClapArgs)]
+struct FilterArgs {
+    /// Only include media with all of these labels
+    #[arg(long = "label")]
+    labels: Vec<String>,
+    /// Only include media with at least one of these labels
+    #[arg(long = "any-label")]
+    any_labels: Vec<String>,
+    /// Exclude media with any of these labels
+    #[arg(long = "not-label")]
+    not_labels: Vec<String>,
+    /// Only include media from this device
+    #[arg(long)]
+    device: Option<String>,
+    /// Only include media of this format
+    #[arg(long)]
+    format: Option<String>,
+    /// Only include media rated at least this many stars
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=5))]
+    min_rating: Option<u8>,
+    /// Only include media with this flag
+    #[arg(long)]
+    flag: Option<Flag>,
+    /// Only include media in this album, which are then in album order
+    #[arg(long)]
+    album: Option<String>,
+    /// Only include media with face regions of all of these people
+    #[arg(long = "person")]
+    people: Vec<String>,
+    /// Only include media in the event with this id
+    #[arg(long)]
+    event: Option<i64>,
+    /// Only include media taken inside the place with this name
+    #[arg(long)]
+    place: Option<String>,
+}
+
+impl FilterArgs {
+    fn to_media_filter(&self) -> MediaFilter {
+        let labels = |labels: &[String]| labels.iter().map(|l| l.as_str().into()).collect();
+        let mut queries: Vec<LabelQuery> = labels(&self.labels);
+        if !self.any_labels.is_empty() {
+            queries.push(LabelQuery::AnyOf(labels(&self.any_labels)));
+        }
+        if !self.not_labels.is_empty() {
+            queries.push(LabelQuery::NoneOf(labels(&self.not_labels)));
+        }
+        MediaFilter {
+            label: (!queries.is_empty()).then_some(LabelQuery::AllOf(queries)),
+            device: self.device.clone(),
+            format: self.format.clone(),
+            min_rating: self.min_rating,
+            flag: self.flag,
+            album: self.album.clone(),
+            people: self.people.clone(),
+            event: self.event,
+            place: self.place.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Parser)]
+struct Search {
+    /// Query like `label:beach taken:2019..2021 -label:blurry`
+    ///
+    /// Fields set by both the query and the filter flags use the query.
+    #[arg(long)]
+    query: Option<String>,
+    /// Start from a saved search, which the query and filter flags narrow down
+    #[arg(long)]
+    saved: Option<String>,
+    /// Save the query and filters under this name instead of printing the matching media
+    #[arg(long)]
+    save: Option<String>,
+    #[command(flatten)]
+    filter: FilterArgs,
+    /// How to print the matching media
+    #[arg(long, value_enum, default_value_t = Output::Paths)]
+    output: Output,
+    /// Create a directory of numbered symlinks to the matching media instead of printing them
+    #[arg(long, conflicts_with = "output")]
+    symlink_dir: Option<PathBuf>,
+    /// Order of the matching media, defaults to how well they match the words in the query or
+    /// created when there are none
+    #[arg(long, value_enum)]
+    sort: Option<Sort>,
+    /// Reverse the order
+    #[arg(long)]
+    reverse: bool,
+    /// Skip this many matching media
+    #[arg(long)]
+    offset: Option<u64>,
+    /// Maximum number of media to output
+    #[arg(long)]
+    limit: Option<u64>,
+}
+
+#[derive(Debug, Parser)]
+struct People {
+    /// Print the face regions of this media instead
+    #[arg(long)]
+    regions: Option<CanonicalizedPathBuf>,
+}
+
+#[derive(Debug, Parser)]
+struct ApplyRules {
+    /// TOML file of rules
+    #[arg(long)]
+    rules: PathBuf,
+    /// Only print the labels that would be added
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(Debug, Parser)]
+struct SavedSearches {
+    /// Delete the saved search with this name instead
+    #[arg(long)]
+    delete: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+struct Label {
+    #[command(subcommand)]
+    command: LabelCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum LabelCommand {
+    /// Print all labels as a JSON tree
+    List,
+    /// Rename a label and the labels below it, merging into an existing label
+    Rename { from: String, to: String },
+    /// Rename several labels to one label
+    Merge {
+        labels: Vec<String>,
+        #[arg(long)]
+        into: String,
+    },
+    /// Remove a label and the labels below it from all media
+    Delete { label: String },
+}
+
+#[derive(Debug, Parser)]
+struct Album {
+    #[command(subcommand)]
+    command: AlbumCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum AlbumCommand {
+    /// Create an empty album
+    Create {
+        name: String,
+        #[arg(long)]
+        description: Option<String>,
+    },
+    /// Delete an album, leaving its media in the index
+    Delete { name: String },
+    /// Print all albums as JSON
+    List,
+    /// Print the media in an album in order as JSON
+    Show { name: String },
+    /// Add media to an album
+    Add {
+        name: String,
+        /// Media to add
+        #[arg(long)]
+        paths: Vec<CanonicalizedPathBuf>,
+        /// Position to insert the media at, starting from 0, defaults to the end
+        #[arg(long)]
+        position: Option<usize>,
+    },
+    /// Remove media from an album
+    Remove {
+        name: String,
+        /// Media to remove
+        #[arg(long)]
+        paths: Vec<CanonicalizedPathBuf>,
+    },
+    /// Move media in an album to a new position
+    Move {
+        name: String,
+        /// Media to move
+        #[arg(long)]
+        path: CanonicalizedPathBuf,
+        /// Position to move the media to, starting from 0
+        #[arg(long)]
+        position: usize,
+    },
+    /// Set or clear the cover and description of an album
+    Edit {
+        name: String,
+        /// Media in the album to show for it
+        #[arg(long)]
+        cover: Option<CanonicalizedPathBuf>,
+        /// Clear the cover
+        #[arg(long, conflicts_with = "cover")]
+        clear_cover: bool,
+        /// New description, an empty one clears it
+        #[arg(long)]
+        description: Option<String>,
+    },
+    /// Create a directory of numbered symlinks to the media in album order
+    Export {
+        name: String,
+        #[arg(long)]
+        symlink_dir: PathBuf,
+    },
+}
+
+#[derive(Debug, Parser)]
+struct Event {
+    #[command(subcommand)]
+    command: EventCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum EventCommand {
+    /// Add media that are not in an event yet to nearby or new events and print those as JSON
+    Detect {
+        /// Longest time in hours between media in the same event
+        #[arg(long, default_value_t = 6)]
+        max_gap_hours: i64,
+        /// Longest distance in kilometres between geotagged media in the same event
+        #[arg(long, default_value_t = 50.0)]
+        max_distance_km: f64,
+    },
+    /// Print all events as JSON
+    List,
+    /// Print the media in an event as JSON
+    Show { id: i64 },
+    /// Rename an event
+    Rename { id: i64, name: String },
+    /// Move the media of an event taken from a media onwards into a new event
+    Split {
+        id: i64,
+        /// First media of the new event
+        #[arg(long)]
+        at: CanonicalizedPathBuf,
+    },
+    /// Move the media of other events into an event
+    Merge {
+        id: i64,
+        #[arg(long)]
+        others: Vec<i64>,
+    },
+    /// Delete all events so they can be detected again
+    Clear,
+}
+
+#[derive(Debug, Parser)]
+struct Place {
+    #[command(subcommand)]
+    command: PlaceCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum PlaceCommand {
+    /// Add a place or move the place with the same name
+    Set {
+        name: String,
+        #[arg(long, allow_negative_numbers = true)]
+        latitude: f64,
+        #[arg(long, allow_negative_numbers = true)]
+        longitude: f64,
+        #[arg(long, default_value_t = 1.0)]
+        radius_km: f64,
+    },
+    /// Delete a place, leaving the labels of media taken in it
+    Delete { name: String },
+    /// Print all places as JSON
+    List,
+    /// Label the media already in the index with the places they were taken in
+    Label,
+}
+
+#[derive(Debug, Parser)]
+struct Trips {
+    /// Place trips start from and return to
+    #[arg(long, default_value = "Home")]
+    home: String,
+    /// Longest time in days between media away from home in the same trip
+    #[arg(long, default_value_t = 3)]
+    max_gap_days: i64,
+}
+
+#[derive(Debug, Parser)]
+struct Memories {
+    /// Day to find memories of as YYYY-MM-DD, today by default
+    #[arg(long)]
+    date: Option<NaiveDate>,
+    /// Days before and after the day that are included
+    #[arg(long, default_value_t = MemoriesConfig::default().window_days)]
+    window_days: u32,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Output {
+    /// One path per line
+    Paths,
+    /// Paths separated by NUL for `xargs -0`
+    Nul,
+    /// JSON array of media
+    Json,
+    /// CSV with a header row
+    Csv,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Sort {
+    Created,
+    Modified,
+    Random,
+    Size,
+    Path,
+}
+
+#[derive(Debug, Parser)]
+struct NearDuplicates {
+    /// Maximum number of differing bits between perceptual hashes of near duplicates
+    #[arg(long, default_value_t = 10)]
+    threshold: u32,
+}
+
+#[derive(Debug, Parser)]
+struct Dedupe {
+    /// Which copy of each duplicate set to keep
+    #[arg(long, value_enum, default_value_t = Keep::Oldest)]
+    keep: Keep,
+    /// Directory to prefer keeping copies under with `--keep preferred-root`
+    #[arg(long, required_if_eq("keep", "preferred-root"))]
+    preferred_root: Option<CanonicalizedPathBuf>,
+    /// What to do with the copies that are not kept
+    #[arg(long, value_enum, default_value_t = Action::DryRun)]
+    action: Action,
+    /// Directory to move copies into with `--action quarantine`
+    ///
+    /// This should not be inside an indexed directory
+    #[arg(long, default_value = default_quarantine_path())]
+    quarantine: PathBuf,
+    /// Path to write the journal to, defaults to a new file in the data directory
+    #[arg(long)]
+    journal: Option<PathBuf>,
+}
+
+#[derive(Debug, Parser)]
+struct UndoDedupe {
+    /// Journal written by the dedupe to undo
+    #[arg(long)]
+    journal: PathBuf,
+}
+
+#[derive(Debug, Parser)]
+struct Thumbnails {
+    /// Longest edge in pixels of the thumbnails to generate
+    #[arg(long, default_values_t = [DEFAULT_SIZE])]
+    sizes: Vec<u32>,
+    /// Remove thumbnails that do not belong to any indexed media
+    #[arg(long)]
+    clean: bool,
+}
+
+#[derive(Debug, Parser)]
+struct Info {
+    /// Media to print
+    #[arg(long)]
+    paths: Vec<CanonicalizedPathBuf>,
+}
+
+#[derive(Debug, Parser)]
+struct Rate {
+    /// Stars from 0 (unrated) to 5
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=5))]
+    rating: u8,
+    /// Media to rate
+    #[arg(long)]
+    paths: Vec<CanonicalizedPathBuf>,
+}
+
+#[derive(Debug, Parser)]
+struct SetFlag {
+    /// Flag to set
+    #[arg(long, value_enum)]
+    flag: FlagArg,
+    /// Media to flag
+    #[arg(long)]
+    paths: Vec<CanonicalizedPathBuf>,
+}
+
+#[derive(Debug, Parser)]
+struct Describe {
+    /// Title, an empty one clears it
+    #[arg(long)]
+    title: Option<String>,
+    /// Caption, an empty one clears it
+    #[arg(long)]
+    caption: Option<String>,
+    /// Notes, empty ones clear them
+    #[arg(long)]
+    notes: Option<String>,
+    /// Media to describe
+    #[arg(long)]
+    paths: Vec<CanonicalizedPathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum FlagArg {
+    Pick,
+    Reject,
+    /// Clear the flag
+    None,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Keep {
+    /// Keep the copy with the oldest modified time
+    Oldest,
+    /// Keep the copy under `--preferred-root`
+    PreferredRoot,
+    /// Keep the copy with the shortest path
+    ShortestPath,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Action {
+    /// Only print what would be done
+    DryRun,
+    /// Move the copies into `--quarantine`
+    Quarantine,
+    /// Delete the copies, this cannot be undone
+    Delete,
+    /// Replace the copies with hardlinks to the kept copy
+    Hardlink,
+    /// Replace the copies with copy on write clones of the kept copy
+    Reflink,
+}
+
 #[derive(Clone, Copy, Debug, ValueEnum)]
 enum Standardization {
     /// Standardize naming of files by moving them to folders of format `YY-mm` within
@@ -124,6 +666,12 @@ fn data_directory() -> PathBuf {
         .unwrap_or_else(|| ".".into())
 }
 
+fn cache_directory() -> PathBuf {
+    ProjectDirs::from(QUALIFIER, ORGANIZATION, APPLICATION)
+        .map(|proj_dirs| proj_dirs.cache_dir().to_path_buf())
+        .unwrap_or_else(|| ".".into())
+}
+
 fn default_main_index_path() -> clap::builder::OsStr {
     let mut path = data_directory();
     path.push("main.db");
@@ -136,6 +684,68 @@ fn default_import_index_path() -> clap::builder::OsStr {
     path.into_os_string().into()
 }
 
+fn default_thumbnail_cache_path() -> clap::builder::OsStr {
+    let mut path = cache_directory();
+    path.push("thumbnails");
+    path.into_os_string().into()
+}
+
+fn default_quarantine_path() -> clap::builder::OsStr {
+    let mut path = data_directory();
+    path.push("quarantine");
+    path.into_os_string().into()
+}
+
+fn write_csv<W, I>(writer: W, media_iter: I) -> Result<()>
+where
+    W: Write,
+    I: Iterator<Item = Result<Media, glance_lib::index::Error>>,
+{
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record([
+        "filepath",
+        "size",
+        "format",
+        "created",
+        "modified",
+        "location",
+        "country",
+        "device",
+        "hash",
+        "rating",
+        "flag",
+        "latitude",
+        "longitude",
+        "title",
+        "caption",
+        "notes",
+    ])?;
+    for media in media_iter {
+        let media = media?;
+        let optional = |value: Option<String>| value.unwrap_or_default();
+        writer.write_record([
+            media.filepath.display().to_string(),
+            media.size.0.to_string(),
+            media.format.clone(),
+            optional(media.created.map(|created| created.to_rfc3339())),
+            media.modified.to_rfc3339(),
+            optional(media.location.clone()),
+            optional(media.country.clone()),
+            optional(media.device.as_ref().map(|device| device.0.clone())),
+            optional(media.hash.map(|hash| hash.to_string())),
+            media.rating.to_string(),
+            optional(media.flag.map(|flag| flag.to_string())),
+            optional(media.latitude.map(|latitude| latitude.to_string())),
+            optional(media.longitude.map(|longitude| longitude.to_string())),
+            optional(media.title),
+            optional(media.caption),
+            optional(media.notes),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
 fn main() -> Result<()> {
     let args = Args::parse();
 
@@ -146,7 +756,10 @@ fn main() -> Result<()> {
         .build()?;
 
     std::fs::create_dir_all(data_directory())?;
-    let mut index = GlanceIndex::new(args.index)?.with_logger(logger.clone());
+    let mut index = GlanceIndex::new(args.index)?
+        .with_logger(logger.clone())
+        .with_thumbnail_cache(ThumbnailCache::new(&args.thumbnail_cache))
+        .with_write_xmp(args.write_xmp);
 
     let config = AddDirectoryConfig {
         hash: !args.disable_hash,
@@ -154,6 +767,14 @@ fn main() -> Result<()> {
         metadata_fallback_for_created: args.metadata_fallback_for_created,
         calculate_nearest_city: args.calculate_nearest_city,
         use_exiftool: !args.disable_exiftool,
+        perceptual_hash: args.perceptual_hash,
+        read_xmp: !args.disable_xmp,
+        rules: args
+            .rules
+            .map(RuleSet::from_file)
+            .transpose()?
+            .unwrap_or_default(),
+        label_places: args.label_places,
     };
 
     match args.command {
@@ -188,10 +809,336 @@ fn main() -> Result<()> {
                 index.standardize_year_month_naming_many(sub_args.paths)?
             }
         },
-        Command::Stats => {
-            let stats = index.stats()?;
+        Command::Stats(sub_args) => {
+            let stats = index.stats_with_filter(&sub_args.filter.to_media_filter())?;
             println!("{}", serde_json::to_string_pretty(&stats)?);
         }
+        Command::NearDuplicates(sub_args) => {
+            let near_duplicates = index.near_duplicates(sub_args.threshold)?;
+            println!("{}", serde_json::to_string_pretty(&near_duplicates)?);
+        }
+        Command::Dedupe(sub_args) => {
+            let keep = match sub_args.keep {
+                Keep::Oldest => KeepPolicy::Oldest,
+                Keep::PreferredRoot => KeepPolicy::PreferredRoot(
+                    sub_args
+                        .preferred_root
+                        .ok_or_else(|| anyhow!("--preferred-root is required"))?
+                        .into(),
+                ),
+                Keep::ShortestPath => KeepPolicy::ShortestPath,
+            };
+            let action = match sub_args.action {
+                Action::DryRun => DuplicateAction::DryRun,
+                Action::Quarantine => DuplicateAction::Quarantine(sub_args.quarantine),
+                Action::Delete => DuplicateAction::Delete,
+                Action::Hardlink => DuplicateAction::Hardlink,
+                Action::Reflink => DuplicateAction::Reflink,
+            };
+            let journal = sub_args.journal.unwrap_or_else(|| {
+                let timestamp = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                data_directory().join(format!("dedupe-{timestamp}.jsonl"))
+            });
+            if !matches!(action, DuplicateAction::DryRun) {
+                info!(logger, "writing dedupe journal"; "journal" => journal.display());
+            }
+            let resolutions = index.resolve_duplicates(&ResolveDuplicatesConfig {
+                keep,
+                action,
+                journal: Some(journal),
+            })?;
+            println!("{}", serde_json::to_string_pretty(&resolutions)?);
+        }
+        Command::UndoDedupe(sub_args) => {
+            index.revert_duplicate_resolutions(sub_args.journal)?;
+        }
+        Command::Thumbnails(sub_args) => {
+            if sub_args.clean {
+                index.clean_thumbnails()?;
+            }
+            ThumbnailCache::new(&args.thumbnail_cache).prewarm(
+                &index.get_media()?,
+                &sub_args.sizes,
+                &logger,
+            );
+        }
+        Command::Info(sub_args) => {
+            let media_vec = sub_args
+                .paths
+                .iter()
+                .map(|path| {
+                    index
+                        .get_media_by_path(path)?
+                        .ok_or_else(|| anyhow!("{} is not indexed", path.as_ref().display()))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            println!("{}", serde_json::to_string_pretty(&media_vec)?);
+        }
+        Command::Rate(sub_args) => {
+            for path in sub_args.paths {
+                index.set_rating(path, sub_args.rating)?;
+            }
+        }
+        Command::Flag(sub_args) => {
+            let flag = match sub_args.flag {
+                FlagArg::Pick => Some(Flag::Pick),
+                FlagArg::Reject => Some(Flag::Reject),
+                FlagArg::None => None,
+            };
+            for path in sub_args.paths {
+                index.set_flag(path, flag)?;
+            }
+        }
+        Command::Describe(sub_args) => {
+            for path in sub_args.paths {
+                let mut text = index.get_media_text(&path)?;
+                for (value, field) in [
+                    (&sub_args.title, &mut text.title),
+                    (&sub_args.caption, &mut text.caption),
+                    (&sub_args.notes, &mut text.notes),
+                ] {
+                    if let Some(value) = value {
+                        *field = Some(value.clone());
+                    }
+                }
+                index.set_media_text(&path, &text)?;
+            }
+        }
+        Command::Search(sub_args) => {
+            let query = sub_args
+                .query
+                .as_deref()
+                .unwrap_or_default()
+                .parse::<MediaFilter>()?;
+            let saved = match &sub_args.saved {
+                Some(name) => {
+                    index
+                        .get_saved_search(name)?
+                        .ok_or_else(|| anyhow!("no saved search named '{name}'"))?
+                        .filter
+                }
+                None => MediaFilter::default(),
+            };
+            let filter = query.and(sub_args.filter.to_media_filter()).and(saved);
+            let filter = MediaFilter {
+                sort: sub_args
+                    .sort
+                    .map(|sort| match sort {
+                        Sort::Created => SortKey::Created,
+                        Sort::Modified => SortKey::Modified,
+                        Sort::Random => SortKey::Random,
+                        Sort::Size => SortKey::Size,
+                        Sort::Path => SortKey::Path,
+                    })
+                    .or(filter.sort),
+                descending: sub_args.reverse || filter.descending,
+                offset: sub_args.offset.or(filter.offset),
+                limit: sub_args.limit.or(filter.limit),
+                ..filter
+            };
+
+            if let Some(name) = sub_args.save {
+                index.save_search(&name, filter)?;
+                return Ok(());
+            }
+            if let Some(symlink_dir) = sub_args.symlink_dir {
+                index.export_symlinks(&index.get_media_with_filter(filter)?, symlink_dir)?;
+                return Ok(());
+            }
+            // Stream the media so large results are never all in memory
+            let mut cursor = index.media_cursor(filter)?;
+            let mut media_iter = cursor.iter()?;
+            let mut stdout = BufWriter::new(io::stdout().lock());
+            match sub_args.output {
+                Output::Paths => {
+                    for media in media_iter {
+                        writeln!(stdout, "{}", media?.filepath.display())?;
+                    }
+                }
+                Output::Nul => {
+                    for media in media_iter {
+                        write!(stdout, "{}\0", media?.filepath.display())?;
+                    }
+                }
+                Output::Json => {
+                    write!(stdout, "[")?;
+                    if let Some(media) = media_iter.next() {
+                        serde_json::to_writer_pretty(&mut stdout, &media?)?;
+                    }
+                    for media in media_iter {
+                        write!(stdout, ",")?;
+                        serde_json::to_writer_pretty(&mut stdout, &media?)?;
+                    }
+                    writeln!(stdout, "]")?;
+                }
+                Output::Csv => write_csv(&mut stdout, media_iter)?,
+            }
+            stdout.flush()?;
+        }
+        Command::SavedSearches(sub_args) => match sub_args.delete {
+            Some(name) => {
+                if !index.delete_saved_search(&name)? {
+                    return Err(anyhow!("no saved search named '{name}'"));
+                }
+            }
+            None => {
+                let saved_searches = index.get_saved_searches()?;
+                println!("{}", serde_json::to_string_pretty(&saved_searches)?);
+            }
+        },
+        Command::People(sub_args) => match sub_args.regions {
+            Some(path) => {
+                let regions = index.get_face_regions(path)?;
+                println!("{}", serde_json::to_string_pretty(&regions)?);
+            }
+            None => println!("{}", serde_json::to_string_pretty(&index.get_people()?)?),
+        },
+        Command::ApplyRules(sub_args) => {
+            let rules = RuleSet::from_file(sub_args.rules)?;
+            let report = index.apply_rules(&rules, sub_args.dry_run)?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+        Command::Label(sub_args) => match sub_args.command {
+            LabelCommand::List => {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&index.get_label_tree()?)?
+                );
+            }
+            LabelCommand::Rename { from, to } => {
+                index.rename_label(&from, &to)?;
+            }
+            LabelCommand::Merge { labels, into } => {
+                index.merge_labels(&labels, &into)?;
+            }
+            LabelCommand::Delete { label } => {
+                let deleted = index.delete_label_everywhere(&label)?;
+                info!(logger, "deleted label"; "label" => label, "deleted" => deleted);
+            }
+        },
+        Command::Event(sub_args) => match sub_args.command {
+            EventCommand::Detect {
+                max_gap_hours,
+                max_distance_km,
+            } => {
+                let config = EventConfig {
+                    max_gap: TimeDelta::hours(max_gap_hours),
+                    max_distance_km,
+                };
+                let events = index.detect_events(&config)?;
+                println!("{}", serde_json::to_string_pretty(&events)?);
+            }
+            EventCommand::List => {
+                println!("{}", serde_json::to_string_pretty(&index.get_events()?)?);
+            }
+            EventCommand::Show { id } => {
+                let media_vec = index.get_event_media(id)?;
+                println!("{}", serde_json::to_string_pretty(&media_vec)?);
+            }
+            EventCommand::Rename { id, name } => {
+                index.rename_event(id, &name)?;
+            }
+            EventCommand::Split { id, at } => {
+                let event = index.split_event(id, at)?;
+                println!("{}", serde_json::to_string_pretty(&event)?);
+            }
+            EventCommand::Merge { id, others } => {
+                let event = index.merge_events(id, &others)?;
+                println!("{}", serde_json::to_string_pretty(&event)?);
+            }
+            EventCommand::Clear => index.clear_events()?,
+        },
+        Command::Place(sub_args) => match sub_args.command {
+            PlaceCommand::Set {
+                name,
+                latitude,
+                longitude,
+                radius_km,
+            } => index.set_place(GlancePlace {
+                name,
+                latitude,
+                longitude,
+                radius_km,
+            })?,
+            PlaceCommand::Delete { name } => index.delete_place(&name)?,
+            PlaceCommand::List => {
+                println!("{}", serde_json::to_string_pretty(&index.get_places()?)?);
+            }
+            PlaceCommand::Label => {
+                let added = index.label_places()?;
+                info!(logger, "labeled media with places"; "added" => added);
+            }
+        },
+        Command::Trips(sub_args) => {
+            let config = TripConfig {
+                home: sub_args.home,
+                max_gap: TimeDelta::days(sub_args.max_gap_days),
+            };
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&index.get_trips(&config)?)?
+            );
+        }
+        Command::Memories(sub_args) => {
+            let date = sub_args.date.unwrap_or_else(|| Local::now().date_naive());
+            let config = MemoriesConfig {
+                window_days: sub_args.window_days,
+            };
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&index.get_memories(date, &config)?)?
+            );
+        }
+        Command::Album(sub_args) => match sub_args.command {
+            AlbumCommand::Create { name, description } => {
+                index.create_album(&name, description.as_deref())?;
+            }
+            AlbumCommand::Delete { name } => index.delete_album(&name)?,
+            AlbumCommand::List => {
+                println!("{}", serde_json::to_string_pretty(&index.get_albums()?)?);
+            }
+            AlbumCommand::Show { name } => {
+                let media_vec = index.get_album_media(&name)?;
+                println!("{}", serde_json::to_string_pretty(&media_vec)?);
+            }
+            AlbumCommand::Add {
+                name,
+                paths,
+                position,
+            } => {
+                let added = index.add_to_album(&name, paths, position)?;
+                info!(logger, "added media to album"; "album" => name, "added" => added);
+            }
+            AlbumCommand::Remove { name, paths } => {
+                let removed = index.remove_from_album(&name, paths)?;
+                info!(logger, "removed media from album"; "album" => name, "removed" => removed);
+            }
+            AlbumCommand::Move {
+                name,
+                path,
+                position,
+            } => index.move_in_album(&name, path, position)?,
+            AlbumCommand::Edit {
+                name,
+                cover,
+                clear_cover,
+                description,
+            } => {
+                if cover.is_some() || clear_cover {
+                    index.set_album_cover(&name, cover)?;
+                }
+                if let Some(description) = description {
+                    let description = (!description.is_empty()).then_some(description.as_str());
+                    index.set_album_description(&name, description)?;
+                }
+            }
+            AlbumCommand::Export { name, symlink_dir } => {
+                index.export_symlinks(&index.get_album_media(&name)?, symlink_dir)?;
+            }
+        },
     }
 
     Ok(())
@@ -1,9 +1,10 @@
 use anyhow::Result;
 use chrono::Local;
 use glance_lib::index::media::Media;
+use glance_lib::index::thumbnail::{ThumbnailCache, DEFAULT_SIZE};
 use glance_lib::index::Index;
 use iced::widget::{button, column, container, image, row, text};
-use iced::{executor, subscription, Application, Command, Element, Event, Settings, Theme};
+use iced::{executor, subscription, Application, Command, Element, Event, Length, Settings, Theme};
 use iced::{keyboard, Subscription};
 use sloggers::terminal::TerminalLoggerBuilder;
 use sloggers::Build;
@@ -17,6 +18,7 @@ pub fn main() -> Result<()> {
 struct GlanceUi {
     media_vec: Vec<Media>,
     current_media_idx: Option<usize>,
+    thumbnail_cache: Option<ThumbnailCache>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -34,7 +36,8 @@ impl Application for GlanceUi {
     fn new(_flags: ()) -> (Self, Command<Message>) {
         let index = Index::new("test.db")
             .expect("unable to initialize index")
-            .with_logger(TerminalLoggerBuilder::new().build().unwrap());
+            .with_logger(TerminalLoggerBuilder::new().build().unwrap())
+            .with_thumbnail_cache(ThumbnailCache::new("thumbnails"));
         // index
         //     .add_directory(
         //         "/media/luke/TOSHIBA-SILVER/pictures/2012",
@@ -53,6 +56,7 @@ impl Application for GlanceUi {
             Self {
                 media_vec,
                 current_media_idx,
+                thumbnail_cache: index.thumbnail_cache().cloned(),
             },
             Command::none(),
         )
@@ -99,7 +103,7 @@ impl Application for GlanceUi {
         })
     }
 
-    fn view(&self) -> Element<Message> {
+    fn view(&self) -> Element<'_, Message> {
         let buttons = row![
             button("Previous")
                 .padding([10, 20])
@@ -128,6 +132,17 @@ impl Application for GlanceUi {
 
             let image = image(media.filepath.clone());
             contents = contents.push(image);
+
+            let mut thumbnails = row![].spacing(10);
+            for next_media in self.media_vec.iter().skip(idx + 1).take(9) {
+                let path = self
+                    .thumbnail_cache
+                    .as_ref()
+                    .and_then(|thumbnail_cache| thumbnail_cache.get(next_media, DEFAULT_SIZE).ok())
+                    .unwrap_or_else(|| next_media.filepath.clone());
+                thumbnails = thumbnails.push(iced::widget::image(path).width(Length::Fixed(100.0)));
+            }
+            contents = contents.push(thumbnails);
         }
 
         container(contents).padding(20).into()
@@ -0,0 +1,26 @@
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::store::album_sql::AlbumSql;
+
+/// Manually curated collection of media in an explicit order
+#[derive(Debug, Clone, Serialize)]
+pub struct Album {
+    pub name: String,
+    pub description: Option<String>,
+    /// Media shown for the album, the first item when not set
+    pub cover: Option<PathBuf>,
+    pub count: i64,
+}
+
+impl From<AlbumSql> for Album {
+    fn from(value: AlbumSql) -> Self {
+        Self {
+            name: value.name,
+            description: value.description,
+            cover: value.cover.map(PathBuf::from),
+            count: value.count,
+        }
+    }
+}
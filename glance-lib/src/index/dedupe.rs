@@ -0,0 +1,199 @@
+//! Resolving sets of byte identical media down to a single kept copy
+
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{media::Media, people::FaceRegion, Error};
+
+/// How to choose the copy of a duplicate set to keep
+#[derive(Debug, Clone)]
+pub enum KeepPolicy {
+    /// Keep the copy with the oldest modified time
+    Oldest,
+    /// Keep the oldest copy under the given directory, falling back to the oldest copy if none
+    /// are under it
+    PreferredRoot(PathBuf),
+    /// Keep the copy with the shortest path
+    ShortestPath,
+}
+
+/// What to do with the copies that are not kept
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DuplicateAction {
+    /// Only report what would be done
+    DryRun,
+    /// Move the copies into the given directory, mirroring their original paths
+    Quarantine(PathBuf),
+    /// Delete the copies from disk
+    ///
+    /// Unlike the other actions this cannot be reverted.
+    Delete,
+    /// Replace the copies with hardlinks to the kept copy
+    Hardlink,
+    /// Replace the copies with reflinks (copy on write clones) of the kept copy
+    Reflink,
+}
+
+#[derive(Debug)]
+pub struct ResolveDuplicatesConfig {
+    pub keep: KeepPolicy,
+    pub action: DuplicateAction,
+    /// File to append a journal of the resolutions to so they can be reverted
+    ///
+    /// Nothing is written for dry runs.
+    pub journal: Option<PathBuf>,
+}
+
+/// The resolution of a single duplicate set
+///
+/// Also used as a journal entry, where `removed` holds the one copy the entry is for.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DuplicateResolution {
+    pub kept: PathBuf,
+    pub action: DuplicateAction,
+    /// Labels from the other copies that were added to the kept copy
+    pub labels_added: Vec<String>,
+    pub removed: Vec<RemovedDuplicate>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemovedDuplicate {
+    pub media: Media,
+    pub labels: Vec<String>,
+    /// Where the copy was moved to if it was quarantined
+    pub quarantined_to: Option<PathBuf>,
+    /// Names of the albums a quarantined copy was in with its index in each
+    #[serde(default)]
+    pub albums: Vec<(String, usize)>,
+    /// Id of the event a quarantined copy was in
+    pub event: Option<i64>,
+    /// Face regions of a quarantined copy
+    #[serde(default)]
+    pub face_regions: Vec<FaceRegion>,
+}
+
+/// Get the index of the media to keep in a duplicate set
+pub(crate) fn choose_kept(group: &[Media], policy: &KeepPolicy) -> usize {
+    let oldest = |candidates: &mut dyn Iterator<Item = (usize, &Media)>| {
+        candidates
+            .min_by(|(_, a), (_, b)| (a.modified, &a.filepath).cmp(&(b.modified, &b.filepath)))
+            .map(|(i, _)| i)
+    };
+    match policy {
+        KeepPolicy::Oldest => oldest(&mut group.iter().enumerate()),
+        KeepPolicy::PreferredRoot(root) => oldest(
+            &mut group
+                .iter()
+                .enumerate()
+                .filter(|(_, m)| m.filepath.starts_with(root)),
+        )
+        .or_else(|| oldest(&mut group.iter().enumerate())),
+        KeepPolicy::ShortestPath => group
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| {
+                let a = a.filepath.as_os_str();
+                let b = b.filepath.as_os_str();
+                (a.len(), a).cmp(&(b.len(), b))
+            })
+            .map(|(i, _)| i),
+    }
+    .unwrap_or_default()
+}
+
+/// Path a file is moved to when quarantined
+///
+/// The full original path is recreated under the quarantine directory, with the root of absolute
+/// paths as `root` and parent directories as `parent` so nothing escapes the quarantine. A number
+/// is added to the file name if the path is already taken so copies never collide.
+pub(crate) fn quarantine_path(quarantine: &Path, filepath: &Path) -> PathBuf {
+    let mut destination = quarantine.to_path_buf();
+    for component in filepath.components() {
+        match component {
+            Component::Prefix(prefix) => destination.push(
+                prefix
+                    .as_os_str()
+                    .to_string_lossy()
+                    .replace(|c: char| !c.is_alphanumeric(), "_"),
+            ),
+            Component::RootDir => destination.push("root"),
+            Component::CurDir => {}
+            Component::ParentDir => destination.push("parent"),
+            Component::Normal(name) => destination.push(name),
+        }
+    }
+    if !destination.exists() {
+        return destination;
+    }
+    let stem = destination.file_stem().unwrap_or_default().to_owned();
+    let extension = destination.extension().map(|e| e.to_owned());
+    (1..)
+        .map(|i| {
+            let mut name = stem.clone();
+            name.push(format!("-{i}"));
+            if let Some(extension) = &extension {
+                name.push(".");
+                name.push(extension);
+            }
+            destination.with_file_name(name)
+        })
+        .find(|candidate| !candidate.exists())
+        .expect("a free quarantine path")
+}
+
+/// Move a file, falling back to copy and remove when moving across filesystems
+pub(crate) fn move_file(from: &Path, to: &Path) -> Result<(), Error> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::rename(from, to).is_err() {
+        fs::copy(from, to)?;
+        fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+/// Replace `filepath` with a link to `kept`
+///
+/// The link is created next to `filepath` and then renamed over it so `filepath` is never
+/// missing.
+pub(crate) fn replace_with_link(
+    kept: &Path,
+    filepath: &Path,
+    action: &DuplicateAction,
+) -> Result<(), Error> {
+    let temporary = temporary_path(filepath);
+    match action {
+        DuplicateAction::Hardlink => fs::hard_link(kept, &temporary)?,
+        DuplicateAction::Reflink => reflink_copy::reflink(kept, &temporary)?,
+        DuplicateAction::DryRun | DuplicateAction::Quarantine(_) | DuplicateAction::Delete => {
+            return Ok(())
+        }
+    }
+    if let Err(e) = fs::rename(&temporary, filepath) {
+        fs::remove_file(&temporary)?;
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// Replace a link at `filepath` with an independent copy of `kept`
+pub(crate) fn replace_with_copy(kept: &Path, filepath: &Path) -> Result<(), Error> {
+    let temporary = temporary_path(filepath);
+    fs::copy(kept, &temporary)?;
+    if let Err(e) = fs::rename(&temporary, filepath) {
+        fs::remove_file(&temporary)?;
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+fn temporary_path(filepath: &Path) -> PathBuf {
+    let mut temporary = filepath.as_os_str().to_owned();
+    temporary.push(".glance-dedupe");
+    temporary.into()
+}
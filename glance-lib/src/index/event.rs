@@ -0,0 +1,141 @@
+//! Grouping media into events like a trip or a birthday by when and where they were taken
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::Serialize;
+
+use crate::store::event_sql::EventSql;
+
+use super::media::Media;
+
+/// Mean radius of the earth in kilometres
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Media taken close together in time and place
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Event {
+    pub id: i64,
+    pub name: String,
+    /// When the first media was taken
+    pub start: DateTime<Utc>,
+    /// When the last media was taken
+    pub end: DateTime<Utc>,
+    /// Most common location of the media
+    pub place: Option<String>,
+    pub count: i64,
+}
+
+impl From<EventSql> for Event {
+    fn from(value: EventSql) -> Self {
+        Self {
+            id: value.id,
+            name: value.name,
+            start: value.start,
+            end: value.end,
+            place: value.place,
+            count: value.count,
+        }
+    }
+}
+
+/// When consecutive media start a new event
+#[derive(Debug, Clone)]
+pub struct EventConfig {
+    /// Longest time between media in the same event
+    pub max_gap: TimeDelta,
+    /// Longest distance between geotagged media in the same event
+    pub max_distance_km: f64,
+}
+
+impl Default for EventConfig {
+    fn default() -> Self {
+        Self {
+            max_gap: TimeDelta::hours(6),
+            max_distance_km: 50.0,
+        }
+    }
+}
+
+/// Split media ordered by created into events
+///
+/// A new event starts when the time since the previous media is more than `max_gap`, or when the
+/// media is geotagged and further than `max_distance_km` from the previous geotagged media in the
+/// event. Media without a created time are left out.
+pub(crate) fn cluster<'a>(media: &'a [Media], config: &EventConfig) -> Vec<Vec<&'a Media>> {
+    let mut events: Vec<Vec<&Media>> = Vec::new();
+    let mut previous: Option<DateTime<Utc>> = None;
+    let mut previous_coordinates: Option<(f64, f64)> = None;
+    for media in media {
+        let Some(created) = media.created else {
+            continue;
+        };
+        let coordinates = coordinates(media);
+        let far = match (previous_coordinates, coordinates) {
+            (Some(a), Some(b)) => distance_km(a, b) > config.max_distance_km,
+            _ => false,
+        };
+        match events.last_mut() {
+            Some(event)
+                if previous.is_some_and(|previous| created - previous <= config.max_gap)
+                    && !far =>
+            {
+                event.push(media)
+            }
+            _ => {
+                events.push(vec![media]);
+                previous_coordinates = None;
+            }
+        }
+        previous = Some(created);
+        previous_coordinates = coordinates.or(previous_coordinates);
+    }
+    events
+}
+
+/// Start, end and most common location of media, `None` if none of them have a created time
+pub(crate) fn summarize<'a, I>(media: I) -> Option<(DateTime<Utc>, DateTime<Utc>, Option<String>)>
+where
+    I: IntoIterator<Item = &'a Media>,
+{
+    let mut range: Option<(DateTime<Utc>, DateTime<Utc>)> = None;
+    let mut places: HashMap<&str, usize> = HashMap::new();
+    for media in media {
+        if let Some(created) = media.created {
+            range = Some(match range {
+                Some((start, end)) => (start.min(created), end.max(created)),
+                None => (created, created),
+            });
+        }
+        if let Some(location) = &media.location {
+            *places.entry(location).or_default() += 1;
+        }
+    }
+    let place = places
+        .into_iter()
+        .max_by(|(a, a_count), (b, b_count)| a_count.cmp(b_count).then(b.cmp(a)))
+        .map(|(place, _)| place.to_string());
+    range.map(|(start, end)| (start, end, place))
+}
+
+/// Name of a detected event from when it started and where it was
+pub(crate) fn default_name(start: DateTime<Utc>, place: Option<&str>) -> String {
+    let date = start.format("%Y-%m-%d");
+    match place {
+        Some(place) => format!("{date} {place}"),
+        None => date.to_string(),
+    }
+}
+
+fn coordinates(media: &Media) -> Option<(f64, f64)> {
+    Some((media.latitude?, media.longitude?))
+}
+
+/// Great circle distance between points in decimal degrees
+fn distance_km((lat1, lon1): (f64, f64), (lat2, lon2): (f64, f64)) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (lon2 - lon1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
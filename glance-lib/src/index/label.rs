@@ -1,6 +1,9 @@
 use std::path::PathBuf;
 
+use serde::Serialize;
+
 use crate::store::label_sql::LabelSql;
+pub use crate::store::label_sql::LABEL_SEPARATOR;
 
 #[derive(Debug)]
 pub struct Label {
@@ -25,3 +28,65 @@ impl From<Label> for LabelSql {
         }
     }
 }
+
+/// Trim each level of a hierarchical label and drop empty levels
+///
+/// Returns `None` when nothing is left.
+pub fn normalize(label: &str) -> Option<String> {
+    let levels: Vec<_> = label
+        .split(LABEL_SEPARATOR)
+        .map(str::trim)
+        .filter(|level| !level.is_empty())
+        .collect();
+    (!levels.is_empty()).then(|| levels.join(&LABEL_SEPARATOR.to_string()))
+}
+
+/// Whether `label` is `ancestor` or one of its descendants
+pub fn is_within(label: &str, ancestor: &str) -> bool {
+    label
+        .strip_prefix(ancestor)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(LABEL_SEPARATOR))
+}
+
+/// Level of a hierarchical label with the levels below it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LabelNode {
+    /// Last level of the label
+    pub name: String,
+    /// Full label including its ancestors
+    pub label: String,
+    pub children: Vec<LabelNode>,
+}
+
+/// Arrange labels into trees, adding ancestors that are not labels themselves
+///
+/// Nodes are sorted by name at every level.
+pub fn label_tree<S: AsRef<str>>(labels: &[S]) -> Vec<LabelNode> {
+    let mut roots: Vec<LabelNode> = Vec::new();
+    for label in labels {
+        let mut nodes = &mut roots;
+        let mut path = String::new();
+        for level in label.as_ref().split(LABEL_SEPARATOR) {
+            if !path.is_empty() {
+                path.push(LABEL_SEPARATOR);
+            }
+            path.push_str(level);
+            let index = match nodes.binary_search_by(|node| node.name.as_str().cmp(level)) {
+                Ok(index) => index,
+                Err(index) => {
+                    nodes.insert(
+                        index,
+                        LabelNode {
+                            name: level.to_string(),
+                            label: path.clone(),
+                            children: Vec::new(),
+                        },
+                    );
+                    index
+                }
+            };
+            nodes = &mut nodes[index].children;
+        }
+    }
+    roots
+}
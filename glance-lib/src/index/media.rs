@@ -1,26 +1,60 @@
-use std::{
-    collections::{HashMap, HashSet},
-    ffi::OsStr,
-    path::PathBuf,
-};
+use std::{ffi::OsStr, fmt, path::PathBuf, str::FromStr};
 
 use blake3::Hash;
 use chrono::{DateTime, Utc};
 use derive_more::{From, Into};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-pub use crate::store::media_sql::MediaFilter;
 use crate::store::media_sql::MediaSql;
+pub use crate::store::media_sql::{
+    Bounds, CreatedRange, LabelQuery, MediaFilter, MonthDay, MonthDayRange, Near, SortKey,
+};
 
-use super::Stats;
-
-#[derive(Debug, Into, From, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, Into, From, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Device(pub String);
 
-#[derive(Debug, Into, From, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, Into, From, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Size(pub u64);
 
-#[derive(Debug, Serialize)]
+/// Highest star rating media can be given
+pub const MAX_RATING: u8 = 5;
+
+/// Culling decision for media
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Flag {
+    Pick,
+    Reject,
+}
+
+impl Flag {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pick => "pick",
+            Self::Reject => "reject",
+        }
+    }
+}
+
+impl fmt::Display for Flag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Flag {
+    type Err = super::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pick" => Ok(Self::Pick),
+            "reject" => Ok(Self::Reject),
+            _ => Err(super::Error::InvalidFlag(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Media {
     pub filepath: PathBuf,
     pub size: Size,
@@ -28,12 +62,44 @@ pub struct Media {
     pub created: Option<DateTime<Utc>>,
     pub modified: DateTime<Utc>,
     pub location: Option<String>,
+    /// ISO 3166 code of the country the nearest city is in
+    pub country: Option<String>,
     pub device: Option<Device>,
     // pub iso: (),
     pub hash: Option<Hash>,
+    pub perceptual_hash: Option<u64>,
+    /// Star rating from 0 (unrated) to `MAX_RATING`
+    #[serde(default)]
+    pub rating: u8,
+    pub flag: Option<Flag>,
+    /// Decimal degrees from the GPS exif data
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub title: Option<String>,
+    /// Short description shown with the media
+    pub caption: Option<String>,
+    /// Free-form text that stays in the index
+    pub notes: Option<String>,
+}
+
+/// Text written about media
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MediaText {
+    pub title: Option<String>,
+    pub caption: Option<String>,
+    pub notes: Option<String>,
 }
 
 impl Media {
+    pub fn text(&self) -> MediaText {
+        MediaText {
+            title: self.title.clone(),
+            caption: self.caption.clone(),
+            notes: self.notes.clone(),
+        }
+    }
+
     pub fn file_name(&self) -> Result<&OsStr, super::Error> {
         self.filepath
             .file_name()
@@ -50,8 +116,17 @@ impl From<MediaSql> for Media {
             created: value.created,
             modified: value.modified,
             location: value.location,
+            country: value.country,
             device: value.device.map(|d| d.into()),
             hash: value.hash.map(|h| h.into()),
+            perceptual_hash: value.perceptual_hash.map(|h| h.into()),
+            rating: value.rating,
+            flag: value.flag,
+            latitude: value.latitude,
+            longitude: value.longitude,
+            title: value.title,
+            caption: value.caption,
+            notes: value.notes,
         }
     }
 }
@@ -65,39 +140,17 @@ impl From<Media> for MediaSql {
             created: value.created,
             modified: value.modified,
             location: value.location,
+            country: value.country,
             device: value.device.map(|d| d.into()),
             hash: value.hash.map(|h| h.into()),
+            perceptual_hash: value.perceptual_hash.map(|h| h.into()),
+            rating: value.rating,
+            flag: value.flag,
+            latitude: value.latitude,
+            longitude: value.longitude,
+            title: value.title,
+            caption: value.caption,
+            notes: value.notes,
         }
     }
 }
-
-pub fn stats_from_media(media_vec: &Vec<Media>) -> Result<Stats, super::Error> {
-    let mut count_by_format = HashMap::new();
-    let mut count_by_device = HashMap::new();
-    // TODO: populate count by year
-    let count_by_year = HashMap::new();
-    let mut hashes_seen = HashSet::new();
-    let mut duplicates = 0;
-    for media in media_vec {
-        *count_by_format
-            .entry(Some(media.format.to_string()))
-            .or_default() += 1;
-        if let Some(device) = &media.device {
-            *count_by_device.entry(Some(device.0.clone())).or_default() += 1;
-        }
-        if let Some(hash) = media.hash {
-            if hashes_seen.contains(&hash) {
-                duplicates += 1;
-            } else {
-                hashes_seen.insert(hash);
-            }
-        }
-    }
-    Ok(Stats {
-        count: media_vec.len() as i64,
-        count_by_format,
-        count_by_device,
-        count_by_year,
-        duplicates,
-    })
-}
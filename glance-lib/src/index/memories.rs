@@ -0,0 +1,95 @@
+//! Media taken on the same day of the year in earlier years
+
+use chrono::{Datelike, NaiveDate, NaiveTime, TimeDelta};
+use serde::Serialize;
+
+use super::media::{Media, MediaFilter, MonthDay, MonthDayRange};
+
+/// Media taken around the same day in one earlier year
+#[derive(Debug, Clone, Serialize)]
+pub struct MemoryYear {
+    /// Year of the day `years_ago` years before the date, media from the edges of a window around
+    /// the new year may be from the year before or after
+    pub year: i32,
+    pub years_ago: i32,
+    /// Ordered by created
+    pub media: Vec<Media>,
+}
+
+/// Which days count as the same day of the year
+#[derive(Debug, Clone)]
+pub struct MemoriesConfig {
+    /// Days before and after the day of the year that are included
+    pub window_days: u32,
+}
+
+impl Default for MemoriesConfig {
+    fn default() -> Self {
+        Self { window_days: 3 }
+    }
+}
+
+/// Filter for the media created within the window around `date` in earlier years
+///
+/// Media created since the window around `date` started are left out so recent media are not
+/// memories, even when the window wraps around the new year.
+pub(crate) fn filter(date: NaiveDate, config: &MemoriesConfig) -> MediaFilter {
+    let window = TimeDelta::days(config.window_days.into());
+    let first = date - window;
+    // A window of half a year or more covers every day
+    let month_day = if config.window_days >= 183 {
+        MonthDayRange {
+            start: MonthDay { month: 1, day: 1 },
+            end: MonthDay { month: 12, day: 31 },
+        }
+    } else {
+        MonthDayRange {
+            start: first.into(),
+            end: (date + window).into(),
+        }
+    };
+    MediaFilter {
+        month_day: Some(month_day),
+        created_end: Some(first.and_time(NaiveTime::MIN).and_utc() - TimeDelta::nanoseconds(1)),
+        ..Default::default()
+    }
+}
+
+/// Group media ordered by created into years, most recent first
+pub(crate) fn group_by_year(date: NaiveDate, media: Vec<Media>) -> Vec<MemoryYear> {
+    let mut years: Vec<MemoryYear> = Vec::new();
+    for media in media {
+        let Some(created) = media.created else {
+            continue;
+        };
+        let years_ago = years_ago(date, created.date_naive());
+        match years.iter_mut().find(|year| year.years_ago == years_ago) {
+            Some(year) => year.media.push(media),
+            None => years.push(MemoryYear {
+                year: date.year() - years_ago,
+                years_ago,
+                media: vec![media],
+            }),
+        }
+    }
+    years.sort_by_key(|year| year.years_ago);
+    years
+}
+
+/// Whole years from the anniversary of `created` closest to `date`
+///
+/// Media from the end of December are a year further back than their year suggests when `date`
+/// is at the start of January, and the other way around.
+fn years_ago(date: NaiveDate, created: NaiveDate) -> i32 {
+    let years = date.year() - created.year();
+    let anniversary = created
+        .with_year(date.year())
+        // February 29th in a year that is not a leap year
+        .or_else(|| NaiveDate::from_ymd_opt(date.year(), 2, 28))
+        .expect("February 28th to exist");
+    match (date - anniversary).num_days() {
+        days if days > 182 => years + 1,
+        days if days < -182 => years - 1,
+        _ => years,
+    }
+}
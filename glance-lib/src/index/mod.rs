@@ -4,9 +4,15 @@ use std::os::unix::fs::symlink;
 use std::os::unix::fs::symlink;
 #[cfg(target_os = "windows")]
 use std::os::windows::fs::symlink_file as symlink;
-use std::{collections::HashMap, fs, path::Path};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    thread::JoinHandle,
+};
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 use dateparser::parse_with_timezone;
 use displaydoc::Display;
 use exif::{Exif, In, Rational, Tag, Value};
@@ -22,35 +28,114 @@ use sloggers::{null::NullLoggerBuilder, Build};
 use thiserror::Error;
 use walkdir::{DirEntry, WalkDir};
 
-use crate::index::media::{Device, Media};
+use crate::index::dedupe::{
+    choose_kept, move_file, quarantine_path, replace_with_copy, replace_with_link, DuplicateAction,
+    DuplicateResolution, RemovedDuplicate, ResolveDuplicatesConfig,
+};
+use crate::index::media::{CreatedRange, Device, Flag, Media, MediaText, MAX_RATING};
+use crate::index::thumbnail::ThumbnailCache;
+use crate::store::album_sql::AlbumSql;
+use crate::store::converters::PathBufSql;
+use crate::store::event_sql::EventSql;
 use crate::store::label_sql::{LabelFilter, LabelSearch, LabelSql};
 use crate::store::media_sql::{
     MediaDuplicates, MediaFilter, MediaNewFromImport, MediaSearch, MediaSql,
+    MediaWithPerceptualHash, XmpStateSql,
 };
-
-use self::label::Label;
-
-mod label;
+use crate::store::people_sql::{FaceRegionSql, PersonSql};
+use crate::store::place_sql::PlaceSql;
+use crate::store::saved_search_sql::SavedSearchSql;
+use crate::store::search_sql::SearchSql;
+
+use self::album::Album;
+use self::event::{Event, EventConfig};
+use self::label::{Label, LabelNode};
+use self::memories::{MemoriesConfig, MemoryYear};
+use self::people::{FaceRegion, Person, Rectangle, RegionSource};
+use self::place::{Place, Trip, TripConfig};
+use self::rules::{ApplyRulesReport, RuleSet};
+use self::saved_search::SavedSearch;
+use self::xmp::XmpMetadata;
+
+pub mod album;
+pub mod dedupe;
+pub mod event;
+pub mod label;
 pub mod media;
+pub mod memories;
+pub mod people;
+mod perceptual_hash;
+pub mod place;
+pub mod query;
+pub mod rules;
+pub mod saved_search;
 #[cfg(test)]
 mod tests;
+pub mod thumbnail;
+mod xmp;
 
 #[derive(Debug, Error, Display)]
 pub enum Error {
+    /// an album named '{0}' already exists
+    AlbumExists(String),
+    /// no album named '{0}'
+    AlbumNotFound(String),
+    /// cannot split event {id} at its first media {path:?}
+    EventSplitAtStart { id: i64, path: PathBuf },
+    /// no event with id {0}
+    EventNotFound(i64),
     /// exif: {0}
     Exif(#[from] exif::Error),
     /// exiftool: {0}
     Exiftool(#[from] exiftool::Error),
     /// hashing enabled but hash missing from media row
     HashMissing,
+    /// media is not part of a duplicate set
+    NotADuplicate,
+    /// image: {0}
+    Image(#[from] image::ImageError),
+    /// invalid flag '{0}', expected 'pick' or 'reject'
+    InvalidFlag(String),
+    /// invalid event name {0:?}, expected a name that is not empty
+    InvalidEventName(String),
+    /// invalid label '{0}', every level of a label must have a name
+    InvalidLabel(String),
+    /// cannot rename label '{from}' to itself or a descendant '{to}'
+    InvalidLabelRename { from: String, to: String },
+    /// invalid place {0:?}, expected a name, a latitude and longitude in degrees and a positive radius
+    InvalidPlace(Place),
+    /// invalid face region {0:?}, expected a rectangle inside the image
+    InvalidRegion(Rectangle),
+    /// invalid rating {0}, expected 0 to 5
+    InvalidRating(u8),
+    /// could not find where to add the rating in xmp sidecar {0:?}
+    InvalidXmp(PathBuf),
+    /// media not in index {0:?}
+    NotIndexed(PathBuf),
+    /// media not in album {0:?}
+    NotInAlbum(PathBuf),
+    /// media not in event {0:?}
+    NotInEvent(PathBuf),
+    /// no place named '{0}'
+    PlaceNotFound(String),
+    /// no face region with id {0}
+    RegionNotFound(i64),
+    /// no saved search named '{0}'
+    SavedSearchNotFound(String),
     /// file name missing
     FileNameMissing,
     /// io: {0}
     Io(#[from] std::io::Error),
+    /// query: {0}
+    Query(#[from] query::ParseError),
     /// rusqlite: {0}
     Rusqlite(#[from] rusqlite::Error),
+    /// serde_json: {0}
+    SerdeJson(#[from] serde_json::Error),
     /// sloggers: {0}
     Sloggers(#[from] sloggers::Error),
+    /// toml: {0}
+    Toml(#[from] toml::de::Error),
     /// walkdir: {0}
     Walkdir(#[from] walkdir::Error),
 }
@@ -58,6 +143,8 @@ pub enum Error {
 pub struct Index {
     connection: Connection,
     logger: Logger,
+    thumbnail_cache: Option<ThumbnailCache>,
+    write_xmp: bool,
 }
 
 #[serde_as]
@@ -70,7 +157,45 @@ pub struct Stats {
     pub count_by_device: HashMap<Option<String>, i64>,
     #[serde_as(as = "FromInto<HashMapWithUnknown<String, i64>>")]
     pub count_by_year: HashMap<Option<String>, i64>,
+    /// Keyed by month from 1 for January
+    pub count_by_month: HashMap<u8, i64>,
+    /// Keyed by day of the week from 0 for Sunday
+    pub count_by_weekday: HashMap<u8, i64>,
+    /// Keyed by hour of the day in UTC
+    pub count_by_hour: HashMap<u8, i64>,
+    #[serde_as(as = "FromInto<HashMapWithUnknown<String, i64>>")]
+    pub count_by_location: HashMap<Option<String>, i64>,
+    /// Keyed by the last part of the location, the state or province the nearest city is in
+    #[serde_as(as = "FromInto<HashMapWithUnknown<String, i64>>")]
+    pub count_by_region: HashMap<Option<String>, i64>,
+    /// Keyed by the ISO 3166 code of the country the nearest city is in
+    #[serde_as(as = "FromInto<HashMapWithUnknown<String, i64>>")]
+    pub count_by_country: HashMap<Option<String>, i64>,
+    /// Media with each label, not counting the labels below it
+    pub count_by_label: HashMap<String, i64>,
+    pub count_by_rating: HashMap<u8, i64>,
+    /// Media that share their hash with other media
     pub duplicates: usize,
+    /// Total size of the media in bytes
+    pub bytes: u64,
+    #[serde_as(as = "FromInto<HashMapWithUnknown<String, i64>>")]
+    pub bytes_by_format: HashMap<Option<String>, i64>,
+    /// Bytes that would be freed by keeping only the largest copy of each duplicate
+    pub duplicate_bytes: u64,
+    pub missing_created: i64,
+    pub missing_location: i64,
+    pub missing_hash: i64,
+}
+
+/// Media matching a filter, see [`Index::media_cursor`]
+pub struct MediaCursor<'conn> {
+    search: MediaSearch<'conn>,
+}
+
+impl MediaCursor<'_> {
+    pub fn iter(&mut self) -> Result<impl Iterator<Item = Result<Media, Error>> + '_, Error> {
+        Ok(self.search.iter()?.map(from_media_sql_result))
+    }
 }
 
 #[derive(Debug)]
@@ -85,6 +210,14 @@ pub struct AddDirectoryConfig {
     pub calculate_nearest_city: bool,
     /// Try to use exiftool cli program
     pub use_exiftool: bool,
+    /// Compute a perceptual hash of images for near duplicate detection
+    pub perceptual_hash: bool,
+    /// Read the rating from xmp sidecars and embedded xmp
+    pub read_xmp: bool,
+    /// Rules whose labels are added to new media
+    pub rules: RuleSet,
+    /// Label new media with the places they were taken in, see [`Place::label`]
+    pub label_places: bool,
 }
 
 impl Default for AddDirectoryConfig {
@@ -95,6 +228,10 @@ impl Default for AddDirectoryConfig {
             metadata_fallback_for_created: true,
             calculate_nearest_city: false,
             use_exiftool: false,
+            perceptual_hash: false,
+            read_xmp: true,
+            rules: RuleSet::default(),
+            label_places: false,
         }
     }
 }
@@ -131,9 +268,17 @@ impl Index {
     fn new_impl(mut connection: Connection) -> Result<Self, Error> {
         MediaSql::create_table(&mut connection)?;
         LabelSql::create_table(&mut connection)?;
+        SearchSql::create_table(&mut connection)?;
+        SavedSearchSql::create_table(&mut connection)?;
+        AlbumSql::create_table(&mut connection)?;
+        EventSql::create_table(&mut connection)?;
+        PlaceSql::create_table(&mut connection)?;
+        FaceRegionSql::create_table(&mut connection)?;
         Ok(Self {
             connection,
             logger: NullLoggerBuilder.build()?,
+            thumbnail_cache: None,
+            write_xmp: false,
         })
     }
 
@@ -142,6 +287,25 @@ impl Index {
         self
     }
 
+    /// Use a thumbnail cache
+    ///
+    /// The cache directory is skipped when indexing and thumbnails are removed when their media is
+    /// deindexed.
+    pub fn with_thumbnail_cache(mut self, thumbnail_cache: ThumbnailCache) -> Self {
+        self.thumbnail_cache = Some(thumbnail_cache);
+        self
+    }
+
+    pub fn thumbnail_cache(&self) -> Option<&ThumbnailCache> {
+        self.thumbnail_cache.as_ref()
+    }
+
+    /// Write ratings and flags to xmp sidecars when they are set
+    pub fn with_write_xmp(mut self, write_xmp: bool) -> Self {
+        self.write_xmp = write_xmp;
+        self
+    }
+
     /// Add the contents of a directory to the index
     pub fn index<P: AsRef<Path>>(
         &mut self,
@@ -161,8 +325,21 @@ impl Index {
         let mut failed_to_read_exif_count = 0u64;
         let mut failed_to_determine_created_from_exif_count = 0u64;
         let mut failed_to_determine_created_count = 0u64;
+        let mut failed_to_compute_perceptual_hash_count = 0u64;
+        let mut failed_to_read_xmp_count = 0u64;
+        let mut added_perceptual_hash = 0u64;
+        let mut updated_from_xmp = 0u64;
+        let mut added_face_regions = 0u64;
+        let mut labeled_by_rules = 0u64;
         let mut failed = 0u64;
         let transaction = self.connection.transaction()?;
+        let places: Vec<Place> = match config.label_places {
+            true => PlaceSql::get_all(&transaction)?
+                .into_iter()
+                .map(Place::from)
+                .collect(),
+            false => Vec::new(),
+        };
         for entry in WalkDir::new(path) {
             let entry = entry?;
             if entry
@@ -173,6 +350,11 @@ impl Index {
             {
                 continue;
             }
+            if let Some(thumbnail_cache) = &self.thumbnail_cache {
+                if entry.path().starts_with(thumbnail_cache.directory()) {
+                    continue;
+                }
+            }
 
             if entry.file_type().is_dir() {
                 dirs += 1;
@@ -191,14 +373,22 @@ impl Index {
                 let filepath = entry.path().to_path_buf().into();
                 // Check if the file already exists in the index?
                 let existing = MediaSql::get_by_filepath(&transaction, &filepath)?.map(Media::from);
+                let xmp_state = match existing.is_some() && config.read_xmp {
+                    true => MediaSql::get_xmp_state(&transaction, &filepath)?,
+                    false => XmpStateSql::default(),
+                };
 
-                match file_to_media_row(&entry, existing.as_ref(), config, &logger) {
+                match file_to_media_row(&entry, existing.as_ref(), &xmp_state, config, &logger) {
                     Ok(FileToMediaRowResult::New {
                         media,
+                        regions,
+                        sidecar_modified,
                         used_exiftool_fallback,
                         failed_to_read_exif,
                         failed_to_determine_created_from_exif,
                         failed_to_determine_created,
+                        failed_to_compute_perceptual_hash,
+                        failed_to_read_xmp,
                     }) => {
                         trace!(logger, "adding file");
                         if used_exiftool_fallback {
@@ -213,18 +403,108 @@ impl Index {
                         if failed_to_determine_created {
                             failed_to_determine_created_count += 1;
                         }
-                        let inserted = MediaSql::from(media).insert(&transaction)?;
+                        if failed_to_compute_perceptual_hash {
+                            failed_to_compute_perceptual_hash_count += 1;
+                        }
+                        if failed_to_read_xmp {
+                            failed_to_read_xmp_count += 1;
+                        }
+                        let rule_matches = config.rules.matches(&media);
+                        let place_labels: Vec<String> = places
+                            .iter()
+                            .filter(|place| place.contains_media(&media))
+                            .map(Place::label)
+                            .collect();
+                        let inserted = MediaSql::from(*media).insert(&transaction)?;
                         if !inserted {
                             error!(logger, "failed to insert media row");
                             failed += 1;
                             continue;
                         }
+                        MediaSql::set_xmp_state(
+                            &transaction,
+                            &filepath,
+                            &XmpStateSql {
+                                modified: sidecar_modified,
+                                regions_read: config.read_xmp,
+                            },
+                        )?;
+                        for (person, rectangle) in regions {
+                            face_region_sql(&filepath.0, person, rectangle, RegionSource::Metadata)
+                                .insert(&transaction)?;
+                        }
+                        for rule_match in rule_matches {
+                            trace!(logger, "labeled by rule";
+                                "rule" => &rule_match.rule,
+                                "label" => &rule_match.label,
+                            );
+                            LabelSql {
+                                filepath: rule_match.filepath.into(),
+                                label: rule_match.label,
+                            }
+                            .insert_or_ignore(&transaction)?;
+                            labeled_by_rules += 1;
+                        }
+                        for label in place_labels {
+                            LabelSql {
+                                filepath: filepath.0.clone().into(),
+                                label,
+                            }
+                            .insert_or_ignore(&transaction)?;
+                        }
                         added += 1;
                     }
                     Ok(FileToMediaRowResult::Unmodified) => {
                         trace!(logger, "unmodified");
                         unmodifed += 1;
                     }
+                    Ok(FileToMediaRowResult::MissingPerceptualHash(perceptual_hash)) => {
+                        trace!(logger, "adding perceptual hash");
+                        MediaSql::set_perceptual_hash(
+                            &transaction,
+                            &filepath,
+                            &perceptual_hash.into(),
+                        )?;
+                        unmodifed += 1;
+                        added_perceptual_hash += 1;
+                    }
+                    Ok(FileToMediaRowResult::MissingFaceRegions(regions)) => {
+                        trace!(logger, "adding face regions"; "regions" => regions.len());
+                        FaceRegionSql::delete_from_metadata(&transaction, &filepath)?;
+                        for (person, rectangle) in regions {
+                            face_region_sql(&filepath.0, person, rectangle, RegionSource::Metadata)
+                                .insert(&transaction)?;
+                            added_face_regions += 1;
+                        }
+                        MediaSql::set_xmp_state(
+                            &transaction,
+                            &filepath,
+                            &XmpStateSql {
+                                regions_read: true,
+                                ..xmp_state
+                            },
+                        )?;
+                        unmodifed += 1;
+                    }
+                    Ok(FileToMediaRowResult::XmpModified {
+                        metadata,
+                        sidecar_modified,
+                    }) => {
+                        trace!(logger, "updating from xmp");
+                        if let Some(existing) = &existing {
+                            update_from_xmp(&transaction, &filepath, existing, metadata)?;
+                        }
+                        MediaSql::set_xmp_state(
+                            &transaction,
+                            &filepath,
+                            &XmpStateSql {
+                                modified: sidecar_modified,
+                                regions_read: true,
+                            },
+                        )?;
+                        unmodifed += 1;
+                        updated_from_xmp += 1;
+                    }
                     Ok(FileToMediaRowResult::SkippedFileType) => {
                         trace!(logger, "filtered file");
                         filtered_due_to_filetype += 1;
@@ -253,6 +533,12 @@ impl Index {
             "failed_to_read_exif" => failed_to_read_exif_count,
             "failed_to_determine_created_from_exif" => failed_to_determine_created_from_exif_count,
             "failed_to_determine_created" => failed_to_determine_created_count,
+            "failed_to_compute_perceptual_hash" => failed_to_compute_perceptual_hash_count,
+            "failed_to_read_xmp" => failed_to_read_xmp_count,
+            "added_perceptual_hash" => added_perceptual_hash,
+            "updated_from_xmp" => updated_from_xmp,
+            "added_face_regions" => added_face_regions,
+            "labeled_by_rules" => labeled_by_rules,
             "failed" => failed,
         );
         Ok(())
@@ -283,7 +569,13 @@ impl Index {
             let media = media?;
             if !media.filepath.exists() {
                 trace!(self.logger, "removing from index"; "path" => media.filepath.display());
-                MediaSql::from(media).delete(&transaction)?;
+                MediaSql::delete_path(&media.filepath, &transaction)?;
+                remove_unused_thumbnail(
+                    &transaction,
+                    self.thumbnail_cache.as_ref(),
+                    &media,
+                    logger,
+                )?;
                 removed += 1;
             }
         }
@@ -302,12 +594,52 @@ impl Index {
         let transaction = self.connection.transaction()?;
         for path in paths {
             // TODO: support directories
+            let existing = MediaSql::get_by_filepath(&transaction, &path.as_ref().into())?;
             MediaSql::delete_path(path.as_ref(), &transaction)?;
+            if let Some(media) = existing.map(Media::from) {
+                remove_unused_thumbnail(
+                    &transaction,
+                    self.thumbnail_cache.as_ref(),
+                    &media,
+                    &self.logger,
+                )?;
+            }
         }
         transaction.commit()?;
         Ok(())
     }
 
+    /// Generate any missing thumbnails for all media in a background thread
+    ///
+    /// Returns `None` if the index has no thumbnail cache.
+    pub fn prewarm_thumbnails_in_background(
+        &self,
+        sizes: Vec<u32>,
+    ) -> Result<Option<JoinHandle<()>>, Error> {
+        Ok(match &self.thumbnail_cache {
+            Some(thumbnail_cache) => Some(thumbnail_cache.prewarm_in_background(
+                self.get_media()?,
+                sizes,
+                self.logger.clone(),
+            )),
+            None => None,
+        })
+    }
+
+    /// Remove thumbnails that do not belong to any indexed media
+    ///
+    /// Returns the number of thumbnails removed.
+    pub fn clean_thumbnails(&self) -> Result<u64, Error> {
+        match &self.thumbnail_cache {
+            Some(thumbnail_cache) => {
+                let removed = thumbnail_cache.retain(&self.get_media()?)?;
+                info!(self.logger, "cleaned thumbnails"; "removed" => removed);
+                Ok(removed)
+            }
+            None => Ok(0),
+        }
+    }
+
     pub fn get_media(&self) -> Result<Vec<Media>, Error> {
         MediaSearch::new_with_filter_defaults(&self.connection)?
             .iter()?
@@ -315,6 +647,19 @@ impl Index {
             .collect()
     }
 
+    /// Get a cursor over the media matching a filter which reads them from the index as it is
+    /// iterated rather than all at once
+    pub fn media_cursor(&self, media_filter: MediaFilter) -> Result<MediaCursor<'_>, Error> {
+        Ok(MediaCursor {
+            search: MediaSearch::new(&self.connection, media_filter)?,
+        })
+    }
+
+    /// Count the media matching a filter, ignoring its `limit` and `offset`
+    pub fn count_media_with_filter(&self, media_filter: &MediaFilter) -> Result<i64, Error> {
+        Ok(MediaSearch::count(&self.connection, media_filter)?)
+    }
+
     pub fn get_media_with_filter(&self, media_filter: MediaFilter) -> Result<Vec<Media>, Error> {
         MediaSearch::new(&self.connection, media_filter)?
             .iter()?
@@ -322,16 +667,257 @@ impl Index {
             .collect()
     }
 
+    /// Get media with words starting with each word of `text` in their path, location, device,
+    /// labels, title, caption or notes, best matches first
+    ///
+    /// Only media that also match `filter` are included.
+    pub fn search_text(&self, text: &str, filter: MediaFilter) -> Result<Vec<Media>, Error> {
+        self.get_media_with_filter(MediaFilter {
+            text: text.split_whitespace().map(str::to_string).collect(),
+            ..filter
+        })
+    }
+
+    /// Get the media matching a query, see [`query`] for the syntax
+    pub fn search(&self, query: &str) -> Result<Vec<Media>, Error> {
+        self.get_media_with_filter(query.parse()?)
+    }
+
+    pub fn get_media_by_path<P: AsRef<Path>>(&self, path: P) -> Result<Option<Media>, Error> {
+        Ok(MediaSql::get_by_filepath(&self.connection, &path.as_ref().into())?.map(Media::from))
+    }
+
+    /// Set the star rating of media from 0 (unrated) to 5
+    pub fn set_rating<P: AsRef<Path>>(&self, path: P, rating: u8) -> Result<(), Error> {
+        if rating > MAX_RATING {
+            return Err(Error::InvalidRating(rating));
+        }
+        let path = path.as_ref();
+        let media = self
+            .get_media_by_path(path)?
+            .ok_or_else(|| Error::NotIndexed(path.to_path_buf()))?;
+        // Write the sidecar first so a failure leaves the index unchanged
+        self.write_xmp_rating(path, rating, media.flag)?;
+        MediaSql::set_rating(&self.connection, &path.into(), rating)?;
+        self.record_xmp_written(path)
+    }
+
+    /// Mark media as picked or rejected, or clear the flag with `None`
+    ///
+    /// Rejects are written to the xmp sidecar as a rating of -1 if enabled. Picks are only stored
+    /// in the index.
+    pub fn set_flag<P: AsRef<Path>>(&self, path: P, flag: Option<Flag>) -> Result<(), Error> {
+        let path = path.as_ref();
+        let media = self
+            .get_media_by_path(path)?
+            .ok_or_else(|| Error::NotIndexed(path.to_path_buf()))?;
+        self.write_xmp_rating(path, media.rating, flag)?;
+        MediaSql::set_flag(&self.connection, &path.into(), flag)?;
+        self.record_xmp_written(path)
+    }
+
+    /// Get the title, caption and notes of media
+    pub fn get_media_text<P: AsRef<Path>>(&self, path: P) -> Result<MediaText, Error> {
+        let path = path.as_ref();
+        self.get_media_by_path(path)?
+            .map(|media| media.text())
+            .ok_or_else(|| Error::NotIndexed(path.to_path_buf()))
+    }
+
+    /// Set the title, caption and notes of media
+    ///
+    /// Text is trimmed and empty text is cleared. The title and caption are also written to the
+    /// xmp sidecar if enabled.
+    pub fn set_media_text<P: AsRef<Path>>(&self, path: P, text: &MediaText) -> Result<(), Error> {
+        let path = path.as_ref();
+        if self.get_media_by_path(path)?.is_none() {
+            return Err(Error::NotIndexed(path.to_path_buf()));
+        }
+        let (title, caption) = (trimmed(&text.title), trimmed(&text.caption));
+        // Write the sidecar first so a failure leaves the index unchanged
+        if self.write_xmp {
+            let sidecar = xmp::write_text(path, title, caption)?;
+            trace!(self.logger, "wrote xmp text"; "sidecar" => sidecar.display());
+        }
+        MediaSql::set_text(
+            &self.connection,
+            &path.into(),
+            title,
+            caption,
+            trimmed(&text.notes),
+        )?;
+        self.record_xmp_written(path)
+    }
+
+    /// Get the people named in face regions with the number of media each is in
+    pub fn get_people(&self) -> Result<Vec<Person>, Error> {
+        Ok(PersonSql::get_all(&self.connection)?
+            .into_iter()
+            .map(Person::from)
+            .collect())
+    }
+
+    /// Get the face regions of media ordered from the left
+    pub fn get_face_regions<P: AsRef<Path>>(&self, path: P) -> Result<Vec<FaceRegion>, Error> {
+        Ok(
+            FaceRegionSql::get_by_filepath(&self.connection, &path.as_ref().into())?
+                .into_iter()
+                .map(FaceRegion::from)
+                .collect(),
+        )
+    }
+
+    /// Add a face region to media by hand and return its id
+    pub fn add_face_region<P: AsRef<Path>>(
+        &self,
+        path: P,
+        person: Option<&str>,
+        rectangle: Rectangle,
+    ) -> Result<i64, Error> {
+        let path = path.as_ref();
+        if self.get_media_by_path(path)?.is_none() {
+            return Err(Error::NotIndexed(path.to_path_buf()));
+        }
+        let rectangle = rectangle.clamped().ok_or(Error::InvalidRegion(rectangle))?;
+        let person = person.map(str::trim).filter(|person| !person.is_empty());
+        Ok(face_region_sql(
+            path,
+            person.map(str::to_string),
+            rectangle,
+            RegionSource::Manual,
+        )
+        .insert(&self.connection)?)
+    }
+
+    /// Change the person and rectangle of a face region, clearing the person with `None`
+    pub fn set_face_region(
+        &self,
+        id: i64,
+        person: Option<&str>,
+        rectangle: Rectangle,
+    ) -> Result<(), Error> {
+        let existing =
+            FaceRegionSql::get_by_id(&self.connection, id)?.ok_or(Error::RegionNotFound(id))?;
+        let rectangle = rectangle.clamped().ok_or(Error::InvalidRegion(rectangle))?;
+        let person = person.map(str::trim).filter(|person| !person.is_empty());
+        FaceRegionSql {
+            id,
+            ..face_region_sql(
+                &existing.filepath.0,
+                person.map(str::to_string),
+                rectangle,
+                RegionSource::Manual,
+            )
+        }
+        .update(&self.connection)?;
+        Ok(())
+    }
+
+    pub fn delete_face_region(&self, id: i64) -> Result<(), Error> {
+        if FaceRegionSql::delete(&self.connection, id)? == 0 {
+            return Err(Error::RegionNotFound(id));
+        }
+        Ok(())
+    }
+
+    /// Write a rating and flag to the xmp sidecar of media if enabled
+    fn write_xmp_rating(&self, path: &Path, rating: u8, flag: Option<Flag>) -> Result<(), Error> {
+        if !self.write_xmp {
+            return Ok(());
+        }
+        let sidecar = xmp::write_rating(path, xmp::to_xmp_rating(rating, flag))?;
+        trace!(self.logger, "wrote xmp rating"; "sidecar" => sidecar.display());
+        Ok(())
+    }
+
+    /// Store the modified time of the sidecar written for media so indexing does not read it again
+    fn record_xmp_written(&self, path: &Path) -> Result<(), Error> {
+        if !self.write_xmp {
+            return Ok(());
+        }
+        let filepath = path.into();
+        let state = XmpStateSql {
+            modified: xmp::sidecar_modified(path)?,
+            ..MediaSql::get_xmp_state(&self.connection, &filepath)?
+        };
+        MediaSql::set_xmp_state(&self.connection, &filepath, &state)?;
+        Ok(())
+    }
+
     pub fn stats(&self) -> Result<Stats, Error> {
+        self.stats_with_filter(&MediaFilter::default())
+    }
+
+    /// Stats of the media matching a filter, ignoring its `limit` and `offset`
+    pub fn stats_with_filter(&self, media_filter: &MediaFilter) -> Result<Stats, Error> {
+        let conn = &self.connection;
+        let totals = MediaSearch::totals(conn, media_filter)?;
+        let duplicates = MediaSearch::duplicate_totals(conn, media_filter)?;
+        let count_by_year: HashMap<i64, i64> =
+            MediaSearch::count_by_created(conn, media_filter, "%Y")?;
         Ok(Stats {
-            count: MediaSql::count(&self.connection)?,
-            count_by_format: MediaSql::count_by_format(&self.connection)?,
-            count_by_device: MediaSql::count_by_device(&self.connection)?,
-            count_by_year: MediaSql::count_by_year(&self.connection)?,
-            duplicates: self.duplicates()?.len(),
+            count: totals.count,
+            count_by_format: MediaSearch::count_by(conn, media_filter, "media.format")?,
+            count_by_device: MediaSearch::count_by(conn, media_filter, "media.device")?,
+            count_by_year: count_by_year
+                .into_iter()
+                .map(|(year, count)| (Some(year.to_string()), count))
+                .collect(),
+            count_by_month: MediaSearch::count_by_created(conn, media_filter, "%m")?,
+            count_by_weekday: MediaSearch::count_by_created(conn, media_filter, "%w")?,
+            count_by_hour: MediaSearch::count_by_created(conn, media_filter, "%H")?,
+            count_by_location: MediaSearch::count_by(conn, media_filter, "media.location")?,
+            count_by_region: MediaSearch::count_by(
+                conn,
+                media_filter,
+                "CASE WHEN instr(media.location, ', ') > 0 \
+                    THEN substr(media.location, instr(media.location, ', ') + 2) END",
+            )?,
+            count_by_country: MediaSearch::count_by(conn, media_filter, "media.country")?,
+            count_by_label: MediaSearch::count_by_label(conn, media_filter)?,
+            count_by_rating: MediaSearch::count_by(conn, media_filter, "media.rating")?,
+            duplicates: duplicates.count as usize,
+            bytes: totals.bytes,
+            bytes_by_format: MediaSearch::bytes_by(conn, media_filter, "media.format")?,
+            duplicate_bytes: duplicates.wasted_bytes,
+            missing_created: totals.missing_created,
+            missing_location: totals.missing_location,
+            missing_hash: totals.missing_hash,
         })
     }
 
+    /// Count the media matching a filter in each month they were created, keyed by the first day
+    /// of the month in UTC
+    pub fn count_by_created_month(
+        &self,
+        media_filter: &MediaFilter,
+    ) -> Result<BTreeMap<NaiveDate, i64>, Error> {
+        let counts: HashMap<i64, i64> =
+            MediaSearch::count_by_created(&self.connection, media_filter, "%Y%m")?;
+        Ok(counts
+            .into_iter()
+            .filter_map(|(month, count)| {
+                let first = NaiveDate::from_ymd_opt(
+                    (month / 100).try_into().ok()?,
+                    (month % 100).try_into().ok()?,
+                    1,
+                )?;
+                Some((first, count))
+            })
+            .collect())
+    }
+
+    /// First and last created of the media matching a filter, `None` when none have created
+    pub fn created_range(&self, media_filter: &MediaFilter) -> Result<Option<CreatedRange>, Error> {
+        Ok(MediaSearch::created_range(&self.connection, media_filter)?)
+    }
+
+    /// Latitude and longitude of the geotagged media matching a filter, ignoring its `limit` and
+    /// `offset`
+    pub fn get_coordinates(&self, media_filter: &MediaFilter) -> Result<Vec<(f64, f64)>, Error> {
+        Ok(MediaSearch::coordinates(&self.connection, media_filter)?)
+    }
+
     pub fn duplicates(&self) -> Result<Vec<Media>, Error> {
         MediaDuplicates::new(&self.connection)?
             .iter()?
@@ -339,6 +925,343 @@ impl Index {
             .collect()
     }
 
+    /// Get the duplicate media grouped into sets with the same hash
+    pub fn duplicate_groups(&self) -> Result<Vec<Vec<Media>>, Error> {
+        let mut groups: Vec<Vec<Media>> = Vec::new();
+        for media in self.duplicates()? {
+            match groups.last_mut() {
+                Some(group) if group[0].hash == media.hash => group.push(media),
+                _ => groups.push(vec![media]),
+            }
+        }
+        Ok(groups)
+    }
+
+    /// Reduce each set of duplicate media to a single copy
+    ///
+    /// The copy to keep is chosen by `config.keep` and the labels of every copy are merged onto
+    /// it. The other copies are handled according to `config.action`. Quarantined and deleted
+    /// copies are removed from the index while linked copies stay indexed at their original paths.
+    pub fn resolve_duplicates(
+        &mut self,
+        config: &ResolveDuplicatesConfig,
+    ) -> Result<Vec<DuplicateResolution>, Error> {
+        let logger = self
+            .logger
+            .new(o!("action" => format!("{:?}", config.action)));
+        info!(logger, "resolving duplicates"; "keep" => format!("{:?}", config.keep));
+        let mut resolutions = Vec::new();
+        let mut removed = 0u64;
+        let mut failed = 0u64;
+        for group in self.duplicate_groups()? {
+            let kept = choose_kept(&group, &config.keep);
+            let (resolution, group_failed) =
+                self.resolve_duplicate_group(group, kept, config, &logger)?;
+            removed += resolution.removed.len() as u64;
+            failed += group_failed;
+            resolutions.push(resolution);
+        }
+        info!(logger, "resolved duplicates";
+            "sets" => resolutions.len(),
+            "removed" => removed,
+            "failed" => failed,
+        );
+        Ok(resolutions)
+    }
+
+    /// Resolve the duplicate set containing `kept` by keeping that copy
+    ///
+    /// `config.keep` is ignored. This allows choosing the copy to keep by hand.
+    pub fn resolve_duplicates_keeping<P: AsRef<Path>>(
+        &mut self,
+        kept: P,
+        config: &ResolveDuplicatesConfig,
+    ) -> Result<DuplicateResolution, Error> {
+        let kept = kept.as_ref();
+        let logger = self.logger.new(o!(
+            "action" => format!("{:?}", config.action),
+            "kept" => kept.display().to_string(),
+        ));
+        let (group, kept) = self
+            .duplicate_groups()?
+            .into_iter()
+            .find_map(|group| {
+                let i = group.iter().position(|m| m.filepath == kept)?;
+                Some((group, i))
+            })
+            .ok_or(Error::NotADuplicate)?;
+        let (resolution, failed) = self.resolve_duplicate_group(group, kept, config, &logger)?;
+        info!(logger, "resolved duplicates";
+            "removed" => resolution.removed.len(),
+            "failed" => failed,
+        );
+        Ok(resolution)
+    }
+
+    /// Keep `group[kept]` and apply `config.action` to the rest of the group
+    ///
+    /// Each copy is written to the journal before its file is touched and committed to the index
+    /// on its own, so a failure partway through a group can still be reverted.
+    ///
+    /// Returns the resolution and the number of copies that failed to be resolved.
+    fn resolve_duplicate_group(
+        &mut self,
+        mut group: Vec<Media>,
+        kept: usize,
+        config: &ResolveDuplicatesConfig,
+        logger: &Logger,
+    ) -> Result<(DuplicateResolution, u64), Error> {
+        let dry_run = config.action == DuplicateAction::DryRun;
+        let kept = group.swap_remove(kept);
+        let mut kept_labels = self.get_labels(&kept.filepath)?;
+        let mut resolution = DuplicateResolution {
+            kept: kept.filepath,
+            action: config.action.clone(),
+            labels_added: Vec::new(),
+            removed: Vec::new(),
+        };
+        let mut journal = match &config.journal {
+            Some(journal) if !dry_run => {
+                Some(OpenOptions::new().create(true).append(true).open(journal)?)
+            }
+            _ => None,
+        };
+        let mut failed = 0u64;
+        for media in group {
+            let logger = logger.new(o!(
+                "path" => media.filepath.display().to_string(),
+                "kept" => resolution.kept.display().to_string(),
+            ));
+            let filepath = PathBufSql::from(media.filepath.as_path());
+            let labels = self.get_labels(&media.filepath)?;
+            let labels_added = labels
+                .iter()
+                .filter(|label| !kept_labels.contains(label))
+                .cloned()
+                .collect::<Vec<_>>();
+            if matches!(
+                config.action,
+                DuplicateAction::Hardlink | DuplicateAction::Reflink
+            ) && same_file::is_same_file(&resolution.kept, &media.filepath)?
+            {
+                trace!(logger, "already linked");
+                continue;
+            }
+            let mut removed = RemovedDuplicate {
+                media,
+                labels,
+                quarantined_to: None,
+                albums: Vec::new(),
+                event: None,
+                face_regions: Vec::new(),
+            };
+            if let DuplicateAction::Quarantine(quarantine) = &config.action {
+                // The delete triggers drop these rows along with the media row
+                removed.quarantined_to = Some(quarantine_path(quarantine, &removed.media.filepath));
+                removed.albums = AlbumSql::get_by_item(&self.connection, &filepath)?;
+                removed.event = EventSql::get_by_item(&self.connection, &filepath)?;
+                removed.face_regions = FaceRegionSql::get_by_filepath(&self.connection, &filepath)?
+                    .into_iter()
+                    .map(FaceRegion::from)
+                    .collect();
+            }
+
+            if let Some(journal) = &mut journal {
+                let entry = DuplicateResolution {
+                    kept: resolution.kept.clone(),
+                    action: config.action.clone(),
+                    labels_added: labels_added.clone(),
+                    removed: vec![removed.clone()],
+                };
+                serde_json::to_writer(&mut *journal, &entry)?;
+                writeln!(journal)?;
+                journal.sync_data()?;
+            }
+
+            let result = match &config.action {
+                DuplicateAction::DryRun => Ok(()),
+                DuplicateAction::Quarantine(_) => match &removed.quarantined_to {
+                    Some(destination) => move_file(&removed.media.filepath, destination),
+                    None => Ok(()),
+                },
+                DuplicateAction::Delete => {
+                    fs::remove_file(&removed.media.filepath).map_err(Error::from)
+                }
+                DuplicateAction::Hardlink | DuplicateAction::Reflink => {
+                    replace_with_link(&resolution.kept, &removed.media.filepath, &config.action)
+                }
+            };
+            if let Err(e) = result {
+                error!(logger, "failed to resolve duplicate"; "error" => %e);
+                failed += 1;
+                continue;
+            }
+
+            if !dry_run {
+                let transaction = self.connection.transaction()?;
+                match &config.action {
+                    DuplicateAction::DryRun => {}
+                    DuplicateAction::Quarantine(_) | DuplicateAction::Delete => {
+                        LabelSql::delete_path(&filepath, &transaction)?;
+                        MediaSql::delete_path(&removed.media.filepath, &transaction)?;
+                    }
+                    DuplicateAction::Hardlink | DuplicateAction::Reflink => {
+                        let modified = fs::metadata(&removed.media.filepath)?.modified()?.into();
+                        MediaSql::set_modified(&transaction, &filepath, &modified)?;
+                    }
+                }
+                for label in &labels_added {
+                    LabelSql::from(Label {
+                        filepath: resolution.kept.clone(),
+                        label: label.clone(),
+                    })
+                    .insert_or_ignore(&transaction)?;
+                }
+                transaction.commit()?;
+            }
+            for label in labels_added {
+                if !resolution.labels_added.contains(&label) {
+                    resolution.labels_added.push(label.clone());
+                }
+                if !dry_run {
+                    kept_labels.push(label);
+                }
+            }
+            trace!(logger, "resolved duplicate");
+            resolution.removed.push(removed);
+        }
+        Ok((resolution, failed))
+    }
+
+    /// Undo the duplicate resolutions recorded in a journal written by `resolve_duplicates`
+    ///
+    /// Quarantined copies are put back in their albums, events and face regions. Albums and events
+    /// that were deleted in the meantime, including events left empty by the dedupe, are not
+    /// recreated.
+    pub fn revert_duplicate_resolutions<P: AsRef<Path>>(
+        &mut self,
+        journal: P,
+    ) -> Result<(), Error> {
+        let logger = self
+            .logger
+            .new(o!("journal" => journal.as_ref().display().to_string()));
+        info!(logger, "reverting duplicate resolutions");
+        let mut resolutions = Vec::new();
+        for line in BufReader::new(fs::File::open(journal)?).lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                resolutions.push(serde_json::from_str::<DuplicateResolution>(&line)?);
+            }
+        }
+        let mut restored = 0u64;
+        let transaction = self.connection.transaction()?;
+        for resolution in resolutions.into_iter().rev() {
+            // Entries are journaled before their files are touched, so an entry whose files were
+            // left alone is skipped along with its labels
+            let mut applied = false;
+            for removed in resolution.removed {
+                let logger = logger.new(o!("path" => removed.media.filepath.display().to_string()));
+                match (&resolution.action, &removed.quarantined_to) {
+                    (DuplicateAction::Quarantine(_), Some(quarantined_to)) => {
+                        if !quarantined_to.exists() {
+                            trace!(logger, "nothing to restore");
+                            continue;
+                        }
+                        applied = true;
+                        if removed.media.filepath.exists() {
+                            error!(logger, "file to restore already exists");
+                            continue;
+                        }
+                        move_file(quarantined_to, &removed.media.filepath)?;
+                        let filepath = removed.media.filepath.clone();
+                        MediaSql::from(removed.media).insert(&transaction)?;
+                        for label in removed.labels {
+                            LabelSql::from(Label {
+                                filepath: filepath.clone(),
+                                label,
+                            })
+                            .insert_or_ignore(&transaction)?;
+                        }
+                        // Albums and events deleted since the copy was quarantined stay deleted
+                        for (name, index) in removed.albums {
+                            let Some(album) = AlbumSql::get_by_name(&transaction, &name)? else {
+                                continue;
+                            };
+                            let mut items = AlbumSql::get_items(&transaction, album.id)?;
+                            items.insert(index.min(items.len()), filepath.as_path().into());
+                            AlbumSql::set_items(&transaction, album.id, &items)?;
+                        }
+                        if let Some(id) = removed.event {
+                            if EventSql::get_by_id(&transaction, id)?.is_some() {
+                                EventSql::add_items(
+                                    &transaction,
+                                    id,
+                                    &[filepath.as_path().into()],
+                                )?;
+                                update_event_summary(&transaction, id)?;
+                            }
+                        }
+                        for region in removed.face_regions {
+                            face_region_sql(
+                                &filepath,
+                                region.person,
+                                region.rectangle,
+                                region.source,
+                            )
+                            .insert(&transaction)?;
+                        }
+                    }
+                    (DuplicateAction::Hardlink | DuplicateAction::Reflink, _) => {
+                        if !same_file::is_same_file(&resolution.kept, &removed.media.filepath)? {
+                            trace!(logger, "nothing to restore");
+                            continue;
+                        }
+                        applied = true;
+                        replace_with_copy(&resolution.kept, &removed.media.filepath)?;
+                        let modified = fs::metadata(&removed.media.filepath)?.modified()?.into();
+                        MediaSql::set_modified(
+                            &transaction,
+                            &removed.media.filepath.as_path().into(),
+                            &modified,
+                        )?;
+                    }
+                    (DuplicateAction::Delete, _) => {
+                        applied |= !removed.media.filepath.exists();
+                        error!(logger, "deleted duplicates cannot be restored");
+                        continue;
+                    }
+                    _ => continue,
+                }
+                trace!(logger, "restored duplicate");
+                restored += 1;
+            }
+            if applied {
+                for label in resolution.labels_added {
+                    LabelSql::from(Label {
+                        filepath: resolution.kept.clone(),
+                        label,
+                    })
+                    .delete(&transaction)?;
+                }
+            }
+        }
+        transaction.commit()?;
+        info!(logger, "reverted duplicate resolutions"; "restored" => restored);
+        Ok(())
+    }
+
+    /// Group media that look alike using their perceptual hashes
+    ///
+    /// `threshold` is the maximum number of differing bits between two hashes for the media to be
+    /// considered near duplicates. Media indexed without a perceptual hash are ignored.
+    pub fn near_duplicates(&self, threshold: u32) -> Result<Vec<Vec<Media>>, Error> {
+        let media_vec = MediaWithPerceptualHash::new(&self.connection)?
+            .iter()?
+            .map(from_media_sql_result)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(perceptual_hash::cluster(media_vec, threshold))
+    }
+
     pub fn import(
         &mut self,
         import_index_path: &Path,
@@ -371,13 +1294,33 @@ impl Index {
                     continue;
                 }
 
+                let regions =
+                    FaceRegionSql::get_imported(&transaction, &media.filepath.as_path().into())?;
                 fs::copy(&media.filepath, &destination_path)?;
-                media.filepath = destination_path;
+                media.filepath = destination_path.clone();
                 let inserted = MediaSql::from(media).insert(&transaction)?;
                 if !inserted {
                     error!(logger, "file to import already exists in index");
                     continue;
                 }
+                // The copy has no sidecar so regions read from one must not be read again
+                if !regions.is_empty() {
+                    MediaSql::set_xmp_state(
+                        &transaction,
+                        &destination_path.as_path().into(),
+                        &XmpStateSql {
+                            modified: None,
+                            regions_read: true,
+                        },
+                    )?;
+                }
+                for region in regions {
+                    FaceRegionSql {
+                        filepath: destination_path.as_path().into(),
+                        ..region
+                    }
+                    .insert(&transaction)?;
+                }
             }
             imported += 1;
         }
@@ -470,7 +1413,7 @@ impl Index {
     pub fn add_label<P: AsRef<Path>>(&self, path: P, label: String) -> Result<(), Error> {
         let label = Label {
             filepath: path.as_ref().to_path_buf(),
-            label,
+            label: normalize_label(&label)?,
         };
         LabelSql::from(label).insert(&self.connection)?;
         Ok(())
@@ -479,51 +1422,620 @@ impl Index {
     pub fn delete_label<P: AsRef<Path>>(&self, path: P, label: String) -> Result<(), Error> {
         let label = Label {
             filepath: path.as_ref().to_path_buf(),
-            label,
+            label: normalize_label(&label)?,
         };
         LabelSql::from(label).delete(&self.connection)?;
         Ok(())
     }
 
+    /// Add `label` to every path in a single transaction
+    ///
+    /// Paths that already have the label are left as is. Returns the number of paths the label
+    /// was added to.
+    pub fn add_label_many<I, P>(&mut self, paths: I, label: String) -> Result<usize, Error>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let label = normalize_label(&label)?;
+        let transaction = self.connection.transaction()?;
+        let mut added = 0;
+        for path in paths {
+            let label = Label {
+                filepath: path.as_ref().to_path_buf(),
+                label: label.clone(),
+            };
+            added += LabelSql::from(label).insert_or_ignore(&transaction)?;
+        }
+        transaction.commit()?;
+        Ok(added)
+    }
+
+    /// Remove `label` from every path in a single transaction
+    ///
+    /// Returns the number of paths the label was removed from.
+    pub fn delete_label_many<I, P>(&mut self, paths: I, label: String) -> Result<usize, Error>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let label = normalize_label(&label)?;
+        let transaction = self.connection.transaction()?;
+        let mut deleted = 0;
+        for path in paths {
+            let label = Label {
+                filepath: path.as_ref().to_path_buf(),
+                label: label.clone(),
+            };
+            deleted += LabelSql::from(label).delete(&transaction)?;
+        }
+        transaction.commit()?;
+        Ok(deleted)
+    }
+
+    /// Add the labels of rules to the media already in the index
+    ///
+    /// Only labels the media do not already have are reported. With `dry_run` nothing is
+    /// changed.
+    pub fn apply_rules(
+        &mut self,
+        rules: &RuleSet,
+        dry_run: bool,
+    ) -> Result<ApplyRulesReport, Error> {
+        let mut report = ApplyRulesReport {
+            dry_run,
+            ..Default::default()
+        };
+        let transaction = self.connection.transaction()?;
+        for media in MediaSearch::new_with_filter_defaults(&transaction)?
+            .iter()?
+            .map(from_media_sql_result)
+        {
+            let media = media?;
+            report.media_checked += 1;
+            let labels = get_labels(&transaction, &media.filepath)?;
+            for rule_match in rules.matches(&media) {
+                if labels.contains(&rule_match.label) {
+                    continue;
+                }
+                if !dry_run {
+                    LabelSql {
+                        filepath: rule_match.filepath.clone().into(),
+                        label: rule_match.label.clone(),
+                    }
+                    .insert_or_ignore(&transaction)?;
+                }
+                report.added.push(rule_match);
+            }
+        }
+        transaction.commit()?;
+        info!(self.logger, "applied rules";
+            "dry_run" => dry_run,
+            "media_checked" => report.media_checked,
+            "added" => report.added.len(),
+        );
+        Ok(report)
+    }
+
     pub fn get_labels<P: AsRef<Path>>(&self, path: P) -> Result<Vec<String>, Error> {
-        LabelSearch::new(
-            &self.connection,
-            LabelFilter {
-                filepath: Some(path.as_ref().to_path_buf().into()),
-            },
-        )?
-        .iter()?
-        .map(from_label_sql_result)
-        .map(|label| label.map(|l| l.label))
-        .collect()
+        get_labels(&self.connection, path.as_ref())
     }
 
     pub fn get_all_labels(&self) -> Result<Vec<String>, Error> {
         LabelSql::get_all_labels(&self.connection).map_err(|e| e.into())
     }
 
+    /// Get all labels arranged by their hierarchy
+    pub fn get_label_tree(&self) -> Result<Vec<LabelNode>, Error> {
+        Ok(label::label_tree(&self.get_all_labels()?))
+    }
+
+    /// Rename a label and its descendants on all media
+    ///
+    /// Renaming to an existing label merges them. Returns the number of labels changed.
+    pub fn rename_label(&mut self, from: &str, to: &str) -> Result<usize, Error> {
+        self.merge_labels(&[from], to)
+    }
+
+    /// Rename several labels and their descendants to one label in a single transaction
+    ///
+    /// `into` is ignored if it is one of `labels`. Returns the number of labels changed.
+    pub fn merge_labels<S: AsRef<str>>(
+        &mut self,
+        labels: &[S],
+        into: &str,
+    ) -> Result<usize, Error> {
+        let into = normalize_label(into)?;
+        let labels = labels
+            .iter()
+            .map(|label| normalize_label(label.as_ref()))
+            .filter(|label| !matches!(label, Ok(label) if *label == into))
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some(from) = labels.iter().find(|from| label::is_within(&into, from)) {
+            return Err(Error::InvalidLabelRename {
+                from: from.clone(),
+                to: into,
+            });
+        }
+        let transaction = self.connection.transaction()?;
+        let mut changed = 0;
+        for from in &labels {
+            changed += LabelSql::rename(&transaction, from, &into)?;
+        }
+        transaction.commit()?;
+        info!(self.logger, "merged labels"; "labels" => ?labels, "into" => into, "changed" => changed);
+        Ok(changed)
+    }
+
+    /// Delete a label and its descendants from all media, returning the number deleted
+    pub fn delete_label_everywhere(&self, label: &str) -> Result<usize, Error> {
+        Ok(LabelSql::delete_everywhere(
+            &self.connection,
+            &normalize_label(label)?,
+        )?)
+    }
+
+    /// Save a filter under a name, replacing any saved search with the same name
+    pub fn save_search(&self, name: &str, filter: MediaFilter) -> Result<(), Error> {
+        let saved_search = SavedSearch {
+            name: name.to_string(),
+            filter,
+        };
+        SavedSearchSql::try_from(&saved_search)?.upsert(&self.connection)?;
+        Ok(())
+    }
+
+    pub fn get_saved_search(&self, name: &str) -> Result<Option<SavedSearch>, Error> {
+        SavedSearchSql::get_by_name(name, &self.connection)?
+            .map(SavedSearch::try_from)
+            .transpose()
+    }
+
+    pub fn get_saved_searches(&self) -> Result<Vec<SavedSearch>, Error> {
+        SavedSearchSql::get_all(&self.connection)?
+            .into_iter()
+            .map(SavedSearch::try_from)
+            .collect()
+    }
+
+    /// Delete a saved search, returning whether it existed
+    pub fn delete_saved_search(&self, name: &str) -> Result<bool, Error> {
+        Ok(SavedSearchSql::delete(name, &self.connection)? > 0)
+    }
+
+    /// Get the media currently matching a saved search
+    pub fn get_media_with_saved_search(&self, name: &str) -> Result<Vec<Media>, Error> {
+        let saved_search = self
+            .get_saved_search(name)?
+            .ok_or_else(|| Error::SavedSearchNotFound(name.to_string()))?;
+        self.get_media_with_filter(saved_search.filter)
+    }
+
+    /// Add media that are not in an event yet to events, see [`event::cluster`]
+    ///
+    /// Media clustered together with media of an existing event join the event of the media taken
+    /// before them, or after them at the start of the cluster. Other media are grouped into new
+    /// events. Existing events, including any edits to them, are kept. Returns the new events and
+    /// the existing events that media were added to.
+    pub fn detect_events(&mut self, config: &EventConfig) -> Result<Vec<Event>, Error> {
+        let transaction = self.connection.transaction()?;
+        let assigned: HashMap<PathBuf, i64> = EventSql::get_all_items(&transaction)?
+            .into_iter()
+            .map(|(filepath, id)| (PathBuf::from(filepath), id))
+            .collect();
+        let mut media = MediaSearch::new_with_filter_defaults(&transaction)?
+            .iter()?
+            .map(from_media_sql_result)
+            .collect::<Result<Vec<_>, _>>()?;
+        media.sort_by_key(|media| media.created);
+        let mut ids = Vec::new();
+        for items in event::cluster(&media, config) {
+            let first_event = items
+                .iter()
+                .find_map(|media| assigned.get(&media.filepath).copied());
+            let Some(mut current) = first_event else {
+                let Some((start, end, place)) = event::summarize(items.iter().copied()) else {
+                    continue;
+                };
+                let id = EventSql {
+                    id: 0,
+                    name: event::default_name(start, place.as_deref()),
+                    start,
+                    end,
+                    place,
+                    count: 0,
+                }
+                .insert(&transaction)?;
+                let items: Vec<PathBufSql> = items
+                    .into_iter()
+                    .map(|media| media.filepath.as_path().into())
+                    .collect();
+                EventSql::add_items(&transaction, id, &items)?;
+                ids.push(id);
+                continue;
+            };
+            let mut added: BTreeMap<i64, Vec<PathBufSql>> = BTreeMap::new();
+            for media in items {
+                match assigned.get(&media.filepath) {
+                    Some(&id) => current = id,
+                    None => added
+                        .entry(current)
+                        .or_default()
+                        .push(media.filepath.as_path().into()),
+                }
+            }
+            for (id, items) in added {
+                EventSql::add_items(&transaction, id, &items)?;
+                update_event_summary(&transaction, id)?;
+                if !ids.contains(&id) {
+                    ids.push(id);
+                }
+            }
+        }
+        transaction.commit()?;
+        info!(self.logger, "detected events"; "events" => ids.len());
+        ids.into_iter().map(|id| self.get_event(id)).collect()
+    }
+
+    /// Get all events ordered by when they started
+    pub fn get_events(&self) -> Result<Vec<Event>, Error> {
+        Ok(EventSql::get_all(&self.connection)?
+            .into_iter()
+            .map(Event::from)
+            .collect())
+    }
+
+    pub fn get_event(&self, id: i64) -> Result<Event, Error> {
+        Ok(EventSql::get_by_id(&self.connection, id)?
+            .ok_or(Error::EventNotFound(id))?
+            .into())
+    }
+
+    /// Get the media in an event ordered by created
+    pub fn get_event_media(&self, id: i64) -> Result<Vec<Media>, Error> {
+        self.get_event(id)?;
+        self.get_media_with_filter(MediaFilter {
+            event: Some(id),
+            ..Default::default()
+        })
+    }
+
+    pub fn rename_event(&self, id: i64, name: &str) -> Result<Event, Error> {
+        let trimmed = name.trim();
+        if trimmed.is_empty() {
+            return Err(Error::InvalidEventName(name.to_string()));
+        }
+        if EventSql::rename(&self.connection, id, trimmed)? == 0 {
+            return Err(Error::EventNotFound(id));
+        }
+        self.get_event(id)
+    }
+
+    /// Move the media of an event taken from `path` onwards into a new event
+    ///
+    /// The new event is named like a detected event and returned.
+    pub fn split_event<P: AsRef<Path>>(&mut self, id: i64, path: P) -> Result<Event, Error> {
+        let path = path.as_ref();
+        let transaction = self.connection.transaction()?;
+        let items = EventSql::get_items(&transaction, id)?;
+        if items.is_empty() {
+            return Err(Error::EventNotFound(id));
+        }
+        let at = items
+            .iter()
+            .position(|item| item.0 == path)
+            .ok_or_else(|| Error::NotInEvent(path.to_path_buf()))?;
+        if at == 0 {
+            return Err(Error::EventSplitAtStart {
+                id,
+                path: path.to_path_buf(),
+            });
+        }
+        let moved = items[at..]
+            .iter()
+            .map(|item| MediaSql::get_by_filepath(&transaction, item))
+            .filter_map(Result::transpose)
+            .map(|media_sql| media_sql.map(Media::from))
+            .collect::<Result<Vec<_>, _>>()?;
+        let (start, end, place) = event::summarize(&moved).ok_or(Error::EventNotFound(id))?;
+        let new_id = EventSql {
+            id: 0,
+            name: event::default_name(start, place.as_deref()),
+            start,
+            end,
+            place,
+            count: 0,
+        }
+        .insert(&transaction)?;
+        EventSql::add_items(&transaction, new_id, &items[at..])?;
+        update_event_summary(&transaction, id)?;
+        transaction.commit()?;
+        self.get_event(new_id)
+    }
+
+    /// Move the media of `others` into the event `into` and delete them
+    pub fn merge_events(&mut self, into: i64, others: &[i64]) -> Result<Event, Error> {
+        let transaction = self.connection.transaction()?;
+        if EventSql::get_by_id(&transaction, into)?.is_none() {
+            return Err(Error::EventNotFound(into));
+        }
+        for &other in others.iter().filter(|&&other| other != into) {
+            let items = EventSql::get_items(&transaction, other)?;
+            if EventSql::delete(&transaction, other)? == 0 {
+                return Err(Error::EventNotFound(other));
+            }
+            EventSql::add_items(&transaction, into, &items)?;
+        }
+        update_event_summary(&transaction, into)?;
+        transaction.commit()?;
+        self.get_event(into)
+    }
+
+    /// Delete all events so they can be detected again, the media stay in the index
+    pub fn clear_events(&self) -> Result<(), Error> {
+        EventSql::delete_all(&self.connection)?;
+        Ok(())
+    }
+
+    /// Add a place, replacing the place with the same name
+    pub fn set_place(&self, place: Place) -> Result<(), Error> {
+        let valid = !place.name.trim().is_empty()
+            && (-90.0..=90.0).contains(&place.latitude)
+            && (-180.0..=180.0).contains(&place.longitude)
+            && place.radius_km > 0.0;
+        if !valid {
+            return Err(Error::InvalidPlace(place));
+        }
+        PlaceSql::from(Place {
+            name: place.name.trim().to_string(),
+            ..place
+        })
+        .upsert(&self.connection)?;
+        Ok(())
+    }
+
+    /// Delete a place, leaving the labels of media taken in it
+    pub fn delete_place(&self, name: &str) -> Result<(), Error> {
+        if PlaceSql::delete(&self.connection, name)? == 0 {
+            return Err(Error::PlaceNotFound(name.to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn get_places(&self) -> Result<Vec<Place>, Error> {
+        Ok(PlaceSql::get_all(&self.connection)?
+            .into_iter()
+            .map(Place::from)
+            .collect())
+    }
+
+    /// Label the media already in the index with the places they were taken in
+    ///
+    /// Returns the number of labels added.
+    pub fn label_places(&mut self) -> Result<usize, Error> {
+        let transaction = self.connection.transaction()?;
+        let places: Vec<Place> = PlaceSql::get_all(&transaction)?
+            .into_iter()
+            .map(Place::from)
+            .collect();
+        let mut added = 0;
+        for media in MediaSearch::new_with_filter_defaults(&transaction)?
+            .iter()?
+            .map(from_media_sql_result)
+        {
+            let media = media?;
+            for place in places.iter().filter(|place| place.contains_media(&media)) {
+                added += LabelSql {
+                    filepath: media.filepath.as_path().into(),
+                    label: place.label(),
+                }
+                .insert_or_ignore(&transaction)?;
+            }
+        }
+        transaction.commit()?;
+        info!(self.logger, "labeled places"; "added" => added);
+        Ok(added)
+    }
+
+    /// Get the periods spent away from the home place, see [`place::trips`]
+    pub fn get_trips(&self, config: &TripConfig) -> Result<Vec<Trip>, Error> {
+        let home = PlaceSql::get_by_name(&self.connection, &config.home)?
+            .ok_or_else(|| Error::PlaceNotFound(config.home.clone()))?;
+        let mut media = self.get_media()?;
+        media.sort_by_key(|media| media.created);
+        Ok(place::trips(&media, &home.into(), config))
+    }
+
+    /// Get the media taken around the month and day of `date` in earlier years, grouped by year
+    /// with the most recent first
+    pub fn get_memories(
+        &self,
+        date: NaiveDate,
+        config: &MemoriesConfig,
+    ) -> Result<Vec<MemoryYear>, Error> {
+        let media = self.get_media_with_filter(memories::filter(date, config))?;
+        Ok(memories::group_by_year(date, media))
+    }
+
+    /// Create an empty album
+    pub fn create_album(&self, name: &str, description: Option<&str>) -> Result<Album, Error> {
+        if AlbumSql::get_by_name(&self.connection, name)?.is_some() {
+            return Err(Error::AlbumExists(name.to_string()));
+        }
+        AlbumSql::insert(&self.connection, name, description)?;
+        self.get_album(name)
+    }
+
+    /// Delete an album, the media in it stay in the index
+    pub fn delete_album(&self, name: &str) -> Result<(), Error> {
+        let album = self.get_album_sql(name)?;
+        AlbumSql::delete(&self.connection, album.id)?;
+        Ok(())
+    }
+
+    pub fn get_album(&self, name: &str) -> Result<Album, Error> {
+        Ok(self.get_album_sql(name)?.into())
+    }
+
+    pub fn get_albums(&self) -> Result<Vec<Album>, Error> {
+        Ok(AlbumSql::get_all(&self.connection)?
+            .into_iter()
+            .map(Album::from)
+            .collect())
+    }
+
+    fn get_album_sql(&self, name: &str) -> Result<AlbumSql, Error> {
+        AlbumSql::get_by_name(&self.connection, name)?
+            .ok_or_else(|| Error::AlbumNotFound(name.to_string()))
+    }
+
+    /// Get the media in an album in album order
+    pub fn get_album_media(&self, name: &str) -> Result<Vec<Media>, Error> {
+        self.get_album_sql(name)?;
+        self.get_media_with_filter(MediaFilter {
+            album: Some(name.to_string()),
+            ..Default::default()
+        })
+    }
+
+    /// Add media to an album at a position, or at the end with `None`
+    ///
+    /// Media already in the album keep their position. Returns the number of media added.
+    pub fn add_to_album<I, P>(
+        &mut self,
+        name: &str,
+        paths: I,
+        position: Option<usize>,
+    ) -> Result<usize, Error>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let album = self.get_album_sql(name)?;
+        let transaction = self.connection.transaction()?;
+        let mut items = AlbumSql::get_items(&transaction, album.id)?;
+        let mut added = Vec::new();
+        for path in paths {
+            let path = path.as_ref();
+            if MediaSql::get_by_filepath(&transaction, &path.into())?.is_none() {
+                return Err(Error::NotIndexed(path.to_path_buf()));
+            }
+            let in_album = |item: &PathBufSql| item.0 == path;
+            if !items.iter().any(in_album) && !added.iter().any(in_album) {
+                added.push(PathBufSql::from(path));
+            }
+        }
+        let count = added.len();
+        let position = position.unwrap_or(items.len()).min(items.len());
+        items.splice(position..position, added);
+        AlbumSql::set_items(&transaction, album.id, &items)?;
+        transaction.commit()?;
+        Ok(count)
+    }
+
+    /// Remove media from an album, returning the number removed
+    pub fn remove_from_album<I, P>(&mut self, name: &str, paths: I) -> Result<usize, Error>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let album = self.get_album_sql(name)?;
+        let paths: Vec<PathBuf> = paths
+            .into_iter()
+            .map(|path| path.as_ref().to_path_buf())
+            .collect();
+        let transaction = self.connection.transaction()?;
+        let mut items = AlbumSql::get_items(&transaction, album.id)?;
+        let count = items.len();
+        items.retain(|item| !paths.contains(&item.0));
+        let removed = count - items.len();
+        AlbumSql::set_items(&transaction, album.id, &items)?;
+        transaction.commit()?;
+        Ok(removed)
+    }
+
+    /// Move media in an album to a position, positions past the end move it to the end
+    pub fn move_in_album<P: AsRef<Path>>(
+        &mut self,
+        name: &str,
+        path: P,
+        position: usize,
+    ) -> Result<(), Error> {
+        let path = path.as_ref();
+        let album = self.get_album_sql(name)?;
+        let transaction = self.connection.transaction()?;
+        let mut items = AlbumSql::get_items(&transaction, album.id)?;
+        let index = items
+            .iter()
+            .position(|item| item.0 == path)
+            .ok_or_else(|| Error::NotInAlbum(path.to_path_buf()))?;
+        let item = items.remove(index);
+        items.insert(position.min(items.len()), item);
+        AlbumSql::set_items(&transaction, album.id, &items)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Set the media shown for an album, which must be in the album, or clear it with `None`
+    pub fn set_album_cover<P: AsRef<Path>>(
+        &self,
+        name: &str,
+        path: Option<P>,
+    ) -> Result<(), Error> {
+        let album = self.get_album_sql(name)?;
+        let cover = path.map(|path| PathBufSql::from(path.as_ref()));
+        if let Some(cover) = &cover {
+            let items = AlbumSql::get_items(&self.connection, album.id)?;
+            if !items.iter().any(|item| item.0 == cover.0) {
+                return Err(Error::NotInAlbum(cover.0.clone()));
+            }
+        }
+        AlbumSql::set_cover(&self.connection, album.id, cover.as_ref())?;
+        Ok(())
+    }
+
+    pub fn set_album_description(
+        &self,
+        name: &str,
+        description: Option<&str>,
+    ) -> Result<(), Error> {
+        let album = self.get_album_sql(name)?;
+        AlbumSql::set_description(&self.connection, album.id, description)?;
+        Ok(())
+    }
+
+    /// Create a directory of symlinks to media
+    ///
+    /// Links are prefixed with the position of the media so the directory lists in the same order.
+    pub fn export_symlinks<P: AsRef<Path>>(
+        &self,
+        media_vec: &[Media],
+        path: P,
+    ) -> Result<(), Error> {
+        let path = path.as_ref();
+        fs::create_dir_all(path)?;
+        for (i, media) in media_vec.iter().enumerate() {
+            let file_name = media.file_name()?;
+            if let Some(file_name) = file_name.to_str() {
+                let link = path.join(format!("{i}_{file_name}"));
+                info!(self.logger, "image"; "name" => link.display());
+                symlink(&media.filepath, link)?;
+            }
+        }
+        Ok(())
+    }
+
     pub fn export_images_with_label(
         &self,
         path_to_index: String,
         label: String,
     ) -> Result<(), Error> {
         let labeled_media = self.get_media_with_filter(MediaFilter {
-            label: Some(label.clone()),
+            label: Some(label.clone().into()),
             ..Default::default()
         })?;
         let label_folder = format!("{path_to_index}/glance-exports/{label}");
-        fs::create_dir_all(label_folder.clone())?;
-        let mut i = 0;
-        for media in labeled_media {
-            let file_name = media.file_name()?;
-            if let Some(file_name) = file_name.to_str() {
-                info!(self.logger, "image";
-                    "name" => format!("{label_folder}/{i}_{file_name}"),
-                );
-                symlink(&media.filepath, format!("{label_folder}/{i}_{file_name}"))?;
-            }
-            i += 1;
-        }
+        self.export_symlinks(&labeled_media, &label_folder)?;
         info!(self.logger, "exported all images with label";
             "label" => label,
             "label_folder" => label_folder,
@@ -534,13 +2046,27 @@ impl Index {
 
 enum FileToMediaRowResult {
     Unmodified,
+    MissingPerceptualHash(u64),
+    /// Face regions of media indexed before they were read from xmp, which replace any read before
+    MissingFaceRegions(Vec<(Option<String>, Rectangle)>),
+    /// The media is unmodified but its xmp sidecar changed since it was read
+    XmpModified {
+        metadata: XmpMetadata,
+        sidecar_modified: Option<DateTime<Utc>>,
+    },
     SkippedFileType,
     New {
-        media: Media,
+        media: Box<Media>,
+        /// Face regions read from xmp with the name of the person if known
+        regions: Vec<(Option<String>, Rectangle)>,
+        /// Modified time of the xmp sidecar if there is one
+        sidecar_modified: Option<DateTime<Utc>>,
         used_exiftool_fallback: bool,
         failed_to_read_exif: bool,
         failed_to_determine_created_from_exif: bool,
         failed_to_determine_created: bool,
+        failed_to_compute_perceptual_hash: bool,
+        failed_to_read_xmp: bool,
     },
 }
 
@@ -553,19 +2079,27 @@ impl FileToMediaRowResult {
         match self {
             Self::New {
                 media,
+                regions: _,
+                sidecar_modified: _,
                 used_exiftool_fallback: _,
                 failed_to_read_exif: _,
                 failed_to_determine_created_from_exif: _,
                 failed_to_determine_created: _,
-            } => Ok(media),
+                failed_to_compute_perceptual_hash: _,
+                failed_to_read_xmp: _,
+            } => Ok(*media),
             _ => Err(err()),
         }
     }
 }
 
+/// Read the media at `entry` unless `existing` is unmodified
+///
+/// `xmp_state` is what was read from the xmp of `existing`.
 fn file_to_media_row(
     entry: &DirEntry,
     existing: Option<&Media>,
+    xmp_state: &XmpStateSql,
     config: &AddDirectoryConfig,
     logger: &Logger,
 ) -> Result<FileToMediaRowResult, Error> {
@@ -581,6 +2115,25 @@ fn file_to_media_row(
                 error!(logger, "hashing enabled but hash missing from media row");
                 return Err(Error::HashMissing);
             }
+            if config.read_xmp {
+                let sidecar_modified = xmp::sidecar_modified(&filepath)?;
+                if sidecar_modified != xmp_state.modified {
+                    return Ok(FileToMediaRowResult::XmpModified {
+                        metadata: xmp::read(&filepath)?,
+                        sidecar_modified,
+                    });
+                }
+                if !xmp_state.regions_read {
+                    let regions = xmp::read(&filepath)?.regions;
+                    return Ok(FileToMediaRowResult::MissingFaceRegions(regions));
+                }
+            }
+            if config.perceptual_hash && existing.perceptual_hash.is_none() {
+                let format = FileFormat::from_file(&filepath)?;
+                if let Some(perceptual_hash) = compute_perceptual_hash(&filepath, &format, logger) {
+                    return Ok(FileToMediaRowResult::MissingPerceptualHash(perceptual_hash));
+                }
+            }
             trace!(logger, "skipping due to modified check");
             return Ok(FileToMediaRowResult::Unmodified);
         }
@@ -601,6 +2154,17 @@ fn file_to_media_row(
         None
     };
 
+    // Compute the perceptual hash
+    let mut failed_to_compute_perceptual_hash = false;
+    let perceptual_hash = if config.perceptual_hash {
+        let perceptual_hash = compute_perceptual_hash(&filepath, &format, logger);
+        failed_to_compute_perceptual_hash =
+            perceptual_hash.is_none() && matches!(format.kind(), Kind::Image);
+        perceptual_hash
+    } else {
+        None
+    };
+
     // Read exif data and extract created, device, and location fields
     let file = std::fs::File::open(&filepath)?;
     let mut bufreader = std::io::BufReader::new(&file);
@@ -609,6 +2173,8 @@ fn file_to_media_row(
     let mut created = None;
     let mut device = None;
     let mut location = None;
+    let mut country = None;
+    let mut coordinates = None;
     let mut used_exiftool_fallback = false;
     let mut failed_to_read_exif = false;
     let mut failed_to_determine_created_from_exif = false;
@@ -631,8 +2197,9 @@ fn file_to_media_row(
                 let model_string = exif_field_to_string(model);
                 device = Some(Device::from(model_string));
             }
+            coordinates = get_coordinates_from_exif(&exif);
             if config.calculate_nearest_city {
-                location = get_location_from_exif(&exif);
+                (location, country) = coordinates.map(nearest_city).unzip();
             }
         }
         Err(e1) => {
@@ -652,6 +2219,32 @@ fn file_to_media_row(
         }
     }
 
+    // Read the rating, flag, title, caption and face regions from xmp
+    let mut rating = 0;
+    let mut flag = None;
+    let mut title = None;
+    let mut caption = None;
+    let mut regions = Vec::new();
+    let mut sidecar_modified = None;
+    let mut failed_to_read_xmp = false;
+    if config.read_xmp {
+        sidecar_modified = xmp::sidecar_modified(&filepath)?;
+        match xmp::read(&filepath) {
+            Ok(metadata) => {
+                if let Some(xmp_rating) = metadata.rating {
+                    (rating, flag) = xmp::to_rating_and_flag(xmp_rating);
+                }
+                title = metadata.title;
+                caption = metadata.caption;
+                regions = metadata.regions;
+            }
+            Err(e) => {
+                error!(logger, "failed reading xmp"; "error" => %e);
+                failed_to_read_xmp = true;
+            }
+        }
+    }
+
     // Fallback to using file data to get created instead of exif
     // TODO: should we always set `metadata_fallback_for_created` to avoid the option
     if created.is_none() {
@@ -668,25 +2261,54 @@ fn file_to_media_row(
     }
 
     Ok(FileToMediaRowResult::New {
-        media: Media {
+        media: Box::new(Media {
             filepath,
             size: metadata.len().into(),
             format: format.name().to_string(),
             created,
             modified,
             location,
+            country,
             device,
             hash,
-        },
+            perceptual_hash,
+            rating,
+            flag,
+            latitude: coordinates.map(|(latitude, _)| latitude),
+            longitude: coordinates.map(|(_, longitude)| longitude),
+            title,
+            caption,
+            notes: None,
+        }),
+        regions,
+        sidecar_modified,
         used_exiftool_fallback,
         failed_to_read_exif,
         failed_to_determine_created_from_exif,
         failed_to_determine_created,
+        failed_to_compute_perceptual_hash,
+        failed_to_read_xmp,
     })
 }
 
+/// Compute the perceptual hash of images, returning `None` for other file types or if the image
+/// could not be decoded
+fn compute_perceptual_hash(filepath: &Path, format: &FileFormat, logger: &Logger) -> Option<u64> {
+    if !matches!(format.kind(), Kind::Image) {
+        return None;
+    }
+    match perceptual_hash::perceptual_hash(filepath) {
+        Ok(perceptual_hash) => Some(perceptual_hash),
+        Err(e) => {
+            error!(logger, "failed to compute perceptual hash"; "error" => %e);
+            None
+        }
+    }
+}
+
+/// Latitude and longitude in decimal degrees
 #[allow(clippy::get_first)]
-fn get_location_from_exif(exif: &Exif) -> Option<String> {
+fn get_coordinates_from_exif(exif: &Exif) -> Option<(f64, f64)> {
     fn to_decimal_degrees(degree_minute_second: &[Rational], bearing: &str) -> Option<f64> {
         let degrees = degree_minute_second.get(0)?.num as i32;
         let minutes = degree_minute_second.get(1)?.num as i32;
@@ -712,18 +2334,147 @@ fn get_location_from_exif(exif: &Exif) -> Option<String> {
         (Value::Rational(lat_dms), Value::Rational(long_dms), lat_bearing, long_bearing) => {
             let lat_degrees = to_decimal_degrees(lat_dms, &lat_bearing)?;
             let long_degrees = to_decimal_degrees(long_dms, &long_bearing)?;
-
-            let geocoder = ReverseGeocoder::new();
-            let search_result = geocoder.search((lat_degrees, long_degrees));
-            Some(format!(
-                "{}, {}",
-                search_result.record.name, search_result.record.admin1
-            ))
+            Some((lat_degrees, long_degrees))
         }
         _ => None,
     }
 }
 
+/// Name and region of the nearest city, and the code of the country it is in
+fn nearest_city(coordinates: (f64, f64)) -> (String, String) {
+    let geocoder = ReverseGeocoder::new();
+    let search_result = geocoder.search(coordinates);
+    (
+        format!(
+            "{}, {}",
+            search_result.record.name, search_result.record.admin1
+        ),
+        search_result.record.cc.clone(),
+    )
+}
+
+/// Remove the thumbnails of deindexed media unless another indexed copy shares them
+fn remove_unused_thumbnail(
+    conn: &Connection,
+    thumbnail_cache: Option<&ThumbnailCache>,
+    media: &Media,
+    logger: &Logger,
+) -> Result<(), Error> {
+    if let Some(thumbnail_cache) = thumbnail_cache {
+        if let Some(hash) = media.hash {
+            if MediaSql::exists_by_hash(conn, hash.into())? {
+                return Ok(());
+            }
+        }
+        if let Err(e) = thumbnail_cache.remove(media) {
+            error!(logger, "failed to remove thumbnail";
+                "path" => media.filepath.display(),
+                "error" => %e,
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Trimmed text, `None` when it is empty
+fn trimmed(text: &Option<String>) -> Option<&str> {
+    text.as_deref()
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+}
+
+/// Update the rating, flag, title, caption and face regions of indexed media from its xmp
+///
+/// Properties missing from the xmp are left as they are. Picks are not written to xmp so they are
+/// kept unless the xmp rejects the media, and regions added or edited by hand are kept.
+fn update_from_xmp(
+    conn: &Connection,
+    filepath: &PathBufSql,
+    media: &Media,
+    metadata: XmpMetadata,
+) -> Result<(), Error> {
+    if let Some(xmp_rating) = metadata.rating {
+        let (rating, flag) = xmp::to_rating_and_flag(xmp_rating);
+        MediaSql::set_rating(conn, filepath, rating)?;
+        MediaSql::set_flag(
+            conn,
+            filepath,
+            flag.or(media.flag.filter(|f| *f == Flag::Pick)),
+        )?;
+    }
+    MediaSql::set_text(
+        conn,
+        filepath,
+        metadata.title.as_deref().or(media.title.as_deref()),
+        metadata.caption.as_deref().or(media.caption.as_deref()),
+        media.notes.as_deref(),
+    )?;
+    FaceRegionSql::delete_from_metadata(conn, filepath)?;
+    for (person, rectangle) in metadata.regions {
+        face_region_sql(&filepath.0, person, rectangle, RegionSource::Metadata).insert(conn)?;
+    }
+    Ok(())
+}
+
+fn face_region_sql(
+    filepath: &Path,
+    person: Option<String>,
+    rectangle: Rectangle,
+    source: RegionSource,
+) -> FaceRegionSql {
+    FaceRegionSql {
+        id: 0,
+        filepath: filepath.into(),
+        person,
+        x: rectangle.x,
+        y: rectangle.y,
+        width: rectangle.width,
+        height: rectangle.height,
+        source,
+    }
+}
+
+/// Set the start, end and place of an event from its media, deleting it if it has none
+fn update_event_summary(conn: &Connection, id: i64) -> Result<(), Error> {
+    let Some(event) = EventSql::get_by_id(conn, id)? else {
+        return Err(Error::EventNotFound(id));
+    };
+    let mut media = Vec::new();
+    for item in EventSql::get_items(conn, id)? {
+        if let Some(media_sql) = MediaSql::get_by_filepath(conn, &item)? {
+            media.push(Media::from(media_sql));
+        }
+    }
+    match event::summarize(&media) {
+        Some((start, end, place)) => EventSql {
+            start,
+            end,
+            place,
+            ..event
+        }
+        .update(conn)?,
+        None => EventSql::delete(conn, id)?,
+    };
+    Ok(())
+}
+
+fn normalize_label(label: &str) -> Result<String, Error> {
+    label::normalize(label).ok_or_else(|| Error::InvalidLabel(label.to_string()))
+}
+
+fn get_labels(conn: &Connection, path: &Path) -> Result<Vec<String>, Error> {
+    LabelSearch::new(
+        conn,
+        LabelFilter {
+            filepath: Some(path.to_path_buf().into()),
+        },
+    )?
+    .iter()?
+    .map(from_label_sql_result)
+    .map(|label| label.map(|l| l.label))
+    .collect()
+}
+
 fn from_media_sql_result(media_sql: Result<MediaSql, rusqlite::Error>) -> Result<Media, Error> {
     media_sql.map(|m| m.into()).map_err(|e| e.into())
 }
@@ -0,0 +1,89 @@
+use serde::{Deserialize, Serialize};
+
+use crate::store::people_sql::{FaceRegionSql, PersonSql};
+
+/// Where a face region came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RegionSource {
+    /// Read from the MWG or Microsoft regions in the XMP of the media
+    Metadata,
+    /// Added or edited by hand
+    Manual,
+}
+
+impl RegionSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Metadata => "metadata",
+            Self::Manual => "manual",
+        }
+    }
+}
+
+/// Rectangle from its top left corner in fractions of the width and height of the image
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Rectangle {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Rectangle {
+    /// Clip the rectangle to the image, returning `None` if nothing is left
+    pub fn clamped(self) -> Option<Self> {
+        let clamp = |value: f64| value.clamp(0.0, 1.0);
+        let (x, y) = (clamp(self.x), clamp(self.y));
+        let width = clamp(self.x + self.width) - x;
+        let height = clamp(self.y + self.height) - y;
+        (width > 0.0 && height > 0.0).then_some(Self {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+}
+
+/// Face in media, optionally named
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FaceRegion {
+    pub id: i64,
+    pub person: Option<String>,
+    pub rectangle: Rectangle,
+    pub source: RegionSource,
+}
+
+impl From<FaceRegionSql> for FaceRegion {
+    fn from(value: FaceRegionSql) -> Self {
+        Self {
+            id: value.id,
+            person: value.person,
+            rectangle: Rectangle {
+                x: value.x,
+                y: value.y,
+                width: value.width,
+                height: value.height,
+            },
+            source: value.source,
+        }
+    }
+}
+
+/// Person named in at least one face region
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Person {
+    pub name: String,
+    /// Number of media the person is in
+    pub count: i64,
+}
+
+impl From<PersonSql> for Person {
+    fn from(value: PersonSql) -> Self {
+        Self {
+            name: value.name,
+            count: value.count,
+        }
+    }
+}
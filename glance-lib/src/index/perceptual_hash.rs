@@ -0,0 +1,106 @@
+//! Perceptual hashing of images for near duplicate detection
+//!
+//! Uses a difference hash (dHash). The image is shrunk to a 9x8 grayscale image and each bit of
+//! the hash records if a pixel is brighter than its right neighbor. Resized, recompressed, or
+//! lightly edited copies of an image produce hashes with a small hamming distance.
+
+use std::{collections::HashMap, path::Path};
+
+use glance_util::bk_tree::BkTree;
+use image::{io::Reader, Rgba};
+
+use super::{media::Media, Error};
+
+const WIDTH: u32 = 8;
+const HEIGHT: u32 = 8;
+
+/// Compute the difference hash of the image at `path`
+pub(crate) fn perceptual_hash(path: &Path) -> Result<u64, Error> {
+    let image = Reader::open(path)?.with_guessed_format()?.decode()?;
+    let image = image.thumbnail_exact(WIDTH + 1, HEIGHT).into_rgba8();
+    let mut hash = 0u64;
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            hash <<= 1;
+            if brightness(image.get_pixel(x, y)) < brightness(image.get_pixel(x + 1, y)) {
+                hash |= 1;
+            }
+        }
+    }
+    Ok(hash)
+}
+
+/// Luma of a pixel composited onto a white background
+///
+/// Flattening transparency makes a png with a transparent background hash the same as a jpg
+/// export of it.
+fn brightness(pixel: &Rgba<u8>) -> f32 {
+    let [r, g, b, a] = pixel.0.map(|c| c as f32 / 255.0);
+    let luma = 0.299 * r + 0.587 * g + 0.114 * b;
+    luma * a + (1.0 - a)
+}
+
+/// Group media whose perceptual hashes are within `threshold` bits of each other
+///
+/// Clustering is transitive so two media in a cluster may be further than `threshold` apart if
+/// they are connected by other media. Media without a perceptual hash and clusters with a single
+/// member are dropped.
+pub(crate) fn cluster(media_vec: Vec<Media>, threshold: u32) -> Vec<Vec<Media>> {
+    let media_vec = media_vec
+        .into_iter()
+        .filter_map(|m| m.perceptual_hash.map(|h| (h, m)))
+        .collect::<Vec<_>>();
+
+    let mut tree = BkTree::new();
+    for (i, (hash, _)) in media_vec.iter().enumerate() {
+        tree.insert(*hash, i);
+    }
+
+    let mut sets = DisjointSets::new(media_vec.len());
+    for (i, (hash, _)) in media_vec.iter().enumerate() {
+        for (_, j) in tree.find(*hash, threshold) {
+            sets.union(i, *j);
+        }
+    }
+
+    let mut clusters = Vec::<Vec<Media>>::new();
+    let mut cluster_by_root = HashMap::new();
+    for (i, (_, media)) in media_vec.into_iter().enumerate() {
+        let root = sets.find(i);
+        let cluster = *cluster_by_root.entry(root).or_insert_with(|| {
+            clusters.push(Vec::new());
+            clusters.len() - 1
+        });
+        clusters[cluster].push(media);
+    }
+    clusters.retain(|c| c.len() > 1);
+    clusters
+}
+
+struct DisjointSets {
+    parents: Vec<usize>,
+}
+
+impl DisjointSets {
+    fn new(len: usize) -> Self {
+        Self {
+            parents: (0..len).collect(),
+        }
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parents[i] != i {
+            self.parents[i] = self.parents[self.parents[i]];
+            i = self.parents[i];
+        }
+        i
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let a = self.find(a);
+        let b = self.find(b);
+        if a != b {
+            self.parents[a.max(b)] = a.min(b);
+        }
+    }
+}
@@ -0,0 +1,143 @@
+//! Named places like Home or the Office and trips spent away from home
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::store::place_sql::{PlaceSql, KM_PER_DEGREE};
+
+use super::{event, label::LABEL_SEPARATOR, media::Media};
+
+/// Label under which media are labeled with the places they were taken in
+pub const PLACE_LABEL_PREFIX: &str = "places";
+
+/// Circle around a point in decimal degrees
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Place {
+    pub name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub radius_km: f64,
+}
+
+impl Place {
+    /// Whether a point is inside the place, measured like [`PlaceSql`] does in the index
+    pub fn contains(&self, latitude: f64, longitude: f64) -> bool {
+        let longitude_scale = self.latitude.to_radians().cos();
+        let radius = self.radius_km / KM_PER_DEGREE;
+        (latitude - self.latitude).powi(2)
+            + (wrap_longitude(longitude - self.longitude) * longitude_scale).powi(2)
+            <= radius.powi(2)
+    }
+
+    /// Whether media was taken inside the place, false if it is not geotagged
+    pub fn contains_media(&self, media: &Media) -> bool {
+        match (media.latitude, media.longitude) {
+            (Some(latitude), Some(longitude)) => self.contains(latitude, longitude),
+            _ => false,
+        }
+    }
+
+    /// Label given to media taken inside the place
+    pub fn label(&self) -> String {
+        format!("{PLACE_LABEL_PREFIX}{LABEL_SEPARATOR}{}", self.name)
+    }
+}
+
+impl From<PlaceSql> for Place {
+    fn from(value: PlaceSql) -> Self {
+        Self {
+            name: value.name,
+            latitude: value.latitude,
+            longitude: value.longitude,
+            radius_km: value.radius_km,
+        }
+    }
+}
+
+impl From<Place> for PlaceSql {
+    fn from(value: Place) -> Self {
+        Self {
+            name: value.name,
+            latitude: value.latitude,
+            longitude: value.longitude,
+            radius_km: value.radius_km,
+        }
+    }
+}
+
+/// Period spent away from home
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Trip {
+    /// When the first media away from home was taken
+    pub start: DateTime<Utc>,
+    /// When the last media before returning home was taken
+    pub end: DateTime<Utc>,
+    /// Most common location of the media taken away
+    pub place: Option<String>,
+    /// Number of geotagged media taken away
+    pub count: usize,
+}
+
+/// How trips are told apart
+#[derive(Debug, Clone)]
+pub struct TripConfig {
+    /// Name of the place trips start from and return to
+    pub home: String,
+    /// Longest time between media away from home in the same trip
+    pub max_gap: TimeDelta,
+}
+
+impl Default for TripConfig {
+    fn default() -> Self {
+        Self {
+            home: "Home".to_string(),
+            max_gap: TimeDelta::days(3),
+        }
+    }
+}
+
+/// Find the trips in media ordered by created
+///
+/// A trip starts with geotagged media outside of `home` and ends with the last media before
+/// either geotagged media inside `home` or a gap longer than `max_gap`. Media that are not
+/// geotagged or have no created time are ignored.
+pub(crate) fn trips(media: &[Media], home: &Place, config: &TripConfig) -> Vec<Trip> {
+    let mut trips = Vec::new();
+    let mut away: Vec<&Media> = Vec::new();
+    for media in media {
+        let Some(created) = media.created else {
+            continue;
+        };
+        if media.latitude.is_none() || media.longitude.is_none() {
+            continue;
+        }
+        let gap = away
+            .last()
+            .and_then(|last| last.created)
+            .is_some_and(|last| created - last > config.max_gap);
+        if home.contains_media(media) || gap {
+            trips.extend(to_trip(&away));
+            away.clear();
+        }
+        if !home.contains_media(media) {
+            away.push(media);
+        }
+    }
+    trips.extend(to_trip(&away));
+    trips
+}
+
+fn to_trip(away: &[&Media]) -> Option<Trip> {
+    let (start, end, place) = event::summarize(away.iter().copied())?;
+    Some(Trip {
+        start,
+        end,
+        place,
+        count: away.len(),
+    })
+}
+
+/// Wrap a difference of longitudes into [-180, 180] like `longitude_difference_sql`
+fn wrap_longitude(difference: f64) -> f64 {
+    (difference + 180.0).rem_euclid(360.0) - 180.0
+}
@@ -0,0 +1,348 @@
+//! Text query language for searching the index
+//!
+//! A query is a list of whitespace separated terms which must all match, for example
+//! `label:beach device:"Canon EOS 40D" taken:2019..2021 -label:blurry near:48.1,11.5,5km`.
+//!
+//! * `label:NAME` - media has the label or a label below it like `NAME/child`, `-label:NAME`
+//!   excludes those media
+//! * `device:NAME` and `format:NAME` - media has exactly this device or format
+//! * `album:NAME` - media is in the album, and media are then in album order
+//! * `event:ID` - media is in the event with this id
+//! * `place:NAME` - media was taken inside the named place
+//! * `person:NAME` - media has a face region of the person, given more than once all of the
+//!   people must be in the media
+//! * `taken:DATE`, `taken:DATE..DATE`, `taken:DATE..` or `taken:..DATE` - media was created
+//!   within the dates, which are `YYYY`, `YYYY-MM` or `YYYY-MM-DD` in UTC and include the whole
+//!   year, month or day
+//! * `on:MM-DD` or `on:MM-DD..MM-DD` - media was created on the day of the year in any year, or
+//!   within the days which wrap around the new year when the first is after the last, in UTC
+//! * `rating:N` - media is rated at least `N` stars
+//! * `flag:pick` or `flag:reject` - media has the flag
+//! * `near:LAT,LON` or `near:LAT,LON,RADIUS` - media was taken within the radius (`5km` or
+//!   `500m`, 1km by default) of a point
+//! * `within:SOUTH,WEST,NORTH,EAST` - media was taken inside the rectangle of latitudes and
+//!   longitudes, which crosses the antimeridian when `WEST` is more than `EAST`
+//! * any other word must start a word in the path, location, device, labels, title, caption or
+//!   notes of media, and media are then ordered by how well they match
+//!
+//! Values with spaces are wrapped in `"`, inside which `\` escapes the next character.
+
+use std::str::FromStr;
+
+use chrono::{DateTime, Months, NaiveDate, NaiveTime, TimeDelta, Utc};
+use displaydoc::Display;
+use thiserror::Error;
+
+use super::media::{
+    Bounds, Flag, LabelQuery, MediaFilter, MonthDay, MonthDayRange, Near, MAX_RATING,
+};
+
+const DEFAULT_RADIUS_KM: f64 = 1.0;
+
+/// First and last instants of a range of dates, `None` when the range is open on that side
+type DateRange = (Option<DateTime<Utc>>, Option<DateTime<Utc>>);
+
+/// Error parsing a query, columns count characters from 1
+#[derive(Debug, Error, Display, PartialEq)]
+pub enum ParseError {
+    /// unterminated quote starting at column {0}
+    UnterminatedQuote(usize),
+    /// missing value for '{field}' at column {column}
+    MissingValue { field: String, column: usize },
+    /// unknown field '{field}' at column {column}
+    UnknownField { field: String, column: usize },
+    /// '{field}' is given more than once at column {column}
+    DuplicateField { field: String, column: usize },
+    /// '-' can only exclude labels at column {column}
+    InvalidNegation { column: usize },
+    /// invalid date '{value}' at column {column}, expected YYYY, YYYY-MM or YYYY-MM-DD
+    InvalidDate { value: String, column: usize },
+    /// invalid day of the year '{value}' at column {column}, expected MM-DD or MM-DD..MM-DD
+    InvalidMonthDay { value: String, column: usize },
+    /// invalid rating '{value}' at column {column}, expected 0 to 5
+    InvalidRating { value: String, column: usize },
+    /// invalid flag '{value}' at column {column}, expected pick or reject
+    InvalidFlag { value: String, column: usize },
+    /// invalid point '{value}' at column {column}, expected LAT,LON or LAT,LON,RADIUS
+    InvalidNear { value: String, column: usize },
+    /// invalid rectangle '{value}' at column {column}, expected SOUTH,WEST,NORTH,EAST
+    InvalidWithin { value: String, column: usize },
+    /// invalid event '{value}' at column {column}, expected the id of an event
+    InvalidEvent { value: String, column: usize },
+}
+
+#[derive(Debug)]
+struct Term {
+    /// Column of the first character of the term
+    column: usize,
+    negated: bool,
+    field: Option<String>,
+    value: String,
+}
+
+/// Parse a query into a filter
+pub fn parse(query: &str) -> Result<MediaFilter, ParseError> {
+    let mut filter = MediaFilter::default();
+    let mut labels = Vec::new();
+    let mut excluded_labels = Vec::new();
+    for term in terms(query)? {
+        let Term {
+            column,
+            negated,
+            field,
+            value,
+        } = term;
+        let Some(field) = field else {
+            if negated {
+                return Err(ParseError::InvalidNegation { column });
+            }
+            if !value.is_empty() {
+                filter.text.push(value);
+            }
+            continue;
+        };
+        if value.is_empty() {
+            return Err(ParseError::MissingValue { field, column });
+        }
+        if negated && field != "label" {
+            return Err(ParseError::InvalidNegation { column });
+        }
+        match field.as_str() {
+            "label" if negated => excluded_labels.push(value.into()),
+            "label" => labels.push(value.into()),
+            "device" => set_once(&mut filter.device, value, &field, column)?,
+            "format" => set_once(&mut filter.format, value, &field, column)?,
+            "album" => set_once(&mut filter.album, value, &field, column)?,
+            "place" => set_once(&mut filter.place, value, &field, column)?,
+            "person" => filter.people.push(value),
+            "event" => {
+                let event = value
+                    .parse()
+                    .map_err(|_| ParseError::InvalidEvent { value, column })?;
+                set_once(&mut filter.event, event, &field, column)?;
+            }
+            "taken" => {
+                let (start, end) =
+                    parse_taken(&value).ok_or(ParseError::InvalidDate { value, column })?;
+                if filter.created_start.is_some() || filter.created_end.is_some() {
+                    return Err(ParseError::DuplicateField { field, column });
+                }
+                filter.created_start = start;
+                filter.created_end = end;
+            }
+            "on" => {
+                let month_day = parse_month_days(&value)
+                    .ok_or(ParseError::InvalidMonthDay { value, column })?;
+                set_once(&mut filter.month_day, month_day, &field, column)?;
+            }
+            "rating" => {
+                let rating = value
+                    .parse()
+                    .ok()
+                    .filter(|rating| *rating <= MAX_RATING)
+                    .ok_or(ParseError::InvalidRating { value, column })?;
+                set_once(&mut filter.min_rating, rating, &field, column)?;
+            }
+            "flag" => {
+                let flag = value
+                    .parse::<Flag>()
+                    .map_err(|_| ParseError::InvalidFlag { value, column })?;
+                set_once(&mut filter.flag, flag, &field, column)?;
+            }
+            "near" => {
+                let near = parse_near(&value).ok_or(ParseError::InvalidNear { value, column })?;
+                set_once(&mut filter.near, near, &field, column)?;
+            }
+            "within" => {
+                let within =
+                    parse_within(&value).ok_or(ParseError::InvalidWithin { value, column })?;
+                set_once(&mut filter.within, within, &field, column)?;
+            }
+            _ => return Err(ParseError::UnknownField { field, column }),
+        }
+    }
+    if !excluded_labels.is_empty() {
+        labels.push(LabelQuery::NoneOf(excluded_labels));
+    }
+    filter.label = match labels.len() {
+        0 => None,
+        1 => labels.pop(),
+        _ => Some(LabelQuery::AllOf(labels)),
+    };
+    Ok(filter)
+}
+
+impl FromStr for MediaFilter {
+    type Err = ParseError;
+
+    fn from_str(query: &str) -> Result<Self, Self::Err> {
+        parse(query)
+    }
+}
+
+/// Split a query into terms, removing quotes
+fn terms(query: &str) -> Result<Vec<Term>, ParseError> {
+    let mut terms = Vec::new();
+    let mut chars = query.chars().zip(1..).peekable();
+    loop {
+        while chars.next_if(|(c, _)| c.is_whitespace()).is_some() {}
+        let Some(&(_, column)) = chars.peek() else {
+            return Ok(terms);
+        };
+        let negated = chars.next_if(|(c, _)| *c == '-').is_some();
+        let mut field = None;
+        let mut value = String::new();
+        let mut quoted = false;
+        while let Some((c, quote_column)) = chars.next_if(|(c, _)| !c.is_whitespace()) {
+            match c {
+                '"' => {
+                    quoted = true;
+                    loop {
+                        match chars.next() {
+                            Some(('"', _)) => break,
+                            Some(('\\', _)) => match chars.next() {
+                                Some((escaped, _)) => value.push(escaped),
+                                None => return Err(ParseError::UnterminatedQuote(quote_column)),
+                            },
+                            Some((c, _)) => value.push(c),
+                            None => return Err(ParseError::UnterminatedQuote(quote_column)),
+                        }
+                    }
+                }
+                ':' if field.is_none() && !quoted => field = Some(std::mem::take(&mut value)),
+                c => value.push(c),
+            }
+        }
+        terms.push(Term {
+            column,
+            negated,
+            field,
+            value,
+        });
+    }
+}
+
+fn set_once<T>(
+    slot: &mut Option<T>,
+    value: T,
+    field: &str,
+    column: usize,
+) -> Result<(), ParseError> {
+    if slot.replace(value).is_some() {
+        return Err(ParseError::DuplicateField {
+            field: field.to_string(),
+            column,
+        });
+    }
+    Ok(())
+}
+
+/// Parse `DATE`, `DATE..DATE`, `DATE..` or `..DATE` into the first and last instants they cover
+fn parse_taken(value: &str) -> Option<DateRange> {
+    let (start, end) = value.split_once("..").unwrap_or((value, value));
+    if start.is_empty() && end.is_empty() {
+        return None;
+    }
+    let start = match start {
+        "" => None,
+        start => Some(parse_date(start)?.0),
+    };
+    let end = match end {
+        "" => None,
+        end => Some(parse_date(end)?.1),
+    };
+    Some((start, end))
+}
+
+/// Parse `YYYY`, `YYYY-MM` or `YYYY-MM-DD` into the first and last instants of the period
+fn parse_date(date: &str) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let parts = date
+        .split('-')
+        .map(|part| part.parse().ok())
+        .collect::<Option<Vec<u32>>>()?;
+    let (first, next) = match parts[..] {
+        [year] => {
+            let first = NaiveDate::from_ymd_opt(year.try_into().ok()?, 1, 1)?;
+            (first, first.checked_add_months(Months::new(12))?)
+        }
+        [year, month] => {
+            let first = NaiveDate::from_ymd_opt(year.try_into().ok()?, month, 1)?;
+            (first, first.checked_add_months(Months::new(1))?)
+        }
+        [year, month, day] => {
+            let first = NaiveDate::from_ymd_opt(year.try_into().ok()?, month, day)?;
+            (first, first.succ_opt()?)
+        }
+        _ => return None,
+    };
+    let last = next.and_time(NaiveTime::MIN) - TimeDelta::nanoseconds(1);
+    Some((first.and_time(NaiveTime::MIN).and_utc(), last.and_utc()))
+}
+
+/// Parse `MM-DD` or `MM-DD..MM-DD`
+fn parse_month_days(value: &str) -> Option<MonthDayRange> {
+    let (start, end) = value.split_once("..").unwrap_or((value, value));
+    Some(MonthDayRange {
+        start: parse_month_day(start)?,
+        end: parse_month_day(end)?,
+    })
+}
+
+/// Parse `MM-DD`, checked against a leap year so `02-29` is allowed
+fn parse_month_day(value: &str) -> Option<MonthDay> {
+    let (month, day) = value.split_once('-')?;
+    let date = NaiveDate::from_ymd_opt(2000, month.parse().ok()?, day.parse().ok()?)?;
+    Some(date.into())
+}
+
+/// Parse `LAT,LON` or `LAT,LON,RADIUS` where the radius ends in `km` or `m`
+fn parse_near(value: &str) -> Option<Near> {
+    let parts: Vec<_> = value.split(',').collect();
+    let (latitude, longitude, radius) = match parts[..] {
+        [latitude, longitude] => (latitude, longitude, None),
+        [latitude, longitude, radius] => (latitude, longitude, Some(radius)),
+        _ => return None,
+    };
+    let latitude: f64 = latitude.trim().parse().ok()?;
+    let longitude: f64 = longitude.trim().parse().ok()?;
+    let radius_km = match radius.map(str::trim) {
+        None => DEFAULT_RADIUS_KM,
+        Some(radius) => match radius.strip_suffix("km") {
+            Some(km) => km.parse().ok()?,
+            None => radius.strip_suffix('m')?.parse::<f64>().ok()? / 1000.0,
+        },
+    };
+    let valid = (-90.0..=90.0).contains(&latitude)
+        && (-180.0..=180.0).contains(&longitude)
+        && radius_km.is_finite()
+        && radius_km > 0.0;
+    valid.then_some(Near {
+        latitude,
+        longitude,
+        radius_km,
+    })
+}
+
+/// Parse `SOUTH,WEST,NORTH,EAST`
+fn parse_within(value: &str) -> Option<Bounds> {
+    let parts = value
+        .split(',')
+        .map(|part| part.trim().parse().ok())
+        .collect::<Option<Vec<f64>>>()?;
+    let [south, west, north, east] = parts[..] else {
+        return None;
+    };
+    let latitudes = -90.0..=90.0;
+    let longitudes = -180.0..=180.0;
+    let valid = latitudes.contains(&south)
+        && latitudes.contains(&north)
+        && south <= north
+        && longitudes.contains(&west)
+        && longitudes.contains(&east);
+    valid.then_some(Bounds {
+        south,
+        west,
+        north,
+        east,
+    })
+}
@@ -0,0 +1,278 @@
+//! Labeling media automatically with rules read from a TOML file
+//!
+//! Each `[[rule]]` has the labels it adds and any number of conditions, all of which must hold
+//! for the labels to be added:
+//!
+//! ```toml
+//! [[rule]]
+//! name = "drone"
+//! device = "DJI FC3170"
+//! labels = ["drone"]
+//!
+//! [[rule]]
+//! path = "phones/alice/**"
+//! labels = ["alice"]
+//!
+//! [[rule]]
+//! min_aspect_ratio = 3.0
+//! labels = ["pano"]
+//!
+//! [[rule]]
+//! exif = { LensModel = "EF16-35mm f/4L IS USM" }
+//! labels = ["wide"]
+//! ```
+//!
+//! `path` is a glob where `*` and `?` match within a directory and `**` matches across
+//! directories. Globs that do not start with `/` may match from any directory. `exif` compares
+//! the values of EXIF tags by their names and the dimension conditions are in pixels. EXIF and
+//! dimensions are only read from the file when a rule needs them, and media that do not have them
+//! never match.
+
+use std::{
+    cell::OnceCell,
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use exif::In;
+use serde::{Deserialize, Serialize};
+
+use super::{exif_field_to_string, label, media::Flag, media::Media, Error};
+
+/// Rules read from a TOML file, see [`self`] for the format
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleSet {
+    #[serde(rename = "rule", default)]
+    pub rules: Vec<Rule>,
+}
+
+/// Labels to add to media matching every condition
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// Name shown in reports, defaults to the position of the rule
+    pub name: Option<String>,
+    pub labels: Vec<String>,
+    /// Glob the path of the media must match
+    pub path: Option<String>,
+    pub device: Option<String>,
+    pub format: Option<String>,
+    pub location: Option<String>,
+    pub min_rating: Option<u8>,
+    pub flag: Option<Flag>,
+    /// Values EXIF tags must have, keyed by the tag name like `Model` or `LensModel`
+    #[serde(default)]
+    pub exif: BTreeMap<String, String>,
+    pub min_width: Option<u32>,
+    pub max_width: Option<u32>,
+    pub min_height: Option<u32>,
+    pub max_height: Option<u32>,
+    /// Smallest width divided by height
+    pub min_aspect_ratio: Option<f64>,
+    pub max_aspect_ratio: Option<f64>,
+}
+
+/// Label a rule adds to media
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RuleMatch {
+    pub filepath: PathBuf,
+    pub rule: String,
+    pub label: String,
+}
+
+/// What applying rules to the media already in the index did, or would do for a dry run
+#[derive(Debug, Default, Serialize)]
+pub struct ApplyRulesReport {
+    pub dry_run: bool,
+    pub media_checked: u64,
+    /// Labels the media did not already have
+    pub added: Vec<RuleMatch>,
+}
+
+impl RuleSet {
+    /// Parse rules, normalizing their labels
+    pub fn from_toml(toml: &str) -> Result<Self, Error> {
+        let mut rule_set: Self = toml::from_str(toml)?;
+        for rule in &mut rule_set.rules {
+            rule.labels = rule
+                .labels
+                .iter()
+                .map(|l| label::normalize(l).ok_or_else(|| Error::InvalidLabel(l.clone())))
+                .collect::<Result<_, _>>()?;
+        }
+        Ok(rule_set)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::from_toml(&fs::read_to_string(path)?)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Labels the rules add to media, each label at most once
+    pub fn matches(&self, media: &Media) -> Vec<RuleMatch> {
+        let facts = Facts::new(&media.filepath);
+        let mut matches: Vec<RuleMatch> = Vec::new();
+        for (i, rule) in self.rules.iter().enumerate() {
+            if !rule.is_match(media, &facts) {
+                continue;
+            }
+            for label in &rule.labels {
+                if matches.iter().all(|m| &m.label != label) {
+                    matches.push(RuleMatch {
+                        filepath: media.filepath.clone(),
+                        rule: rule.name.clone().unwrap_or_else(|| format!("#{}", i + 1)),
+                        label: label.clone(),
+                    });
+                }
+            }
+        }
+        matches
+    }
+}
+
+impl Rule {
+    fn is_match(&self, media: &Media, facts: &Facts) -> bool {
+        let equals = |condition: &Option<String>, value: Option<&str>| {
+            condition.as_deref().is_none_or(|c| Some(c) == value)
+        };
+        let matches_media = self
+            .path
+            .as_deref()
+            .is_none_or(|glob| glob_match(glob, &media.filepath.to_string_lossy()))
+            && equals(&self.device, media.device.as_ref().map(|d| d.0.as_str()))
+            && equals(&self.format, Some(&media.format))
+            && equals(&self.location, media.location.as_deref())
+            && self.min_rating.is_none_or(|r| media.rating >= r)
+            && self.flag.is_none_or(|f| media.flag == Some(f));
+        if !matches_media {
+            return false;
+        }
+        if !self.exif.is_empty() {
+            let Some(exif) = facts.exif() else {
+                return false;
+            };
+            if !self
+                .exif
+                .iter()
+                .all(|(tag, value)| exif.get(tag) == Some(value))
+            {
+                return false;
+            }
+        }
+        let needs_dimensions = self.min_width.is_some()
+            || self.max_width.is_some()
+            || self.min_height.is_some()
+            || self.max_height.is_some()
+            || self.min_aspect_ratio.is_some()
+            || self.max_aspect_ratio.is_some();
+        if needs_dimensions {
+            let Some((width, height)) = facts.dimensions() else {
+                return false;
+            };
+            let aspect_ratio = width as f64 / height.max(1) as f64;
+            return self.min_width.is_none_or(|w| width >= w)
+                && self.max_width.is_none_or(|w| width <= w)
+                && self.min_height.is_none_or(|h| height >= h)
+                && self.max_height.is_none_or(|h| height <= h)
+                && self.min_aspect_ratio.is_none_or(|r| aspect_ratio >= r)
+                && self.max_aspect_ratio.is_none_or(|r| aspect_ratio <= r);
+        }
+        true
+    }
+}
+
+/// Properties of media read from its file the first time a rule needs them
+struct Facts<'a> {
+    filepath: &'a Path,
+    exif: OnceCell<Option<BTreeMap<String, String>>>,
+    dimensions: OnceCell<Option<(u32, u32)>>,
+}
+
+impl<'a> Facts<'a> {
+    fn new(filepath: &'a Path) -> Self {
+        Self {
+            filepath,
+            exif: OnceCell::new(),
+            dimensions: OnceCell::new(),
+        }
+    }
+
+    fn exif(&self) -> Option<&BTreeMap<String, String>> {
+        self.exif
+            .get_or_init(|| {
+                let file = fs::File::open(self.filepath).ok()?;
+                let exif = exif::Reader::new()
+                    .read_from_container(&mut std::io::BufReader::new(file))
+                    .ok()?;
+                Some(
+                    exif.fields()
+                        .filter(|field| field.ifd_num == In::PRIMARY)
+                        .map(|field| (field.tag.to_string(), exif_field_to_string(field)))
+                        .collect(),
+                )
+            })
+            .as_ref()
+    }
+
+    fn dimensions(&self) -> Option<(u32, u32)> {
+        *self
+            .dimensions
+            .get_or_init(|| image::image_dimensions(self.filepath).ok())
+    }
+}
+
+/// Whether `path` matches the glob, see [`self`] for the syntax
+fn glob_match(glob: &str, path: &str) -> bool {
+    let glob = match glob.starts_with('/') {
+        true => glob.to_string(),
+        false => format!("**/{glob}"),
+    };
+    let glob: Vec<char> = glob.chars().collect();
+    let path: Vec<char> = path.chars().collect();
+    glob_match_chars(&glob, &path)
+}
+
+/// Match by tracking every position in the glob the path read so far can reach, which takes time
+/// proportional to the product of their lengths instead of backtracking
+fn glob_match_chars(glob: &[char], path: &[char]) -> bool {
+    let mut reached = vec![false; glob.len() + 1];
+    reached[0] = true;
+    skip_empty_stars(glob, &mut reached);
+    for &c in path {
+        let mut next = vec![false; glob.len() + 1];
+        for i in (0..glob.len()).filter(|&i| reached[i]) {
+            match glob[i..] {
+                ['*', '*', ..] => next[i] = true,
+                ['*', ..] | ['?', ..] if c == '/' => {}
+                ['*', ..] => next[i] = true,
+                ['?', ..] => next[i + 1] = true,
+                [g, ..] if g == c => next[i + 1] = true,
+                _ => {}
+            }
+        }
+        reached = next;
+        skip_empty_stars(glob, &mut reached);
+        if !reached.contains(&true) {
+            return false;
+        }
+    }
+    reached[glob.len()]
+}
+
+/// Also reach the positions after stars that are reached, as stars may match nothing
+fn skip_empty_stars(glob: &[char], reached: &mut [bool]) {
+    for i in 0..glob.len() {
+        if reached[i] {
+            match glob[i..] {
+                ['*', '*', ..] => reached[i + 2] = true,
+                ['*', ..] => reached[i + 1] = true,
+                _ => {}
+            }
+        }
+    }
+}
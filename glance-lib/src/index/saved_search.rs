@@ -0,0 +1,34 @@
+use serde::Serialize;
+
+use crate::store::saved_search_sql::SavedSearchSql;
+
+use super::{media::MediaFilter, Error};
+
+/// Named filter that is evaluated again every time it is used, like a smart album
+#[derive(Debug, Clone, Serialize)]
+pub struct SavedSearch {
+    pub name: String,
+    pub filter: MediaFilter,
+}
+
+impl TryFrom<SavedSearchSql> for SavedSearch {
+    type Error = Error;
+
+    fn try_from(value: SavedSearchSql) -> Result<Self, Self::Error> {
+        Ok(Self {
+            name: value.name,
+            filter: serde_json::from_str(&value.filter)?,
+        })
+    }
+}
+
+impl TryFrom<&SavedSearch> for SavedSearchSql {
+    type Error = Error;
+
+    fn try_from(value: &SavedSearch) -> Result<Self, Self::Error> {
+        Ok(Self {
+            name: value.name.clone(),
+            filter: serde_json::to_string(&value.filter)?,
+        })
+    }
+}
@@ -1,4 +1,10 @@
+// Older tests predate these lints and are left as they were written
+#![allow(clippy::get_first, clippy::vec_init_then_push)]
+
+use std::{collections::HashMap, path::PathBuf};
+
 use anyhow::{anyhow, Result};
+use chrono::{DateTime, NaiveDate, TimeDelta};
 use file_format::FileFormat;
 use glance_util::function;
 use insta::assert_yaml_snapshot;
@@ -6,10 +12,31 @@ use slog::o;
 use walkdir::WalkDir;
 
 use crate::{
-    index::{file_to_media_row, media::Device, AddDirectoryConfig, Index},
-    store::media_sql::MediaFilter,
+    index::{
+        dedupe::{quarantine_path, DuplicateAction, KeepPolicy, ResolveDuplicatesConfig},
+        event::EventConfig,
+        file_to_media_row,
+        media::{Device, Flag, LabelQuery, Media, MediaText, SortKey},
+        memories::{MemoriesConfig, MemoryYear},
+        people::{Rectangle, RegionSource},
+        place::{Place, TripConfig},
+        rules::RuleSet,
+        thumbnail::ThumbnailCache,
+        xmp, AddDirectoryConfig, Error, Index,
+    },
+    store::media_sql::{MediaFilter, MediaSql, XmpStateSql},
 };
 
+/// Empty directory for the files of a test
+fn test_dir(test: &str) -> Result<PathBuf> {
+    let directory = PathBuf::from("test-dbs").join(test);
+    if directory.exists() {
+        std::fs::remove_dir_all(&directory)?;
+    }
+    std::fs::create_dir_all(&directory)?;
+    Ok(directory)
+}
+
 #[test]
 fn file_to_media_row_test() -> Result<()> {
     let logger = slog::Logger::root(slog::Discard, o!());
@@ -21,8 +48,12 @@ fn file_to_media_row_test() -> Result<()> {
             metadata_fallback_for_created: false,
             calculate_nearest_city: false,
             use_exiftool: false,
+            perceptual_hash: false,
+            read_xmp: true,
+            rules: RuleSet::default(),
+            label_places: false,
         };
-        let media_row = file_to_media_row(&entry, None, &config, &logger)?
+        let media_row = file_to_media_row(&entry, None, &XmpStateSql::default(), &config, &logger)?
             .new_or_else(|| anyhow!("should be some"))?;
         assert_eq!(media_row.filepath, entry.path());
         assert_eq!(media_row.size, 7958.into());
@@ -48,6 +79,10 @@ fn add_directory_test() -> Result<()> {
         metadata_fallback_for_created: false,
         calculate_nearest_city: true,
         use_exiftool: false,
+        perceptual_hash: true,
+        read_xmp: true,
+        rules: RuleSet::default(),
+        label_places: false,
     };
     index.index("../test-media", &config)?;
     let mut data = index.get_media()?;
@@ -77,6 +112,35 @@ fn add_label_test() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn add_label_many_test() -> Result<()> {
+    let mut index = Index::new_for_test(function!())?;
+    let config = AddDirectoryConfig::default();
+    index.index("../test-media", &config)?;
+    let mut data = index.get_media()?;
+    data.sort_by(|a, b| a.filepath.cmp(&b.filepath));
+    let first = data
+        .first()
+        .ok_or_else(|| anyhow!("should have first element"))?;
+    index.add_label(first.filepath.clone(), "trip".to_string())?;
+
+    // Paths that already have the label are skipped rather than failing the batch
+    let added = index.add_label_many(data.iter().map(|m| &m.filepath), "trip".to_string())?;
+    assert_eq!(added, data.len() - 1);
+    let labeled = index.get_media_with_filter(MediaFilter {
+        label: Some("trip".into()),
+        ..Default::default()
+    })?;
+    assert_eq!(labeled.len(), data.len());
+
+    let deleted =
+        index.delete_label_many(data.iter().skip(1).map(|m| &m.filepath), "trip".to_string())?;
+    assert_eq!(deleted, data.len() - 1);
+    assert_eq!(index.get_labels(first.filepath.clone())?, vec!["trip"]);
+
+    Ok(())
+}
+
 #[test]
 fn get_media_with_label_filter_test() -> Result<()> {
     let mut index = Index::new_for_test(function!())?;
@@ -93,25 +157,403 @@ fn get_media_with_label_filter_test() -> Result<()> {
     index.add_label(first.filepath.clone(), "test".to_string())?;
 
     let labeled_first_media = index.get_media_with_filter(MediaFilter {
-        label: Some("test".to_string()),
+        label: Some("test".into()),
         ..Default::default()
     })?;
     assert_eq!(labeled_first_media.len(), 1);
 
     let labeled_all_media = index.get_media_with_filter(MediaFilter {
-        label: Some("all".to_string()),
+        label: Some("all".into()),
         ..Default::default()
     })?;
     assert_eq!(labeled_all_media.len(), data.len());
 
     let labeled_invalid_media = index.get_media_with_filter(MediaFilter {
-        label: Some("invalid".to_string()),
+        label: Some("invalid".into()),
         ..Default::default()
     })?;
     assert_eq!(labeled_invalid_media.len(), 0);
     Ok(())
 }
 
+#[test]
+fn label_query_test() -> Result<()> {
+    let mut index = Index::new_for_test(function!())?;
+    let config = AddDirectoryConfig::default();
+    index.index("../test-media", &config)?;
+    let mut data = index.get_media()?;
+    data.sort_by(|a, b| a.filepath.cmp(&b.filepath));
+    assert!(data.len() >= 3, "expected at least 3 test media");
+    index.add_label(data[0].filepath.clone(), "family".to_string())?;
+    index.add_label(data[0].filepath.clone(), "beach".to_string())?;
+    index.add_label(data[1].filepath.clone(), "family".to_string())?;
+    index.add_label(data[2].filepath.clone(), "beach".to_string())?;
+    index.add_label(data[2].filepath.clone(), "blurry".to_string())?;
+
+    let query = |label: LabelQuery| -> Result<Vec<_>> {
+        let mut paths: Vec<_> = index
+            .get_media_with_filter(MediaFilter {
+                label: Some(label),
+                ..Default::default()
+            })?
+            .into_iter()
+            .map(|m| m.filepath)
+            .collect();
+        paths.sort();
+        Ok(paths)
+    };
+
+    let all_of = query(LabelQuery::AllOf(vec!["family".into(), "beach".into()]))?;
+    assert_eq!(all_of, vec![data[0].filepath.clone()]);
+
+    let any_of = query(LabelQuery::AnyOf(vec!["family".into(), "blurry".into()]))?;
+    assert_eq!(any_of.len(), 3);
+
+    let none_of = query(LabelQuery::NoneOf(vec!["family".into(), "beach".into()]))?;
+    assert_eq!(none_of.len(), data.len() - 3);
+
+    // Beach photos that are not blurry
+    let nested = query(LabelQuery::AllOf(vec![
+        "beach".into(),
+        LabelQuery::NoneOf(vec!["blurry".into()]),
+    ]))?;
+    assert_eq!(nested, vec![data[0].filepath.clone()]);
+
+    let empty_any = query(LabelQuery::AnyOf(vec![]))?;
+    assert!(empty_any.is_empty());
+    let empty_all = query(LabelQuery::AllOf(vec![]))?;
+    assert_eq!(empty_all.len(), data.len());
+    Ok(())
+}
+
+#[test]
+fn search_test() -> Result<()> {
+    let mut index = Index::new_for_test(function!())?;
+    let config = AddDirectoryConfig::default();
+    index.index("../test-media", &config)?;
+    let data = index.get_media()?;
+    for media in &data {
+        index.add_label(media.filepath.clone(), "all".to_string())?;
+    }
+    let beach = index
+        .search("beach")?
+        .pop()
+        .ok_or_else(|| anyhow!("should find beach by path"))?;
+    index.add_label(beach.filepath.clone(), "blurry".to_string())?;
+
+    let canon = index.search(r#"device:"Canon EOS 40D" taken:2008..2008-05"#)?;
+    assert_eq!(canon.len(), 1);
+    assert!(index
+        .search("device:\"Canon EOS 40D\" taken:2009..")?
+        .is_empty());
+    assert_eq!(
+        index.search("label:all -label:blurry")?.len(),
+        data.len() - 1
+    );
+
+    let latitude = beach
+        .latitude
+        .ok_or_else(|| anyhow!("beach should have gps"))?;
+    let longitude = beach
+        .longitude
+        .ok_or_else(|| anyhow!("beach should have gps"))?;
+    let near = index.search(&format!("near:{latitude},{}", longitude + 0.01))?;
+    assert_eq!(near.len(), 1);
+    let far = index.search(&format!("near:{latitude},{},500m", longitude + 0.01))?;
+    assert!(far.is_empty());
+    insert_test_media(&index, "/near/fiji.jpg", |media| {
+        media.latitude = Some(-17.0);
+        media.longitude = Some(179.99);
+    })?;
+    let dateline = index.search("near:-17.0,-179.99,5km")?;
+    assert_eq!(
+        dateline.len(),
+        1,
+        "nearby searches should reach across the antimeridian"
+    );
+
+    assert_eq!(
+        index
+            .search("label:all unknown:value")
+            .unwrap_err()
+            .to_string(),
+        "query: unknown field 'unknown' at column 11"
+    );
+    assert_eq!(
+        index.search("device:\"Canon").unwrap_err().to_string(),
+        "query: unterminated quote starting at column 8"
+    );
+    assert_eq!(
+        index.search("-device:x").unwrap_err().to_string(),
+        "query: '-' can only exclude labels at column 1"
+    );
+    assert!(index.search("taken:2019-13").is_err());
+    assert!(index.search("rating:6").is_err());
+    Ok(())
+}
+
+#[test]
+fn search_text_test() -> Result<()> {
+    let mut index = Index::new_for_test(function!())?;
+    let config = AddDirectoryConfig {
+        calculate_nearest_city: true,
+        ..Default::default()
+    };
+    index.index("../test-media", &config)?;
+    let paths = |media: Vec<Media>| media.into_iter().map(|m| m.filepath).collect::<Vec<_>>();
+
+    // Location and device are searched by prefix
+    let santa = paths(index.search_text("santa barb", MediaFilter::default())?);
+    assert_eq!(santa.len(), 1);
+    assert!(santa[0].ends_with("beach.jpg"));
+    let beach = index
+        .get_media_by_path(&santa[0])?
+        .ok_or_else(|| anyhow!("should be indexed"))?;
+    assert_eq!(beach.country.as_deref(), Some("US"));
+    let nikon = paths(index.search_text("nik", MediaFilter::default())?);
+    assert_eq!(nikon.len(), 1);
+
+    // Labels are kept in sync as they are added and removed
+    let ferris = index
+        .search_text("ferris", MediaFilter::default())?
+        .into_iter()
+        .find(|m| m.format == "Portable Network Graphics")
+        .ok_or_else(|| anyhow!("should find ferris.png by path"))?;
+    assert!(index
+        .search_text("lisbon", MediaFilter::default())?
+        .is_empty());
+    index.add_label(ferris.filepath.clone(), "Lisbon trip".to_string())?;
+    assert_eq!(
+        paths(index.search_text("lisbon", MediaFilter::default())?),
+        vec![ferris.filepath.clone()]
+    );
+    index.delete_label(ferris.filepath.clone(), "Lisbon trip".to_string())?;
+    assert!(index
+        .search_text("lisbon", MediaFilter::default())?
+        .is_empty());
+
+    // Media are matched by filepath as rowids can change, for example when vacuuming
+    index
+        .connection
+        .execute("UPDATE media SET rowid = rowid + 1000", [])?;
+    index.add_label(ferris.filepath.clone(), "Porto trip".to_string())?;
+    assert_eq!(
+        paths(index.search_text("porto", MediaFilter::default())?),
+        vec![ferris.filepath.clone()]
+    );
+    assert_eq!(
+        index
+            .search_text("santa barb", MediaFilter::default())?
+            .len(),
+        1
+    );
+    index.delete_label(ferris.filepath.clone(), "Porto trip".to_string())?;
+
+    // Combined with structured filters
+    let ferris_jpg = index.search_text(
+        "ferris",
+        MediaFilter {
+            format: Some("Joint Photographic Experts Group".to_string()),
+            ..Default::default()
+        },
+    )?;
+    assert_eq!(ferris_jpg.len(), 1);
+
+    // Deindexed media are removed from the search table
+    index.deindex([&ferris.filepath])?;
+    assert_eq!(
+        index.search_text("ferris", MediaFilter::default())?.len(),
+        1
+    );
+
+    // Words without letters or digits are ignored rather than being FTS5 syntax errors
+    assert_eq!(
+        index.search_text("\" * -", MediaFilter::default())?.len(),
+        index.get_media()?.len()
+    );
+    Ok(())
+}
+
+#[test]
+fn sort_and_paginate_test() -> Result<()> {
+    let mut index = Index::new_for_test(function!())?;
+    let config = AddDirectoryConfig::default();
+    index.index("../test-media", &config)?;
+    let mut data = index.get_media()?;
+    data.sort_by(|a, b| b.size.0.cmp(&a.size.0).then(b.filepath.cmp(&a.filepath)));
+    let by_size = |offset, limit| MediaFilter {
+        sort: Some(SortKey::Size),
+        descending: true,
+        offset,
+        limit,
+        ..Default::default()
+    };
+
+    let page = index.get_media_with_filter(by_size(Some(1), Some(2)))?;
+    let paths = |media: &[Media]| media.iter().map(|m| m.filepath.clone()).collect::<Vec<_>>();
+    assert_eq!(paths(&page), paths(&data[1..3]));
+    let rest = index.get_media_with_filter(by_size(Some(3), None))?;
+    assert_eq!(paths(&rest), paths(&data[3..]));
+    assert_eq!(
+        index.count_media_with_filter(&by_size(Some(3), Some(1)))?,
+        data.len() as i64
+    );
+
+    let mut cursor = index.media_cursor(by_size(None, None))?;
+    let streamed = cursor.iter()?.collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(paths(&streamed), paths(&data));
+    Ok(())
+}
+
+#[test]
+fn saved_search_test() -> Result<()> {
+    let mut index = Index::new_for_test(function!())?;
+    let config = AddDirectoryConfig::default();
+    index.index("../test-media", &config)?;
+    let data = index.get_media()?;
+    index.save_search("favourites", "label:favourite -label:blurry".parse()?)?;
+    assert!(index.get_media_with_saved_search("favourites")?.is_empty());
+
+    // The search is evaluated again each time so new labels are included
+    index.add_label(data[0].filepath.clone(), "favourite".to_string())?;
+    index.add_label(data[1].filepath.clone(), "favourite".to_string())?;
+    index.add_label(data[1].filepath.clone(), "blurry".to_string())?;
+    let favourites = index.get_media_with_saved_search("favourites")?;
+    assert_eq!(favourites.len(), 1);
+    assert_eq!(favourites[0].filepath, data[0].filepath);
+
+    // Saving under an existing name replaces it
+    index.save_search("favourites", "label:blurry".parse()?)?;
+    index.save_search("canon", r#"device:"Canon EOS 40D""#.parse()?)?;
+    let names = index
+        .get_saved_searches()?
+        .into_iter()
+        .map(|saved_search| saved_search.name)
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec!["canon", "favourites"]);
+    assert_eq!(
+        index.get_media_with_saved_search("favourites")?[0].filepath,
+        data[1].filepath
+    );
+
+    assert!(index.delete_saved_search("favourites")?);
+    assert!(!index.delete_saved_search("favourites")?);
+    assert!(index.get_media_with_saved_search("favourites").is_err());
+    Ok(())
+}
+
+#[test]
+fn album_test() -> Result<()> {
+    let mut index = Index::new_for_test(function!())?;
+    let config = AddDirectoryConfig::default();
+    index.index("../test-media", &config)?;
+    let data = index.get_media()?;
+    index.create_album("trip", Some("summer"))?;
+    assert!(index.create_album("trip", None).is_err());
+
+    // Items keep the order they are added in rather than the order they were taken in
+    let paths = [&data[2].filepath, &data[0].filepath, &data[1].filepath];
+    assert_eq!(index.add_to_album("trip", paths, None)?, 3);
+    assert_eq!(index.add_to_album("trip", [&data[0].filepath], None)?, 0);
+    assert_eq!(index.add_to_album("trip", [&data[3].filepath], Some(1))?, 1);
+    let album_paths = |index: &Index| -> Result<Vec<_>> {
+        Ok(index
+            .get_album_media("trip")?
+            .into_iter()
+            .map(|media| media.filepath)
+            .collect())
+    };
+    let expected = [2, 3, 0, 1].map(|i| data[i].filepath.clone());
+    assert_eq!(album_paths(&index)?, expected);
+
+    index.move_in_album("trip", &data[1].filepath, 0)?;
+    index.remove_from_album("trip", [&data[3].filepath])?;
+    let expected = [1, 2, 0].map(|i| data[i].filepath.clone());
+    assert_eq!(album_paths(&index)?, expected);
+    assert_eq!(index.search("album:trip")?.len(), 3);
+
+    assert!(index
+        .set_album_cover("trip", Some(&data[3].filepath))
+        .is_err());
+    index.set_album_cover("trip", Some(&data[0].filepath))?;
+    let album = index.get_album("trip")?;
+    assert_eq!(album.cover.as_ref(), Some(&data[0].filepath));
+    assert_eq!(album.count, 3);
+
+    // Deindexed media leave the album
+    index.deindex([&data[0].filepath])?;
+    let album = index.get_album("trip")?;
+    assert_eq!(album.cover, None);
+    assert_eq!(album.count, 2);
+
+    index.delete_album("trip")?;
+    assert!(index.get_albums()?.is_empty());
+    assert!(index.get_album_media("trip").is_err());
+    Ok(())
+}
+
+#[test]
+fn hierarchical_label_test() -> Result<()> {
+    let mut index = Index::new_for_test(function!())?;
+    let config = AddDirectoryConfig::default();
+    index.index("../test-media", &config)?;
+    let data = index.get_media()?;
+    index.add_label(&data[0].filepath, " places/ portugal//lisbon/".to_string())?;
+    index.add_label(&data[1].filepath, "places/portugal".to_string())?;
+    index.add_label(&data[2].filepath, "places/portugal-north".to_string())?;
+    index.add_label(&data[2].filepath, "Grandma".to_string())?;
+    index.add_label(&data[3].filepath, "Nana".to_string())?;
+    index.add_label(&data[3].filepath, "Grandma Rose".to_string())?;
+    assert!(index
+        .add_label(&data[3].filepath, " / ".to_string())
+        .is_err());
+
+    // Filtering on a parent includes its children but not labels that only share a prefix
+    assert_eq!(index.search("label:places/portugal")?.len(), 2);
+    assert_eq!(index.search("label:places")?.len(), 3);
+    let tree = index.get_label_tree()?;
+    let names = tree
+        .iter()
+        .map(|node| node.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec!["Grandma", "Grandma Rose", "Nana", "places"]);
+    assert_eq!(
+        tree[3].children[0].children[0].label,
+        "places/portugal/lisbon"
+    );
+
+    // Renaming moves the descendants too
+    assert_eq!(index.rename_label("places/portugal", "trips/portugal")?, 2);
+    assert_eq!(
+        index.get_labels(&data[0].filepath)?,
+        vec!["trips/portugal/lisbon"]
+    );
+    assert!(index.rename_label("trips", "trips/old").is_err());
+
+    // Merging into a label media already have leaves a single copy
+    assert_eq!(index.merge_labels(&["Grandma", "Nana"], "Grandma Rose")?, 2);
+    assert_eq!(index.search("label:\"Grandma Rose\"")?.len(), 2);
+    assert_eq!(index.get_labels(&data[3].filepath)?, vec!["Grandma Rose"]);
+    index.add_label(&data[1].filepath, "Nana".to_string())?;
+    assert_eq!(
+        index.merge_labels(&["Nana", "Grandma Rose"], "Grandma Rose")?,
+        1
+    );
+    assert!(index.merge_labels(&["Nana", " "], "Grandma Rose").is_err());
+
+    // Labels are normalized when deleted like when added
+    index.add_label(&data[1].filepath, "Nana".to_string())?;
+    index.delete_label(&data[1].filepath, " Nana / ".to_string())?;
+    assert!(index.search("label:Nana")?.is_empty());
+
+    assert_eq!(index.delete_label_everywhere("places")?, 1);
+    assert_eq!(
+        index.get_all_labels()?,
+        vec!["Grandma Rose", "trips/portugal", "trips/portugal/lisbon"]
+    );
+    Ok(())
+}
+
 #[test]
 fn get_all_labels_test() -> Result<()> {
     let mut index = Index::new_for_test(function!())?;
@@ -134,3 +576,1141 @@ fn get_all_labels_test() -> Result<()> {
     assert_eq!(all_labels, expected);
     Ok(())
 }
+
+#[test]
+fn near_duplicates_test() -> Result<()> {
+    let mut index = Index::new_for_test(function!())?;
+    let config = AddDirectoryConfig {
+        perceptual_hash: true,
+        ..Default::default()
+    };
+
+    // None of the test media look alike
+    index.index("../test-media", &config)?;
+    assert!(index.near_duplicates(10)?.is_empty());
+
+    // Add a resized and re-encoded copy of an image
+    let copies = test_dir(function!())?;
+    let original = "../test-media/exif-images/Canon_40D.jpg";
+    let resized = copies.join("Canon_40D_small.png");
+    image::open(original)?.thumbnail(50, 50).save(&resized)?;
+    index.index(&copies, &config)?;
+
+    let near_duplicates = index.near_duplicates(10)?;
+    assert_eq!(near_duplicates.len(), 1);
+    let mut paths = near_duplicates[0]
+        .iter()
+        .map(|m| m.filepath.clone())
+        .collect::<Vec<_>>();
+    paths.sort();
+    assert_eq!(paths, vec![std::path::PathBuf::from(original), resized]);
+    Ok(())
+}
+
+#[test]
+fn resolve_duplicates_test() -> Result<()> {
+    let mut index = Index::new_for_test(function!())?;
+    let config = AddDirectoryConfig {
+        hash: true,
+        ..Default::default()
+    };
+
+    // Make two copies of an image
+    let directory = test_dir(function!())?;
+    let media_directory = directory.join("media");
+    let short = media_directory.join("Canon_40D.jpg");
+    let long = media_directory.join("nested").join("Canon_40D.jpg");
+    std::fs::create_dir_all(long.parent().ok_or_else(|| anyhow!("missing parent"))?)?;
+    std::fs::copy("../test-media/exif-images/Canon_40D.jpg", &short)?;
+    std::fs::copy("../test-media/exif-images/Canon_40D.jpg", &long)?;
+    index.index(&media_directory, &config)?;
+    index.add_label(&short, "short".to_string())?;
+    index.add_label(&long, "long".to_string())?;
+    assert_eq!(index.duplicate_groups()?.len(), 1);
+
+    // A dry run does not change anything
+    let resolutions = index.resolve_duplicates(&ResolveDuplicatesConfig {
+        keep: KeepPolicy::ShortestPath,
+        action: DuplicateAction::DryRun,
+        journal: None,
+    })?;
+    assert_eq!(resolutions.len(), 1);
+    assert_eq!(resolutions[0].kept, short);
+    assert_eq!(resolutions[0].labels_added, vec!["long"]);
+    assert!(long.exists());
+    assert_eq!(index.get_labels(&short)?, vec!["short"]);
+
+    // Quarantine the longer path and merge its labels
+    let quarantine = directory.join("quarantine");
+    let journal = directory.join("journal.jsonl");
+    index.resolve_duplicates(&ResolveDuplicatesConfig {
+        keep: KeepPolicy::ShortestPath,
+        action: DuplicateAction::Quarantine(quarantine.clone()),
+        journal: Some(journal.clone()),
+    })?;
+    assert!(!long.exists());
+    assert!(quarantine.join(&long).exists());
+    assert!(index.duplicate_groups()?.is_empty());
+    assert_eq!(index.get_labels(&short)?, vec!["long", "short"]);
+    assert!(index.get_labels(&long)?.is_empty());
+
+    // Revert the quarantine
+    index.revert_duplicate_resolutions(&journal)?;
+    assert!(long.exists());
+    assert!(!quarantine.join(&long).exists());
+    assert_eq!(index.duplicate_groups()?.len(), 1);
+    assert_eq!(index.get_labels(&short)?, vec!["short"]);
+    assert_eq!(index.get_labels(&long)?, vec!["long"]);
+
+    // Albums, events and face regions of a quarantined copy are restored with it
+    index.create_album("copies", None)?;
+    index.add_to_album("copies", [&long, &short], None)?;
+    let event = index.detect_events(&EventConfig::default())?[0].id;
+    let rectangle = Rectangle {
+        x: 0.25,
+        y: 0.25,
+        width: 0.5,
+        height: 0.5,
+    };
+    index.add_face_region(&long, Some("Ann"), rectangle)?;
+    let journal = directory.join("memberships.jsonl");
+    index.resolve_duplicates(&ResolveDuplicatesConfig {
+        keep: KeepPolicy::ShortestPath,
+        action: DuplicateAction::Quarantine(quarantine.clone()),
+        journal: Some(journal.clone()),
+    })?;
+    assert_eq!(index.get_album_media("copies")?.len(), 1);
+    assert_eq!(index.get_event(event)?.count, 1);
+    index.revert_duplicate_resolutions(&journal)?;
+    let album_paths = index
+        .get_album_media("copies")?
+        .into_iter()
+        .map(|m| m.filepath)
+        .collect::<Vec<_>>();
+    assert_eq!(album_paths, vec![long.clone(), short.clone()]);
+    assert_eq!(index.get_event(event)?.count, 2);
+    let regions = index.get_face_regions(&long)?;
+    assert_eq!(regions.len(), 1);
+    assert_eq!(regions[0].person.as_deref(), Some("Ann"));
+    assert_eq!(regions[0].rectangle, rectangle);
+
+    // Copies are journaled before they are moved, and reverting skips copies that were not moved
+    let blocked = directory.join("blocked");
+    std::fs::write(&blocked, "")?;
+    let blocked_journal = directory.join("blocked.jsonl");
+    index.resolve_duplicates(&ResolveDuplicatesConfig {
+        keep: KeepPolicy::ShortestPath,
+        action: DuplicateAction::Quarantine(blocked),
+        journal: Some(blocked_journal.clone()),
+    })?;
+    assert!(long.exists());
+    assert_eq!(
+        std::fs::read_to_string(&blocked_journal)?.lines().count(),
+        1
+    );
+    index.revert_duplicate_resolutions(&blocked_journal)?;
+    assert!(long.exists());
+    assert_eq!(index.get_labels(&short)?, vec!["short"]);
+    assert_eq!(index.get_labels(&long)?, vec!["long"]);
+
+    // Quarantine paths stay inside the quarantine and never collide
+    let parent = quarantine_path(&quarantine, std::path::Path::new("../photo.jpg"));
+    assert_eq!(parent, quarantine.join("parent").join("photo.jpg"));
+    std::fs::create_dir_all(quarantine.join("parent"))?;
+    std::fs::write(&parent, "")?;
+    assert_eq!(
+        quarantine_path(&quarantine, std::path::Path::new("parent/photo.jpg")),
+        quarantine.join("parent").join("photo-1.jpg")
+    );
+
+    // Hardlinked copies stay in the index
+    index.resolve_duplicates(&ResolveDuplicatesConfig {
+        keep: KeepPolicy::PreferredRoot(long.parent().unwrap_or(&long).to_path_buf()),
+        action: DuplicateAction::Hardlink,
+        journal: None,
+    })?;
+    assert!(same_file::is_same_file(&short, &long)?);
+    assert_eq!(index.duplicate_groups()?.len(), 1);
+    assert_eq!(index.get_labels(&long)?, vec!["long", "short"]);
+
+    // Keep a copy chosen by hand and delete the other
+    let resolution = index.resolve_duplicates_keeping(
+        &long,
+        &ResolveDuplicatesConfig {
+            keep: KeepPolicy::Oldest,
+            action: DuplicateAction::Delete,
+            journal: None,
+        },
+    )?;
+    assert_eq!(resolution.kept, long);
+    assert!(!short.exists());
+    assert!(index.duplicate_groups()?.is_empty());
+    assert!(index
+        .resolve_duplicates_keeping(
+            &long,
+            &ResolveDuplicatesConfig {
+                keep: KeepPolicy::Oldest,
+                action: DuplicateAction::Delete,
+                journal: None,
+            }
+        )
+        .is_err());
+    Ok(())
+}
+
+#[test]
+fn thumbnail_cache_test() -> Result<()> {
+    let directory = test_dir(function!())?;
+    let thumbnail_cache = ThumbnailCache::new(directory.join("thumbnails"));
+    let mut index = Index::new_for_test(function!())?.with_thumbnail_cache(thumbnail_cache.clone());
+    let config = AddDirectoryConfig {
+        hash: true,
+        ..Default::default()
+    };
+    index.index("../test-media", &config)?;
+    let data = index.get_media()?;
+    let beach = data
+        .iter()
+        .find(|m| m.filepath.ends_with("beach.jpg"))
+        .ok_or_else(|| anyhow!("missing beach.jpg"))?;
+
+    // Thumbnails are generated lazily and fit within the size
+    let path = thumbnail_cache.get(beach, 64)?;
+    assert_eq!(path, thumbnail_cache.path(beach, 64));
+    let (width, height) = image::image_dimensions(&path)?;
+    assert_eq!(width.max(height), 64);
+
+    // Prewarming generates every thumbnail
+    index
+        .prewarm_thumbnails_in_background(vec![32])?
+        .ok_or_else(|| anyhow!("should have a thumbnail cache"))?
+        .join()
+        .map_err(|_| anyhow!("prewarm panicked"))?;
+    assert!(data.iter().all(|m| thumbnail_cache.path(m, 32).exists()));
+
+    // Thumbnails are removed with their media
+    index.deindex([&beach.filepath])?;
+    assert!(!thumbnail_cache.path(beach, 64).exists());
+    assert!(!thumbnail_cache.path(beach, 32).exists());
+
+    // Cleaning removes thumbnails without media
+    let other = data
+        .iter()
+        .find(|m| m.filepath != beach.filepath)
+        .ok_or_else(|| anyhow!("should have other media"))?;
+    std::fs::copy(
+        thumbnail_cache.path(other, 32),
+        directory.join("thumbnails").join("32").join("orphan.jpg"),
+    )?;
+    // Thumbnails being generated by another process are not removed
+    let temporary = directory
+        .join("thumbnails")
+        .join("32")
+        .join("orphan.jpg.1-0");
+    std::fs::write(&temporary, b"")?;
+    assert_eq!(index.clean_thumbnails()?, 1);
+    assert_eq!(index.clean_thumbnails()?, 0);
+    assert!(temporary.exists());
+    Ok(())
+}
+
+#[test]
+fn rating_and_flag_test() -> Result<()> {
+    let mut index = Index::new_for_test(function!())?.with_write_xmp(true);
+    let directory = test_dir(function!())?;
+    let original = "../test-media/exif-images/Canon_40D.jpg";
+    let unrated = directory.join("unrated.jpg");
+    let rated = directory.join("rated.jpg");
+    let rejected = directory.join("rejected.jpg");
+    for path in [&unrated, &rated, &rejected] {
+        std::fs::copy(original, path)?;
+    }
+    std::fs::write(
+        directory.join("rated.jpg.xmp"),
+        "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><rdf:RDF \
+        xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\"><rdf:Description \
+        xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\"><xmp:Rating>4</xmp:Rating>\
+        </rdf:Description></rdf:RDF></x:xmpmeta>",
+    )?;
+    std::fs::write(
+        directory.join("rejected.xmp"),
+        "<rdf:Description xmp:Rating=\"-1\"/>",
+    )?;
+
+    // Ratings and flags are read from sidecars when indexing
+    index.index(&directory, &AddDirectoryConfig::default())?;
+    let get = |index: &Index, path| -> Result<_> {
+        let media = index
+            .get_media_by_path(path)?
+            .ok_or_else(|| anyhow!("should be indexed"))?;
+        Ok((media.rating, media.flag))
+    };
+    assert_eq!(get(&index, &unrated)?, (0, None));
+    assert_eq!(get(&index, &rated)?, (4, None));
+    assert_eq!(get(&index, &rejected)?, (0, Some(Flag::Reject)));
+
+    index.set_rating(&unrated, 5)?;
+    index.set_flag(&unrated, Some(Flag::Pick))?;
+    assert!(index.set_rating(&unrated, 6).is_err());
+    assert!(index.set_flag(directory.join("missing.jpg"), None).is_err());
+
+    let min_rating = index.get_media_with_filter(MediaFilter {
+        min_rating: Some(4),
+        ..Default::default()
+    })?;
+    assert_eq!(min_rating.len(), 2);
+    let picked = index.get_media_with_filter(MediaFilter {
+        flag: Some(Flag::Pick),
+        ..Default::default()
+    })?;
+    assert_eq!(picked.len(), 1);
+    assert_eq!(picked[0].filepath, unrated);
+    let stats = index.stats()?;
+    assert_eq!(stats.count_by_rating.get(&0), Some(&1));
+    assert_eq!(stats.count_by_rating.get(&4), Some(&1));
+    assert_eq!(stats.count_by_rating.get(&5), Some(&1));
+
+    // Changes are written back to the sidecars and read by a new index
+    index.set_flag(&rated, Some(Flag::Reject))?;
+    index.set_flag(&rejected, None)?;
+    index.set_rating(&rejected, 2)?;
+    let written = MediaSql::get_xmp_state(&index.connection, &rejected.as_path().into())?;
+    assert_eq!(written.modified, xmp::sidecar_modified(&rejected)?);
+    let mut reindexed = Index::new_for_test(&format!("{}_reindexed", function!()))?;
+    reindexed.index(&directory, &AddDirectoryConfig::default())?;
+    assert_eq!(get(&reindexed, &unrated)?, (5, None));
+    assert_eq!(get(&reindexed, &rated)?, (0, Some(Flag::Reject)));
+    assert_eq!(get(&reindexed, &rejected)?, (2, None));
+
+    // Sidecars edited by other tools are read again, keeping picks that xmp cannot store
+    let rewrite = |path: PathBuf, rating: i8| -> Result<()> {
+        std::fs::write(&path, format!("<rdf:Description xmp:Rating=\"{rating}\"/>"))?;
+        let modified = std::time::SystemTime::now() + std::time::Duration::from_secs(60);
+        std::fs::File::options()
+            .write(true)
+            .open(path)?
+            .set_modified(modified)?;
+        Ok(())
+    };
+    rewrite(directory.join("unrated.jpg.xmp"), 1)?;
+    rewrite(directory.join("rated.jpg.xmp"), 3)?;
+    index.index(&directory, &AddDirectoryConfig::default())?;
+    assert_eq!(get(&index, &unrated)?, (1, Some(Flag::Pick)));
+    assert_eq!(get(&index, &rated)?, (3, None));
+    assert_eq!(get(&index, &rejected)?, (2, None));
+    Ok(())
+}
+
+#[test]
+fn import_test() -> Result<()> {
+    let directory = test_dir(function!())?;
+    let source_path = directory.join("source.db");
+    Index::new(&source_path)?.index("../test-media/exif-images", &AddDirectoryConfig::default())?;
+    // Remove columns added by newer versions of glance
+    rusqlite::Connection::open(&source_path)?.execute_batch(
+        "ALTER TABLE media DROP COLUMN rating;
+        ALTER TABLE media DROP COLUMN xmp_modified;",
+    )?;
+    let source = std::fs::read(&source_path)?;
+
+    let destination = directory.join("imported");
+    std::fs::create_dir_all(&destination)?;
+    let mut index = Index::new_for_test(function!())?;
+    index.import(&source_path, &destination, false)?;
+    let imported = index.get_media()?;
+    assert_eq!(imported.len(), 3);
+    assert!(imported
+        .iter()
+        .all(|media| media.filepath.starts_with(&destination) && media.rating == 0));
+
+    // The index imported from is left as it was
+    assert_eq!(std::fs::read(&source_path)?, source);
+    Ok(())
+}
+
+#[test]
+fn media_text_test() -> Result<()> {
+    let mut index = Index::new_for_test(function!())?.with_write_xmp(true);
+    let directory = test_dir(function!())?;
+    let captioned = directory.join("captioned.jpg");
+    let rated = directory.join("rated.jpg");
+    for path in [&captioned, &rated] {
+        std::fs::copy("../test-media/exif-images/Canon_40D.jpg", path)?;
+    }
+    std::fs::write(
+        directory.join("captioned.jpg.xmp"),
+        "<x:xmpmeta><rdf:RDF><rdf:Description xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\
+        <dc:title><rdf:Alt><rdf:li xml:lang=\"x-default\">Lisbon</rdf:li></rdf:Alt></dc:title>\
+        </rdf:Description></rdf:RDF></x:xmpmeta>",
+    )?;
+    std::fs::write(
+        directory.join("rated.xmp"),
+        "<rdf:Description xmp:Rating=\"3\"/>",
+    )?;
+    index.index(&directory, &AddDirectoryConfig::default())?;
+    assert_eq!(
+        index.get_media_text(&captioned)?.title.as_deref(),
+        Some("Lisbon")
+    );
+
+    // Title and caption are written to the sidecars but notes stay in the index
+    let text = MediaText {
+        title: Some(" Tram 28 ".to_string()),
+        caption: Some("Grandma & <me> on the tram".to_string()),
+        notes: Some("Scan the ticket".to_string()),
+    };
+    index.set_media_text(&rated, &text)?;
+    index.set_media_text(&captioned, &MediaText::default())?;
+    assert!(index
+        .set_media_text(directory.join("missing.jpg"), &text)
+        .is_err());
+    let expected = MediaText {
+        title: Some("Tram 28".to_string()),
+        ..text
+    };
+    assert_eq!(index.get_media_text(&rated)?, expected);
+    assert_eq!(index.get_media_text(&captioned)?, MediaText::default());
+    let written = MediaSql::get_xmp_state(&index.connection, &rated.as_path().into())?;
+    assert_eq!(written.modified, xmp::sidecar_modified(&rated)?);
+
+    // Titles, captions and notes are searched
+    let found = |query: &str| -> Result<Vec<PathBuf>> {
+        Ok(index
+            .search_text(query, MediaFilter::default())?
+            .into_iter()
+            .map(|media| media.filepath)
+            .collect())
+    };
+    assert_eq!(found("grandma on the tram")?, vec![rated.clone()]);
+    assert_eq!(found("ticket")?, vec![rated.clone()]);
+    assert!(found("lisbon")?.is_empty());
+
+    let mut reindexed = Index::new_for_test(&format!("{}_reindexed", function!()))?;
+    reindexed.index(&directory, &AddDirectoryConfig::default())?;
+    let media = reindexed
+        .get_media_by_path(&rated)?
+        .ok_or_else(|| anyhow!("should be indexed"))?;
+    assert_eq!(media.rating, 3);
+    assert_eq!(media.title.as_deref(), Some("Tram 28"));
+    assert_eq!(media.caption.as_deref(), Some("Grandma & <me> on the tram"));
+    assert_eq!(media.notes, None);
+    assert_eq!(reindexed.get_media_text(&captioned)?, MediaText::default());
+    Ok(())
+}
+
+#[test]
+fn face_regions_test() -> Result<()> {
+    let mut index = Index::new_for_test(function!())?;
+    let directory = test_dir(function!())?;
+    let mwg = directory.join("mwg.jpg");
+    let microsoft = directory.join("microsoft.jpg");
+    let unknown = directory.join("unknown.jpg");
+    for path in [&mwg, &microsoft, &unknown] {
+        std::fs::copy("../test-media/exif-images/Canon_40D.jpg", path)?;
+    }
+    std::fs::write(
+        directory.join("mwg.jpg.xmp"),
+        "<rdf:Description><mwg-rs:Regions rdf:parseType=\"Resource\"><mwg-rs:RegionList>\
+        <rdf:Bag><rdf:li><rdf:Description mwg-rs:Name=\"Ana\" mwg-rs:Type=\"Face\">\
+        <mwg-rs:Extensions><rdf:Bag><rdf:li>smiling</rdf:li></rdf:Bag></mwg-rs:Extensions>\
+        <mwg-rs:Area stArea:x=\"0.25\" stArea:y=\"0.5\" stArea:w=\"0.2\" stArea:h=\"0.4\" \
+        stArea:unit=\"normalized\"/></rdf:Description></rdf:li>\
+        <rdf:li><rdf:Description mwg-rs:Name=\"Pet\" mwg-rs:Type=\"Pet\">\
+        <mwg-rs:Area stArea:x=\"0.5\" stArea:y=\"0.5\" stArea:w=\"0.1\" stArea:h=\"0.1\" \
+        stArea:unit=\"normalized\"/></rdf:Description></rdf:li>\
+        <rdf:li><rdf:Description mwg-rs:Type=\"Face\">\
+        <mwg-rs:Area stArea:x=\"0.95\" stArea:y=\"0.5\" stArea:w=\"0.2\" stArea:h=\"0.2\" \
+        stArea:unit=\"normalized\"/></rdf:Description></rdf:li>\
+        </rdf:Bag></mwg-rs:RegionList></mwg-rs:Regions></rdf:Description>",
+    )?;
+    std::fs::write(
+        directory.join("microsoft.xmp"),
+        "<rdf:Description><MP:RegionInfo><MPRI:Regions><rdf:Bag>\
+        <rdf:li MPReg:Rectangle=\"0.1, 0.2, 0.3, 0.4\" MPReg:PersonDisplayName=\"Ana\"/>\
+        <rdf:li MPReg:Rectangle=\"0.5, 0.5, 0.2, 0.2\" MPReg:PersonDisplayName=\"Bruno\"/>\
+        </rdf:Bag></MPRI:Regions></MP:RegionInfo></rdf:Description>",
+    )?;
+    index.index(&directory, &AddDirectoryConfig::default())?;
+
+    // Only faces are read and regions are converted to clipped rectangles from the top left
+    let regions = index.get_face_regions(&mwg)?;
+    assert_eq!(regions.len(), 2);
+    assert_eq!(regions[0].person.as_deref(), Some("Ana"));
+    let rounded = |rectangle: Rectangle| {
+        [rectangle.x, rectangle.y, rectangle.width, rectangle.height]
+            .map(|value| (value * 1000.0).round() / 1000.0)
+    };
+    assert_eq!(rounded(regions[0].rectangle), [0.15, 0.3, 0.2, 0.4]);
+    assert_eq!(regions[0].source, RegionSource::Metadata);
+    assert_eq!(regions[1].person, None);
+    assert_eq!(rounded(regions[1].rectangle), [0.85, 0.4, 0.15, 0.2]);
+    let regions = index.get_face_regions(&microsoft)?;
+    assert_eq!(regions.len(), 2);
+    assert_eq!(regions[1].person.as_deref(), Some("Bruno"));
+    assert!(index.get_face_regions(&unknown)?.is_empty());
+
+    let people: Vec<_> = index
+        .get_people()?
+        .into_iter()
+        .map(|person| (person.name, person.count))
+        .collect();
+    assert_eq!(
+        people,
+        vec![("Ana".to_string(), 2), ("Bruno".to_string(), 1)]
+    );
+    let with_people = |people: &[&str]| -> Result<usize> {
+        Ok(index
+            .get_media_with_filter(MediaFilter {
+                people: people.iter().map(|person| person.to_string()).collect(),
+                ..Default::default()
+            })?
+            .len())
+    };
+    assert_eq!(with_people(&["Ana"])?, 2);
+    assert_eq!(with_people(&["Ana", "Bruno"])?, 1);
+    assert_eq!(with_people(&["Carla"])?, 0);
+
+    // Regions can be added, renamed and deleted by hand
+    let rectangle = Rectangle {
+        x: 0.8,
+        y: 0.8,
+        width: 0.4,
+        height: 0.1,
+    };
+    let id = index.add_face_region(&unknown, Some(" Carla "), rectangle)?;
+    let added = &index.get_face_regions(&unknown)?[0];
+    assert_eq!(added.person.as_deref(), Some("Carla"));
+    assert_eq!(added.source, RegionSource::Manual);
+    assert_eq!(rounded(added.rectangle), [0.8, 0.8, 0.2, 0.1]);
+    assert_eq!(with_people(&["Carla"])?, 1);
+    assert!(index
+        .add_face_region(
+            &unknown,
+            None,
+            Rectangle {
+                x: 1.5,
+                ..rectangle
+            }
+        )
+        .is_err());
+    assert!(index
+        .add_face_region(directory.join("missing.jpg"), None, rectangle)
+        .is_err());
+
+    index.set_face_region(id, None, added.rectangle)?;
+    assert_eq!(index.get_face_regions(&unknown)?[0].person, None);
+    assert_eq!(with_people(&["Carla"])?, 0);
+    index.delete_face_region(id)?;
+    assert!(index.get_face_regions(&unknown)?.is_empty());
+    assert!(index.delete_face_region(id).is_err());
+    assert!(index.set_face_region(id, None, rectangle).is_err());
+
+    // Regions are read again when the sidecar changes, keeping those added by hand
+    index.add_face_region(&microsoft, Some("Carla"), rectangle)?;
+    let sidecar = directory.join("microsoft.xmp");
+    std::fs::write(
+        &sidecar,
+        "<rdf:Description><MP:RegionInfo><MPRI:Regions><rdf:Bag>\
+        <rdf:li MPReg:Rectangle=\"0.5, 0.5, 0.2, 0.2\" MPReg:PersonDisplayName=\"Bruno\"/>\
+        </rdf:Bag></MPRI:Regions></MP:RegionInfo></rdf:Description>",
+    )?;
+    std::fs::File::options()
+        .write(true)
+        .open(&sidecar)?
+        .set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(60))?;
+    index.index(&directory, &AddDirectoryConfig::default())?;
+    let people = |index: &Index, path| -> Result<Vec<Option<String>>> {
+        Ok(index
+            .get_face_regions(path)?
+            .into_iter()
+            .map(|region| region.person)
+            .collect())
+    };
+    assert_eq!(
+        people(&index, &microsoft)?,
+        vec![Some("Bruno".to_string()), Some("Carla".to_string())]
+    );
+
+    // Media indexed before regions were read have them added
+    index.connection.execute(
+        "DELETE FROM face_region WHERE filepath = ?1",
+        [mwg.to_string_lossy()],
+    )?;
+    index
+        .connection
+        .execute("UPDATE media SET regions_read = 0", [])?;
+    index.index(&directory, &AddDirectoryConfig::default())?;
+    assert_eq!(people(&index, &mwg)?, vec![Some("Ana".to_string()), None]);
+    assert_eq!(people(&index, &microsoft)?.len(), 2);
+
+    // Regions are kept when importing
+    let mut imported = Index::new_for_test(&format!("{}_imported", function!()))?;
+    let destination = directory.join("imported");
+    std::fs::create_dir_all(&destination)?;
+    let index_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("test-dbs")
+        .join(format!("{}.db", function!()));
+    imported.import(&index_path, &destination, false)?;
+    imported.index(&destination, &AddDirectoryConfig::default())?;
+    assert_eq!(
+        people(&imported, &destination.join("microsoft.jpg"))?,
+        vec![Some("Bruno".to_string()), Some("Carla".to_string())]
+    );
+    Ok(())
+}
+
+#[test]
+fn rules_test() -> Result<()> {
+    let mut index = Index::new_for_test(function!())?;
+    let directory = test_dir(function!())?;
+    let alice = directory.join("phones").join("alice");
+    std::fs::create_dir_all(&alice)?;
+    let canon = alice.join("canon.jpg");
+    let nikon = directory.join("nikon.jpg");
+    let pano = directory.join("pano.png");
+    std::fs::copy("../test-media/exif-images/Canon_40D.jpg", &canon)?;
+    std::fs::copy("../test-media/exif-images/Nikon_D70.jpg", &nikon)?;
+    image::RgbImage::new(300, 90).save(&pano)?;
+
+    let rules = RuleSet::from_toml(
+        r#"
+        [[rule]]
+        name = "canon"
+        device = "Canon EOS 40D"
+        labels = ["camera/canon", " canon "]
+
+        [[rule]]
+        path = "phones/alice/**"
+        labels = ["alice"]
+
+        [[rule]]
+        min_aspect_ratio = 3.0
+        labels = ["pano"]
+
+        [[rule]]
+        exif = { Make = "NIKON CORPORATION" }
+        labels = ["nikon"]
+        "#,
+    )?;
+    assert!(RuleSet::from_toml("[[rule]]\nlabels = [\"a\"]\ncolour = \"red\"").is_err());
+    assert!(RuleSet::from_toml("[[rule]]\nlabels = [\"/\"]").is_err());
+    assert!(RuleSet::from_toml("[[rules]]\nlabels = [\"a\"]").is_err());
+
+    // Rules only label media as they are added to the index
+    index.index(&nikon, &AddDirectoryConfig::default())?;
+    let config = AddDirectoryConfig {
+        rules: rules.clone(),
+        ..Default::default()
+    };
+    index.index(&directory, &config)?;
+    let labels = |index: &Index, path| -> Result<Vec<String>> {
+        let mut labels = index.get_labels(path)?;
+        labels.sort();
+        Ok(labels)
+    };
+    assert_eq!(
+        labels(&index, &canon)?,
+        vec!["alice", "camera/canon", "canon"]
+    );
+    assert_eq!(labels(&index, &pano)?, vec!["pano"]);
+    assert!(labels(&index, &nikon)?.is_empty());
+
+    // Backfilling reports the labels media are missing and only adds them outside dry runs
+    let report = index.apply_rules(&rules, true)?;
+    assert_eq!(report.media_checked, 3);
+    assert_eq!(report.added.len(), 1);
+    assert_eq!(report.added[0].filepath, nikon);
+    assert_eq!(report.added[0].rule, "#4");
+    assert!(labels(&index, &nikon)?.is_empty());
+    let report = index.apply_rules(&rules, false)?;
+    assert_eq!(report.added.len(), 1);
+    assert_eq!(labels(&index, &nikon)?, vec!["nikon"]);
+    assert!(index.apply_rules(&rules, false)?.added.is_empty());
+
+    // Globs that would take exponential time to match by backtracking are matched quickly
+    let many_stars = RuleSet::from_toml(
+        "[[rule]]\npath = \"**a**a**a**a**a**a**a**a**a**a**a**a**z\"\nlabels = [\"z\"]",
+    )?;
+    assert!(index.apply_rules(&many_stars, true)?.added.is_empty());
+    Ok(())
+}
+
+/// Insert a one byte jpg that only exists in the index, with fields changed by `update`
+fn insert_test_media(
+    index: &Index,
+    path: &str,
+    update: impl FnOnce(&mut Media),
+) -> Result<PathBuf> {
+    let mut media = Media {
+        filepath: PathBuf::from(path),
+        size: 1.into(),
+        format: "jpg".to_string(),
+        created: None,
+        modified: DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")?.to_utc(),
+        location: None,
+        country: None,
+        device: None,
+        hash: None,
+        perceptual_hash: None,
+        rating: 0,
+        flag: None,
+        latitude: None,
+        longitude: None,
+        title: None,
+        caption: None,
+        notes: None,
+    };
+    update(&mut media);
+    let filepath = media.filepath.clone();
+    MediaSql::from(media).insert(&index.connection)?;
+    Ok(filepath)
+}
+
+#[test]
+fn events_test() -> Result<()> {
+    let mut index = Index::new_for_test(function!())?;
+    let start = DateTime::parse_from_rfc3339("2024-06-01T09:00:00Z")?.to_utc();
+    let lisbon = (38.72, -9.14);
+    let porto = (41.15, -8.61);
+    let add = |index: &Index, name: &str, hours: i64, place: Option<(f64, f64)>| {
+        insert_test_media(index, &format!("/events/{name}.jpg"), |media| {
+            media.created = Some(start + TimeDelta::hours(hours));
+            media.location = place.map(|(latitude, _)| format!("city at {latitude}"));
+            media.latitude = place.map(|(latitude, _)| latitude);
+            media.longitude = place.map(|(_, longitude)| longitude);
+        })
+    };
+    // Morning in Lisbon then an afternoon drive to Porto, and a party days later
+    let breakfast = add(&index, "breakfast", 0, Some(lisbon))?;
+    add(&index, "tram", 1, Some(lisbon))?;
+    add(&index, "lunch", 3, None)?;
+    let porto_dinner = add(&index, "porto_dinner", 5, Some(porto))?;
+    add(&index, "party", 72, None)?;
+    let cake = add(&index, "cake", 73, None)?;
+
+    let events = index.detect_events(&EventConfig::default())?;
+    let summary: Vec<_> = events
+        .iter()
+        .map(|event| (event.name.as_str(), event.count, event.place.as_deref()))
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            ("2024-06-01 city at 38.72", 3, Some("city at 38.72")),
+            ("2024-06-01 city at 41.15", 1, Some("city at 41.15")),
+            ("2024-06-04", 2, None),
+        ]
+    );
+    assert_eq!(events[0].start, start);
+    assert_eq!(events[0].end, start + TimeDelta::hours(3));
+    assert!(index.detect_events(&EventConfig::default())?.is_empty());
+
+    let party = events[2].id;
+    let in_event = |index: &Index, event| -> Result<usize> {
+        Ok(index.search(&format!("event:{event}"))?.len())
+    };
+    assert_eq!(in_event(&index, party)?, 2);
+    assert_eq!(index.get_event_media(party)?[1].filepath, cake);
+
+    // Edits are kept when detecting again
+    index.rename_event(party, " Sam's birthday ")?;
+    let trip = index.merge_events(events[0].id, &[events[1].id])?;
+    assert_eq!(trip.count, 4);
+    assert_eq!(trip.end, start + TimeDelta::hours(5));
+    assert!(index.get_event(events[1].id).is_err());
+    let dinner = index.split_event(trip.id, &porto_dinner)?;
+    assert_eq!(dinner.count, 1);
+    assert_eq!(index.get_event(trip.id)?.count, 3);
+    assert!(index.split_event(trip.id, &breakfast).is_err());
+    assert!(index.split_event(trip.id, &cake).is_err());
+    assert!(index.detect_events(&EventConfig::default())?.is_empty());
+    assert!(matches!(
+        index.rename_event(party, "  "),
+        Err(Error::InvalidEventName(_))
+    ));
+
+    // New media close to an existing event are added to it
+    add(&index, "candles", 74, None)?;
+    add(&index, "porto_night", 7, Some(porto))?;
+    let hike_path = add(&index, "hike", 200, None)?;
+    let detected: Vec<_> = index
+        .detect_events(&EventConfig::default())?
+        .into_iter()
+        .map(|event| (event.id, event.name, event.count))
+        .collect();
+    let hike = index
+        .get_events()?
+        .last()
+        .map(|event| event.id)
+        .unwrap_or_default();
+    assert_eq!(
+        detected,
+        vec![
+            (dinner.id, "2024-06-01 city at 41.15".to_string(), 2),
+            (party, "Sam's birthday".to_string(), 3),
+            (hike, "2024-06-09".to_string(), 1),
+        ]
+    );
+    assert_eq!(index.get_event(party)?.end, start + TimeDelta::hours(74));
+    let names: Vec<_> = index
+        .get_events()?
+        .into_iter()
+        .map(|event| event.name)
+        .collect();
+    assert_eq!(
+        names,
+        vec![
+            "2024-06-01 city at 38.72",
+            "2024-06-01 city at 41.15",
+            "Sam's birthday",
+            "2024-06-09"
+        ]
+    );
+
+    // Deleted media leave their event, which is summarized again or removed when left empty
+    index.deindex([&porto_dinner])?;
+    let dinner = index.get_event(dinner.id)?;
+    assert_eq!(
+        (dinner.count, dinner.start),
+        (1, start + TimeDelta::hours(7))
+    );
+    index.deindex([&hike_path])?;
+    assert!(index.get_event(hike).is_err());
+    assert_eq!(index.get_event(party)?.count, 3);
+    index.clear_events()?;
+    assert!(index.get_events()?.is_empty());
+    assert_eq!(index.detect_events(&EventConfig::default())?.len(), 3);
+    Ok(())
+}
+
+#[test]
+fn places_test() -> Result<()> {
+    let mut index = Index::new_for_test(function!())?;
+    let beach_path = std::path::Path::new("../test-media/exif-images/beach.jpg");
+    let mut scratch = Index::new_for_test(&format!("{}_scratch", function!()))?;
+    scratch.index(beach_path, &AddDirectoryConfig::default())?;
+    let beach = scratch
+        .get_media_by_path(beach_path)?
+        .ok_or_else(|| anyhow!("should be indexed"))?;
+    let latitude = beach
+        .latitude
+        .ok_or_else(|| anyhow!("beach should have gps"))?;
+    let longitude = beach
+        .longitude
+        .ok_or_else(|| anyhow!("beach should have gps"))?;
+
+    let place = |name: &str, latitude, longitude, radius_km| Place {
+        name: name.to_string(),
+        latitude,
+        longitude,
+        radius_km,
+    };
+    index.set_place(place(" Beach ", latitude, longitude + 0.01, 1.0))?;
+    index.set_place(place("Home", 48.14, 11.58, 2.0))?;
+    assert!(index.set_place(place("Nowhere", 91.0, 0.0, 1.0)).is_err());
+    assert!(index.set_place(place("Nowhere", 0.0, 0.0, 0.0)).is_err());
+    let names: Vec<_> = index.get_places()?.into_iter().map(|p| p.name).collect();
+    assert_eq!(names, vec!["Beach", "Home"]);
+
+    // New media are labeled with their places when indexing
+    let config = AddDirectoryConfig {
+        label_places: true,
+        ..Default::default()
+    };
+    index.index("../test-media/exif-images", &config)?;
+    assert_eq!(index.get_labels(beach_path)?, vec!["places/Beach"]);
+    assert_eq!(index.search("label:places")?.len(), 1);
+    assert_eq!(index.search("place:Beach")?.len(), 1);
+    assert!(index.search("place:Home")?.is_empty());
+
+    // Existing media are labeled when places change
+    index.set_place(place("Beach", latitude, longitude + 0.01, 0.5))?;
+    assert!(index.search("place:Beach")?.is_empty());
+    index.set_place(place("Wide beach", latitude, longitude, 5.0))?;
+    assert_eq!(index.label_places()?, 1);
+    assert_eq!(index.label_places()?, 0);
+    assert_eq!(index.search("label:\"places/Wide beach\"")?.len(), 1);
+    index.delete_place("Wide beach")?;
+    assert!(index.delete_place("Wide beach").is_err());
+
+    // Trips are the periods between leaving home and coming back
+    let start = DateTime::parse_from_rfc3339("2024-06-01T09:00:00Z")?.to_utc();
+    let add = |name: &str, days: i64, point: Option<(f64, f64)>| {
+        insert_test_media(&index, &format!("/trips/{name}.jpg"), |media| {
+            media.created = Some(start + TimeDelta::days(days));
+            media.location = point.map(|_| name.trim_end_matches("_again").to_string());
+            media.latitude = point.map(|(latitude, _)| latitude);
+            media.longitude = point.map(|(_, longitude)| longitude);
+        })
+    };
+    let home = Some((48.14, 11.58));
+    let vienna = Some((48.21, 16.37));
+    add("leaving", 0, home)?;
+    add("vienna", 1, vienna)?;
+    add("unknown", 2, None)?;
+    add("vienna_again", 3, vienna)?;
+    add("back", 4, home)?;
+    add("lisbon", 30, Some((38.72, -9.14)))?;
+    let trips = index.get_trips(&TripConfig::default())?;
+    let summary: Vec<_> = trips
+        .iter()
+        .map(|trip| (trip.start, trip.end, trip.place.as_deref(), trip.count))
+        .collect();
+    let beach_created = beach
+        .created
+        .ok_or_else(|| anyhow!("beach should have created"))?;
+    assert_eq!(
+        summary,
+        vec![
+            (beach_created, beach_created, None, 1),
+            (
+                start + TimeDelta::days(1),
+                start + TimeDelta::days(3),
+                Some("vienna"),
+                2
+            ),
+            (
+                start + TimeDelta::days(30),
+                start + TimeDelta::days(30),
+                Some("lisbon"),
+                1
+            ),
+        ]
+    );
+    assert!(index
+        .get_trips(&TripConfig {
+            home: "Office".to_string(),
+            ..Default::default()
+        })
+        .is_err());
+
+    // Places reach across the antimeridian
+    add("fiji", 60, Some((-17.0, 179.99)))?;
+    index.set_place(place("Dateline", -17.0, -179.99, 5.0))?;
+    assert_eq!(index.search("place:Dateline")?.len(), 1);
+    assert!(place("Dateline", -17.0, -179.99, 5.0).contains(-17.0, 179.99));
+    Ok(())
+}
+
+#[test]
+fn memories_test() -> Result<()> {
+    let index = Index::new_for_test(function!())?;
+    let add = |name: &str, created: &str| -> Result<()> {
+        let created = DateTime::parse_from_rfc3339(created)?.to_utc();
+        insert_test_media(&index, &format!("/memories/{name}.jpg"), |media| {
+            media.created = Some(created);
+        })?;
+        Ok(())
+    };
+    add("new_year_2020", "2020-01-01T10:00:00Z")?;
+    add("new_years_eve_2020", "2020-12-31T23:00:00Z")?;
+    add("new_year_2022", "2022-01-02T10:00:00Z")?;
+    add("new_years_eve_2023", "2023-12-30T10:00:00Z")?;
+    add("february_2022", "2022-02-01T10:00:00Z")?;
+    add("new_year_2024", "2024-01-01T08:00:00Z")?;
+
+    let names = |memories: &[MemoryYear]| -> Vec<(i32, i32, Vec<String>)> {
+        memories
+            .iter()
+            .map(|year| {
+                let names = year
+                    .media
+                    .iter()
+                    .map(|media| media.filepath.file_stem().unwrap().to_string_lossy().into())
+                    .collect();
+                (year.year, year.years_ago, names)
+            })
+            .collect()
+    };
+    // The window wraps around the new year and media from this window are not memories
+    let date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+    let memories = index.get_memories(date, &MemoriesConfig { window_days: 2 })?;
+    assert_eq!(
+        names(&memories),
+        vec![
+            (2022, 2, vec!["new_year_2022".to_string()]),
+            (2021, 3, vec!["new_years_eve_2020".to_string()]),
+            (2020, 4, vec!["new_year_2020".to_string()]),
+        ]
+    );
+    let memories = index.get_memories(date, &MemoriesConfig { window_days: 0 })?;
+    assert_eq!(
+        names(&memories),
+        vec![(2020, 4, vec!["new_year_2020".to_string()])]
+    );
+
+    // Memories can be searched for by day of the year
+    assert_eq!(index.search("on:01-01")?.len(), 2);
+    assert_eq!(index.search("on:12-30..01-01")?.len(), 4);
+    assert_eq!(index.search("on:01-02..02-01")?.len(), 2);
+    assert!(index.search("on:02-30").is_err());
+    assert!(index.search("on:01-01 on:01-02").is_err());
+    Ok(())
+}
+
+#[test]
+fn stats_test() -> Result<()> {
+    let index = Index::new_for_test(function!())?;
+    let add = |name: &str,
+               created: Option<&str>,
+               size: u64,
+               location: Option<&str>,
+               hash: Option<&str>|
+     -> Result<()> {
+        let created = created
+            .map(|created| DateTime::parse_from_rfc3339(created).map(|c| c.to_utc()))
+            .transpose()?;
+        insert_test_media(&index, &format!("/stats/{name}"), |media| {
+            media.size = size.into();
+            media.format = name.rsplit('.').next().unwrap_or_default().to_string();
+            media.created = created;
+            media.location = location.map(str::to_string);
+            media.country = location
+                .filter(|l| l.contains(", "))
+                .map(|_| "PT".to_string());
+            media.hash = hash.map(|hash| blake3::hash(hash.as_bytes()));
+        })?;
+        Ok(())
+    };
+    // Sunday 2023-07-02 at 14:00 and Monday 2024-07-01 at 09:00
+    add(
+        "a.jpg",
+        Some("2023-07-02T14:00:00Z"),
+        100,
+        Some("Lisbon, Lisbon"),
+        Some("a"),
+    )?;
+    add(
+        "a_copy.jpg",
+        Some("2023-07-02T14:00:00Z"),
+        100,
+        Some("Lisbon, Lisbon"),
+        Some("a"),
+    )?;
+    add(
+        "a_copy_2.jpg",
+        Some("2023-07-02T14:00:00Z"),
+        100,
+        None,
+        Some("a"),
+    )?;
+    add(
+        "b.png",
+        Some("2024-07-01T09:00:00Z"),
+        50,
+        Some("Porto, Porto"),
+        Some("b"),
+    )?;
+    add("c.mp4", None, 1000, None, None)?;
+    index.add_label("/stats/a.jpg", "trips/portugal".to_string())?;
+    index.add_label("/stats/b.png", "trips/portugal".to_string())?;
+    index.add_label("/stats/b.png", "favorites".to_string())?;
+
+    let stats = index.stats()?;
+    assert_eq!(stats.count, 5);
+    assert_eq!(stats.bytes, 1350);
+    assert_eq!(
+        stats.bytes_by_format.get(&Some("jpg".to_string())),
+        Some(&300)
+    );
+    assert_eq!(
+        stats.bytes_by_format.get(&Some("mp4".to_string())),
+        Some(&1000)
+    );
+    assert_eq!(stats.count_by_year.get(&Some("2023".to_string())), Some(&3));
+    assert_eq!(stats.count_by_month.get(&7), Some(&4));
+    assert_eq!(stats.count_by_weekday.get(&0), Some(&3));
+    assert_eq!(stats.count_by_weekday.get(&1), Some(&1));
+    assert_eq!(stats.count_by_hour.get(&14), Some(&3));
+    assert_eq!(stats.count_by_hour.get(&9), Some(&1));
+    assert_eq!(stats.count_by_location.get(&None), Some(&2));
+    assert_eq!(
+        stats.count_by_region.get(&Some("Lisbon".to_string())),
+        Some(&2)
+    );
+    assert_eq!(
+        stats.count_by_country.get(&Some("PT".to_string())),
+        Some(&3)
+    );
+    assert_eq!(stats.count_by_country.get(&None), Some(&2));
+    assert_eq!(stats.count_by_label.get("trips/portugal"), Some(&2));
+    assert_eq!(stats.count_by_label.get("favorites"), Some(&1));
+    assert_eq!(stats.duplicates, 3);
+    assert_eq!(stats.duplicate_bytes, 200);
+    assert_eq!(stats.missing_created, 1);
+    assert_eq!(stats.missing_location, 2);
+    assert_eq!(stats.missing_hash, 1);
+
+    // The timeline counts media by the month they were created in
+    let july = |year| NaiveDate::from_ymd_opt(year, 7, 1).unwrap();
+    let months: Vec<_> = index
+        .count_by_created_month(&MediaFilter::default())?
+        .into_iter()
+        .collect();
+    assert_eq!(months, vec![(july(2023), 3), (july(2024), 1)]);
+    let (first, last) = index
+        .created_range(&MediaFilter::default())?
+        .ok_or_else(|| anyhow!("media should have created"))?;
+    assert_eq!(first, DateTime::parse_from_rfc3339("2023-07-02T14:00:00Z")?);
+    assert_eq!(last, DateTime::parse_from_rfc3339("2024-07-01T09:00:00Z")?);
+    assert!(index.created_range(&"format:mp4".parse()?)?.is_none());
+
+    // Filtered stats only count the media matching the filter
+    let stats = index.stats_with_filter(&"lisbon".parse()?)?;
+    assert_eq!(stats.count, 2);
+    assert_eq!(stats.duplicates, 2);
+    assert_eq!(stats.duplicate_bytes, 100);
+    assert_eq!(stats.count_by_label.get("trips/portugal"), Some(&1));
+    assert_eq!(stats.count_by_label.get("favorites"), None);
+    let stats = index.stats_with_filter(&"format:png".parse()?)?;
+    assert_eq!(stats.count, 1);
+    assert_eq!(stats.bytes, 50);
+    assert_eq!(stats.duplicates, 0);
+    assert_eq!(stats.duplicate_bytes, 0);
+
+    // Locations without a region and media without a country are counted as unknown
+    add("d.jpg", None, 10, Some("Atlantis"), None)?;
+    let stats = index.stats_with_filter(&"atlantis".parse()?)?;
+    assert_eq!(stats.count_by_region, HashMap::from([(None, 1)]));
+    assert_eq!(stats.count_by_country, HashMap::from([(None, 1)]));
+    Ok(())
+}
+
+#[test]
+fn within_test() -> Result<()> {
+    let index = Index::new_for_test(function!())?;
+    let created = DateTime::parse_from_rfc3339("2024-06-01T09:00:00Z")?.to_utc();
+    let add = |name: &str, point: Option<(f64, f64)>| {
+        insert_test_media(&index, &format!("/within/{name}.jpg"), |media| {
+            media.created = Some(created);
+            media.latitude = point.map(|(latitude, _)| latitude);
+            media.longitude = point.map(|(_, longitude)| longitude);
+        })
+    };
+    add("munich", Some((48.14, 11.58)))?;
+    add("vienna", Some((48.21, 16.37)))?;
+    add("lisbon", Some((38.72, -9.14)))?;
+    add("fiji", Some((-17.7, 178.0)))?;
+    add("samoa", Some((-13.8, -172.1)))?;
+    add("unknown", None)?;
+
+    let names = |query: &str| -> Result<Vec<String>> {
+        let mut names: Vec<String> = index
+            .search(query)?
+            .into_iter()
+            .map(|media| media.filepath.file_stem().unwrap().to_string_lossy().into())
+            .collect();
+        names.sort();
+        Ok(names)
+    };
+    assert_eq!(names("within:45,10,50,20")?, vec!["munich", "vienna"]);
+    // The rectangle crosses the antimeridian when west is more than east
+    assert_eq!(names("within:-20,170,-10,-170")?, vec!["fiji", "samoa"]);
+    assert!(index.search("within:50,10,45,20").is_err());
+    assert!(index.search("within:45,10,50").is_err());
+
+    assert_eq!(index.get_coordinates(&MediaFilter::default())?.len(), 5);
+    let coordinates = index.get_coordinates(&"within:30,-20,40,0".parse()?)?;
+    assert_eq!(coordinates, vec![(38.72, -9.14)]);
+    Ok(())
+}
@@ -0,0 +1,230 @@
+//! A cache of downscaled copies of media for quickly drawing previews
+//!
+//! Thumbnails are stored as jpgs at `<directory>/<size>/<key prefix>/<key>.jpg`. The key is the
+//! media's content hash when it was indexed with hashing enabled so copies of a file share
+//! thumbnails. Otherwise the key is derived from the path and modified time so an edited file
+//! gets a new thumbnail.
+
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use image::{io::Reader, ImageFormat};
+use slog::{error, info, trace, Logger};
+use walkdir::WalkDir;
+
+use super::{media::Media, Error};
+
+/// Default longest edge in pixels of generated thumbnails
+pub const DEFAULT_SIZE: u32 = 256;
+
+const EXTENSION: &str = "jpg";
+
+/// Used to give each in progress thumbnail a unique temporary name
+static TEMPORARY_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Age after which a temporary thumbnail is assumed to be left over from a generator that died
+const STALE_TEMPORARY_AGE: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone)]
+pub struct ThumbnailCache {
+    directory: PathBuf,
+}
+
+impl ThumbnailCache {
+    pub fn new<P: AsRef<Path>>(directory: P) -> Self {
+        Self {
+            directory: directory.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Path the thumbnail of `media` is stored at whether or not it has been generated
+    pub fn path(&self, media: &Media, size: u32) -> PathBuf {
+        let key = key(media);
+        let mut path = self
+            .directory
+            .join(size.to_string())
+            .join(&key[..2])
+            .join(key);
+        path.set_extension(EXTENSION);
+        path
+    }
+
+    /// Get the path to the thumbnail of `media`, generating it if it does not exist
+    ///
+    /// The thumbnail fits within a `size` by `size` square while keeping the aspect ratio.
+    pub fn get(&self, media: &Media, size: u32) -> Result<PathBuf, Error> {
+        let path = self.path(media, size);
+        if !path.exists() {
+            generate(&media.filepath, &path, size)?;
+        }
+        Ok(path)
+    }
+
+    /// Generate any missing thumbnails for `media_vec` at each of `sizes`
+    pub fn prewarm<'a, I>(&self, media_vec: I, sizes: &[u32], logger: &Logger)
+    where
+        I: IntoIterator<Item = &'a Media>,
+    {
+        info!(logger, "prewarming thumbnails"; "sizes" => format!("{sizes:?}"));
+        let mut generated = 0u64;
+        let mut existing = 0u64;
+        let mut failed = 0u64;
+        for media in media_vec {
+            for size in sizes {
+                let path = self.path(media, *size);
+                if path.exists() {
+                    existing += 1;
+                    continue;
+                }
+                match generate(&media.filepath, &path, *size) {
+                    Ok(()) => {
+                        trace!(logger, "generated thumbnail"; "path" => media.filepath.display());
+                        generated += 1;
+                    }
+                    Err(e) => {
+                        error!(logger, "failed to generate thumbnail";
+                            "path" => media.filepath.display(),
+                            "error" => %e,
+                        );
+                        failed += 1;
+                    }
+                }
+            }
+        }
+        info!(logger, "prewarmed thumbnails";
+            "generated" => generated,
+            "existing" => existing,
+            "failed" => failed,
+        );
+    }
+
+    /// Run `prewarm` on a background thread
+    pub fn prewarm_in_background(
+        &self,
+        media_vec: Vec<Media>,
+        sizes: Vec<u32>,
+        logger: Logger,
+    ) -> JoinHandle<()> {
+        let cache = self.clone();
+        thread::spawn(move || cache.prewarm(&media_vec, &sizes, &logger))
+    }
+
+    /// Remove every size of the thumbnail for `media`
+    pub fn remove(&self, media: &Media) -> Result<(), Error> {
+        for size in self.sizes()? {
+            let path = self.path(media, size);
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove thumbnails that do not belong to any of `media_vec`
+    ///
+    /// Temporary files of thumbnails being generated are kept unless they are stale, so cleaning
+    /// is safe while another process prewarms the cache. Returns the number of files removed.
+    pub fn retain<'a, I>(&self, media_vec: I) -> Result<u64, Error>
+    where
+        I: IntoIterator<Item = &'a Media>,
+    {
+        let keys = media_vec.into_iter().map(key).collect::<HashSet<_>>();
+        let mut removed = 0u64;
+        if !self.directory.exists() {
+            return Ok(removed);
+        }
+        for entry in WalkDir::new(&self.directory) {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let path = entry.path();
+            let unused = if path.extension().is_some_and(|e| e == EXTENSION) {
+                !path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .is_some_and(|stem| keys.contains(stem))
+            } else if is_temporary(path) {
+                entry
+                    .metadata()?
+                    .modified()?
+                    .elapsed()
+                    .is_ok_and(|age| age > STALE_TEMPORARY_AGE)
+            } else {
+                false
+            };
+            if unused {
+                fs::remove_file(path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Sizes that have thumbnails in the cache
+    fn sizes(&self) -> Result<Vec<u32>, Error> {
+        if !self.directory.exists() {
+            return Ok(Vec::new());
+        }
+        let mut sizes = Vec::new();
+        for entry in fs::read_dir(&self.directory)? {
+            if let Some(size) = entry?.file_name().to_str().and_then(|s| s.parse().ok()) {
+                sizes.push(size);
+            }
+        }
+        Ok(sizes)
+    }
+}
+
+fn key(media: &Media) -> String {
+    match media.hash {
+        Some(hash) => hash.to_hex().to_string(),
+        None => blake3::hash(
+            format!(
+                "{}:{}",
+                media.filepath.display(),
+                media.modified.timestamp_nanos_opt().unwrap_or_default()
+            )
+            .as_bytes(),
+        )
+        .to_hex()
+        .to_string(),
+    }
+}
+
+/// Whether `path` is a thumbnail being written by `generate`, named `<key>.jpg.<pid>-<n>`
+fn is_temporary(path: &Path) -> bool {
+    path.file_stem()
+        .and_then(|stem| Path::new(stem).extension())
+        .is_some_and(|e| e == EXTENSION)
+}
+
+/// Write a thumbnail of `source` to `destination`
+///
+/// The thumbnail is written to a temporary file first so readers never see a partial image.
+fn generate(source: &Path, destination: &Path, size: u32) -> Result<(), Error> {
+    let image = Reader::open(source)?.with_guessed_format()?.decode()?;
+    let thumbnail = image.thumbnail(size, size).into_rgb8();
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut temporary = destination.as_os_str().to_owned();
+    temporary.push(format!(
+        ".{}-{}",
+        std::process::id(),
+        TEMPORARY_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    thumbnail.save_with_format(&temporary, ImageFormat::Jpeg)?;
+    fs::rename(&temporary, destination)?;
+    Ok(())
+}
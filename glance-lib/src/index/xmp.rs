@@ -0,0 +1,477 @@
+//! Reading and writing the XMP rating, title and caption of media, and reading face regions
+//!
+//! `xmp:Rating`, `dc:title`, `dc:description` and MWG or Microsoft Photo face regions are read
+//! from a sidecar file next to the media (`IMG_1.jpg.xmp` or `IMG_1.xmp`) falling back to the
+//! packet embedded in the media itself. Writes only ever go to the sidecar so
+//! the media file is never modified. The packets are scanned as text rather than parsed as RDF,
+//! which is enough for the packets written by common photo tools.
+//!
+//! Rejected media are written as an `xmp:Rating` of -1 like Lightroom does. XMP has no standard
+//! property for picks so they are only stored in the index.
+
+use std::{
+    fs::{self, File},
+    io::Read,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+
+use super::{
+    media::{Flag, MAX_RATING},
+    people::Rectangle,
+    Error,
+};
+
+const PROPERTY: &[u8] = b"xmp:Rating";
+const NAMESPACE: &str = "xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\"";
+const DESCRIPTION: &[u8] = b"<rdf:Description";
+const TITLE_PROPERTY: &str = "dc:title";
+const CAPTION_PROPERTY: &str = "dc:description";
+const DC_NAMESPACE: &str = "xmlns:dc=\"http://purl.org/dc/elements/1.1/\"";
+/// Embedded packets are near the start of the file so only this much is searched
+const EMBEDDED_SEARCH_LIMIT: u64 = 256 * 1024;
+/// `xmp:Rating` value used by Lightroom and others to mark rejected media
+const REJECT_RATING: i8 = -1;
+
+/// XMP properties of media, each `None` or empty when the media does not have it
+#[derive(Debug, Default)]
+pub(crate) struct XmpMetadata {
+    pub rating: Option<i8>,
+    pub title: Option<String>,
+    pub caption: Option<String>,
+    /// Faces with the name of the person if known
+    pub regions: Vec<(Option<String>, Rectangle)>,
+}
+
+impl XmpMetadata {
+    fn parse(bytes: &[u8]) -> Self {
+        Self {
+            rating: parse_rating(bytes),
+            title: parse_text(bytes, TITLE_PROPERTY),
+            caption: parse_text(bytes, CAPTION_PROPERTY),
+            regions: parse_regions(bytes),
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.rating.is_some()
+            && self.title.is_some()
+            && self.caption.is_some()
+            && !self.regions.is_empty()
+    }
+
+    /// Fill the properties missing from `self` with those from `other`
+    fn or(self, other: Self) -> Self {
+        Self {
+            rating: self.rating.or(other.rating),
+            title: self.title.or(other.title),
+            caption: self.caption.or(other.caption),
+            regions: match self.regions.is_empty() {
+                true => other.regions,
+                false => self.regions,
+            },
+        }
+    }
+}
+
+/// Read the XMP properties of the media at `path`
+///
+/// Properties missing from the sidecar are read from the embedded packet.
+pub(crate) fn read(path: &Path) -> Result<XmpMetadata, Error> {
+    let mut metadata = XmpMetadata::default();
+    if let Some(sidecar) = existing_sidecar(path) {
+        metadata = XmpMetadata::parse(&fs::read(sidecar)?);
+        if metadata.is_complete() {
+            return Ok(metadata);
+        }
+    }
+    let mut bytes = Vec::new();
+    File::open(path)?
+        .take(EMBEDDED_SEARCH_LIMIT)
+        .read_to_end(&mut bytes)?;
+    Ok(metadata.or(XmpMetadata::parse(&bytes)))
+}
+
+/// Write the XMP rating of the media at `path` to its sidecar, creating the sidecar if needed
+///
+/// Returns the path of the sidecar.
+pub(crate) fn write_rating(path: &Path, rating: i8) -> Result<PathBuf, Error> {
+    let (sidecar, contents) = sidecar_contents(path)?;
+    let value = rating.to_string();
+    let contents = match rating_span(&contents) {
+        Some(span) => splice(&contents, span, value.as_bytes()),
+        None => match find(&contents, DESCRIPTION) {
+            Some(start) => {
+                let end = start + DESCRIPTION.len();
+                let mut attributes = String::new();
+                if find(&contents, b"xmlns:xmp=").is_none() {
+                    attributes.push_str(&format!(" {NAMESPACE}"));
+                }
+                attributes.push_str(&format!(" xmp:Rating=\"{value}\""));
+                splice(&contents, end..end, attributes.as_bytes())
+            }
+            None if contents.is_empty() => {
+                new_packet(&format!(" {NAMESPACE} xmp:Rating=\"{value}\"")).into_bytes()
+            }
+            None => return Err(Error::InvalidXmp(sidecar)),
+        },
+    };
+    fs::write(&sidecar, contents)?;
+    Ok(sidecar)
+}
+
+/// Write the title and caption of the media at `path` to its sidecar as `dc:title` and
+/// `dc:description`, removing them when `None`
+///
+/// Returns the path of the sidecar.
+pub(crate) fn write_text(
+    path: &Path,
+    title: Option<&str>,
+    caption: Option<&str>,
+) -> Result<PathBuf, Error> {
+    let (sidecar, mut contents) = sidecar_contents(path)?;
+    for (property, value) in [(TITLE_PROPERTY, title), (CAPTION_PROPERTY, caption)] {
+        contents = set_text(contents, property, value).ok_or(Error::InvalidXmp(sidecar.clone()))?;
+    }
+    fs::write(&sidecar, contents)?;
+    Ok(sidecar)
+}
+
+/// Convert an XMP rating to a glance rating and flag
+///
+/// Ratings out of range are clamped.
+pub(crate) fn to_rating_and_flag(xmp_rating: i8) -> (u8, Option<Flag>) {
+    if xmp_rating <= REJECT_RATING {
+        (0, Some(Flag::Reject))
+    } else {
+        ((xmp_rating as u8).min(MAX_RATING), None)
+    }
+}
+
+/// Convert a glance rating and flag to an XMP rating
+pub(crate) fn to_xmp_rating(rating: u8, flag: Option<Flag>) -> i8 {
+    match flag {
+        Some(Flag::Reject) => REJECT_RATING,
+        Some(Flag::Pick) | None => rating.min(MAX_RATING) as i8,
+    }
+}
+
+/// Modified time of the sidecar of the media at `path`, `None` if it does not have one
+pub(crate) fn sidecar_modified(path: &Path) -> Result<Option<DateTime<Utc>>, Error> {
+    existing_sidecar(path)
+        .map(|sidecar| Ok(fs::metadata(sidecar)?.modified()?.into()))
+        .transpose()
+}
+
+fn existing_sidecar(path: &Path) -> Option<PathBuf> {
+    let mut with_extension = path.as_os_str().to_owned();
+    with_extension.push(".xmp");
+    [PathBuf::from(with_extension), path.with_extension("xmp")]
+        .into_iter()
+        .find(|sidecar| sidecar != path && sidecar.is_file())
+}
+
+/// Path and contents of the sidecar of the media, which are empty if it does not exist yet
+fn sidecar_contents(path: &Path) -> Result<(PathBuf, Vec<u8>), Error> {
+    Ok(match existing_sidecar(path) {
+        Some(sidecar) => {
+            let contents = fs::read(&sidecar)?;
+            (sidecar, contents)
+        }
+        None => (default_sidecar(path)?, Vec::new()),
+    })
+}
+
+fn default_sidecar(path: &Path) -> Result<PathBuf, Error> {
+    let mut sidecar = path.file_name().ok_or(Error::FileNameMissing)?.to_owned();
+    sidecar.push(".xmp");
+    Ok(path.with_file_name(sidecar))
+}
+
+/// Packet with a single description that has `attributes`, which start with a space
+fn new_packet(attributes: &str) -> String {
+    format!(
+        "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n \
+        <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n  \
+        <rdf:Description rdf:about=\"\"{attributes}/>\n \
+        </rdf:RDF>\n\
+        </x:xmpmeta>\n"
+    )
+}
+
+fn parse_rating(bytes: &[u8]) -> Option<i8> {
+    let value = std::str::from_utf8(&bytes[rating_span(bytes)?])
+        .ok()?
+        .trim();
+    value
+        .parse()
+        .ok()
+        .or_else(|| value.parse::<f32>().ok().map(|v| v.round() as i8))
+}
+
+/// Range of the value of the first `xmp:Rating` in either attribute or element form
+fn rating_span(bytes: &[u8]) -> Option<Range<usize>> {
+    property_span(bytes, PROPERTY)
+}
+
+/// Range of the value of the first `property` in either attribute or element form
+fn property_span(bytes: &[u8], property: &[u8]) -> Option<Range<usize>> {
+    let mut offset = 0;
+    while let Some(found) = find(&bytes[offset..], property) {
+        let mut i = offset + found + property.len();
+        offset = i;
+        while bytes.get(i).is_some_and(u8::is_ascii_whitespace) {
+            i += 1;
+        }
+        match bytes.get(i) {
+            // Attribute form `xmp:Rating="3"`
+            Some(b'=') => {
+                i += 1;
+                while bytes.get(i).is_some_and(u8::is_ascii_whitespace) {
+                    i += 1;
+                }
+                let quote = *bytes.get(i).filter(|q| matches!(q, b'"' | b'\''))?;
+                let start = i + 1;
+                let end = start + bytes[start..].iter().position(|b| *b == quote)?;
+                return Some(start..end);
+            }
+            // Element form `<xmp:Rating>3</xmp:Rating>`
+            Some(b'>') => {
+                let start = i + 1;
+                let end = start + bytes[start..].iter().position(|b| *b == b'<')?;
+                return Some(start..end);
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Text of a `dc` property, which is usually a language alternative holding the text in
+/// `rdf:li` elements, the first of which is the default
+fn parse_text(bytes: &[u8], property: &str) -> Option<String> {
+    let (_, content) = element_spans(bytes, property)?;
+    let content = std::str::from_utf8(&bytes[content]).ok()?;
+    let text = match content.find("<rdf:li") {
+        Some(start) => {
+            let item = &content[start..];
+            &item[item.find('>')? + 1..item.find("</rdf:li>")?]
+        }
+        None => content,
+    };
+    let text = unescape(text.trim());
+    (!text.is_empty()).then_some(text)
+}
+
+/// Value of the first `property` in either attribute or element form
+fn property_value(bytes: &[u8], property: &str) -> Option<String> {
+    let value = std::str::from_utf8(&bytes[property_span(bytes, property.as_bytes())?]).ok()?;
+    Some(unescape(value.trim()))
+}
+
+/// Face regions from the MWG region list, or the Microsoft Photo one if there is none
+fn parse_regions(bytes: &[u8]) -> Vec<(Option<String>, Rectangle)> {
+    let regions: Vec<_> = list_items(bytes, "mwg-rs:RegionList")
+        .into_iter()
+        .filter_map(parse_mwg_region)
+        .collect();
+    if !regions.is_empty() {
+        return regions;
+    }
+    list_items(bytes, "MPRI:Regions")
+        .into_iter()
+        .filter_map(parse_microsoft_region)
+        .collect()
+}
+
+/// MWG region, whose area is centred on `x` and `y`
+fn parse_mwg_region(item: &[u8]) -> Option<(Option<String>, Rectangle)> {
+    let value = |property| property_value(item, property);
+    if value("mwg-rs:Type").is_some_and(|kind| kind != "Face")
+        || value("stArea:unit").is_some_and(|unit| unit != "normalized")
+    {
+        return None;
+    }
+    let number = |property| value(property)?.parse::<f64>().ok();
+    let (width, height) = (number("stArea:w")?, number("stArea:h")?);
+    let rectangle = Rectangle {
+        x: number("stArea:x")? - width / 2.0,
+        y: number("stArea:y")? - height / 2.0,
+        width,
+        height,
+    };
+    let name = value("mwg-rs:Name").filter(|name| !name.is_empty());
+    Some((name, rectangle.clamped()?))
+}
+
+/// Microsoft Photo region, whose rectangle is `x, y, width, height` from the top left
+fn parse_microsoft_region(item: &[u8]) -> Option<(Option<String>, Rectangle)> {
+    let numbers = property_value(item, "MPReg:Rectangle")?
+        .split(',')
+        .map(|number| number.trim().parse().ok())
+        .collect::<Option<Vec<f64>>>()?;
+    let [x, y, width, height] = numbers[..] else {
+        return None;
+    };
+    let rectangle = Rectangle {
+        x,
+        y,
+        width,
+        height,
+    };
+    let name = property_value(item, "MPReg:PersonDisplayName").filter(|name| !name.is_empty());
+    Some((name, rectangle.clamped()?))
+}
+
+/// Each `rdf:li` directly in the first `list` element including its tags, which may hold the
+/// properties as attributes
+///
+/// Items are matched by their open and close tags so that lists nested inside an item, like the
+/// bags of extensions some tools add to regions, stay part of it.
+fn list_items<'a>(bytes: &'a [u8], list: &str) -> Vec<&'a [u8]> {
+    let Some((_, content)) = element_spans(bytes, list) else {
+        return Vec::new();
+    };
+    let content = &bytes[content];
+    let is_tag = |start: usize, name: &[u8]| {
+        content[start..].starts_with(name)
+            && content
+                .get(start + name.len())
+                .is_some_and(|c| matches!(c, b'>' | b'/') || c.is_ascii_whitespace())
+    };
+    let mut items = Vec::new();
+    let mut depth = 0usize;
+    let mut item_start = 0;
+    let mut offset = 0;
+    while let Some(found) = find(&content[offset..], b"<") {
+        let start = offset + found;
+        let Some(end) = tag_end(&content[start..]).map(|end| start + end) else {
+            break;
+        };
+        offset = end + 1;
+        if is_tag(start, b"<rdf:li") {
+            if depth == 0 {
+                item_start = start;
+            }
+            match content[end - 1] == b'/' {
+                true if depth == 0 => items.push(&content[item_start..offset]),
+                true => {}
+                false => depth += 1,
+            }
+        } else if is_tag(start, b"</rdf:li") && depth > 0 {
+            depth -= 1;
+            if depth == 0 {
+                items.push(&content[item_start..offset]);
+            }
+        }
+    }
+    items
+}
+
+/// Replace the element of a `dc` property with one holding `value`, or remove it with `None`
+///
+/// Returns `None` if there is no description to add the element to.
+fn set_text(contents: Vec<u8>, property: &str, value: Option<&str>) -> Option<Vec<u8>> {
+    let element = value.map(|value| {
+        format!(
+            "<{property}><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></{property}>",
+            escape(value)
+        )
+    });
+    if let Some((span, _)) = element_spans(&contents, property) {
+        return Some(splice(
+            &contents,
+            span,
+            element.unwrap_or_default().as_bytes(),
+        ));
+    }
+    let Some(element) = element else {
+        return Some(contents);
+    };
+    let mut contents = match contents.is_empty() {
+        true => new_packet("").into_bytes(),
+        false => contents,
+    };
+    let start = find(&contents, DESCRIPTION)?;
+    if find(&contents, b"xmlns:dc=").is_none() {
+        let end = start + DESCRIPTION.len();
+        contents = splice(&contents, end..end, format!(" {DC_NAMESPACE}").as_bytes());
+    }
+    let end = start + tag_end(&contents[start..])?;
+    Some(if contents[end - 1] == b'/' {
+        // Open a self-closing description so it can hold the element
+        let replacement = format!(">{element}</rdf:Description>");
+        splice(&contents, end - 1..end + 1, replacement.as_bytes())
+    } else {
+        splice(&contents, end + 1..end + 1, element.as_bytes())
+    })
+}
+
+/// Ranges of the first whole `<property>...</property>` element and of its content
+fn element_spans(bytes: &[u8], property: &str) -> Option<(Range<usize>, Range<usize>)> {
+    let open = format!("<{property}");
+    let close = format!("</{property}>");
+    let mut offset = 0;
+    while let Some(found) = find(&bytes[offset..], open.as_bytes()) {
+        let start = offset + found;
+        offset = start + open.len();
+        let end = match bytes.get(offset) {
+            Some(b'>') => offset,
+            Some(c) if c.is_ascii_whitespace() => offset + tag_end(&bytes[offset..])?,
+            // A longer name with the same prefix
+            _ => continue,
+        };
+        if bytes[end - 1] == b'/' {
+            return None;
+        }
+        let content_start = end + 1;
+        let content_end = content_start + find(&bytes[content_start..], close.as_bytes())?;
+        return Some((start..content_end + close.len(), content_start..content_end));
+    }
+    None
+}
+
+/// Index of the `>` ending the tag at the start of `bytes`, skipping quoted attribute values
+fn tag_end(bytes: &[u8]) -> Option<usize> {
+    let mut quote = None;
+    for (i, b) in bytes.iter().enumerate() {
+        match (quote, b) {
+            (None, b'"' | b'\'') => quote = Some(*b),
+            (Some(q), b) if q == *b => quote = None,
+            (None, b'>') => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn splice(bytes: &[u8], range: Range<usize>, replacement: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(bytes.len() + replacement.len());
+    result.extend_from_slice(&bytes[..range.start]);
+    result.extend_from_slice(replacement);
+    result.extend_from_slice(&bytes[range.end..]);
+    result
+}
@@ -0,0 +1,177 @@
+use rusqlite::{named_params, Connection, Error, OptionalExtension, Row};
+
+use super::converters::PathBufSql;
+
+/// Low level type for interacting with album rows
+#[derive(Debug)]
+pub(crate) struct AlbumSql {
+    pub id: i64,
+    pub name: String,
+    pub description: Option<String>,
+    pub cover: Option<PathBufSql>,
+    /// Number of items in the album
+    pub count: i64,
+}
+
+const SELECT_ALBUMS: &str = "SELECT id, name, description, cover, \
+    (SELECT COUNT(*) FROM album_item WHERE album_id = album.id) \
+    FROM album";
+
+impl AlbumSql {
+    pub fn create_table(conn: &mut Connection) -> Result<(), Error> {
+        let transaction = conn.transaction()?;
+        transaction.execute_batch(
+            "CREATE TABLE IF NOT EXISTS album (
+                    id INTEGER PRIMARY KEY,
+                    name TEXT NOT NULL UNIQUE,
+                    description TEXT,
+                    cover TEXT,
+                    FOREIGN KEY (cover) REFERENCES media(filepath),
+                    CHECK(name <> '')
+                );
+            CREATE TABLE IF NOT EXISTS album_item (
+                    album_id INTEGER NOT NULL,
+                    filepath TEXT NOT NULL,
+                    position INTEGER NOT NULL,
+                    FOREIGN KEY (album_id) REFERENCES album(id),
+                    FOREIGN KEY (filepath) REFERENCES media(filepath),
+                    UNIQUE (album_id, filepath)
+                );
+            CREATE INDEX IF NOT EXISTS album_item_position_index
+                ON album_item (album_id, position);
+            CREATE INDEX IF NOT EXISTS album_item_filepath_index ON album_item (filepath);
+            CREATE TRIGGER IF NOT EXISTS album_media_delete AFTER DELETE ON media BEGIN
+                DELETE FROM album_item WHERE filepath = old.filepath;
+                UPDATE album SET cover = NULL WHERE cover = old.filepath;
+            END;
+            CREATE TRIGGER IF NOT EXISTS album_media_rename
+            AFTER UPDATE OF filepath ON media BEGIN
+                UPDATE album_item SET filepath = new.filepath WHERE filepath = old.filepath;
+                UPDATE album SET cover = new.filepath WHERE cover = old.filepath;
+            END;",
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    pub fn insert(conn: &Connection, name: &str, description: Option<&str>) -> Result<i64, Error> {
+        let mut stmt =
+            conn.prepare("INSERT INTO album (name, description) VALUES (:name, :description)")?;
+        stmt.insert(named_params! {
+            ":name": name,
+            ":description": description,
+        })
+    }
+
+    pub fn delete(conn: &Connection, id: i64) -> Result<usize, Error> {
+        conn.execute(
+            "DELETE FROM album_item WHERE album_id = :id",
+            named_params! { ":id": id },
+        )?;
+        conn.execute(
+            "DELETE FROM album WHERE id = :id",
+            named_params! { ":id": id },
+        )
+    }
+
+    pub fn get_by_name(conn: &Connection, name: &str) -> Result<Option<AlbumSql>, Error> {
+        let mut stmt = conn.prepare(&format!("{SELECT_ALBUMS} WHERE name = :name"))?;
+        stmt.query_row(named_params! { ":name": name }, |row| {
+            AlbumSql::try_from(row)
+        })
+        .optional()
+    }
+
+    pub fn get_all(conn: &Connection) -> Result<Vec<AlbumSql>, Error> {
+        let mut stmt = conn.prepare(&format!("{SELECT_ALBUMS} ORDER BY name"))?;
+        let iter = stmt.query_map([], |row| AlbumSql::try_from(row))?;
+        iter.collect()
+    }
+
+    pub fn set_description(
+        conn: &Connection,
+        id: i64,
+        description: Option<&str>,
+    ) -> Result<usize, Error> {
+        let mut stmt =
+            conn.prepare("UPDATE album SET description = :description WHERE id = :id")?;
+        stmt.execute(named_params! {
+            ":description": description,
+            ":id": id,
+        })
+    }
+
+    pub fn set_cover(
+        conn: &Connection,
+        id: i64,
+        cover: Option<&PathBufSql>,
+    ) -> Result<usize, Error> {
+        let mut stmt = conn.prepare("UPDATE album SET cover = :cover WHERE id = :id")?;
+        stmt.execute(named_params! {
+            ":cover": cover,
+            ":id": id,
+        })
+    }
+
+    /// Paths of the items in the album in order
+    pub fn get_items(conn: &Connection, id: i64) -> Result<Vec<PathBufSql>, Error> {
+        let mut stmt = conn.prepare(
+            "SELECT filepath FROM album_item WHERE album_id = :id ORDER BY position, rowid",
+        )?;
+        let iter = stmt.query_map(named_params! { ":id": id }, |row| row.get(0))?;
+        iter.collect()
+    }
+
+    /// Names of the albums `filepath` is in with its index in each
+    pub fn get_by_item(
+        conn: &Connection,
+        filepath: &PathBufSql,
+    ) -> Result<Vec<(String, usize)>, Error> {
+        let mut stmt = conn.prepare(
+            "SELECT album.name, (SELECT COUNT(*) FROM album_item AS other \
+                WHERE other.album_id = album_item.album_id \
+                AND (other.position, other.rowid) < (album_item.position, album_item.rowid)) \
+                FROM album_item JOIN album ON album.id = album_item.album_id \
+                WHERE filepath = :filepath \
+                ORDER BY album.name",
+        )?;
+        let iter = stmt.query_map(named_params! { ":filepath": filepath }, |row| {
+            Ok((row.get(0)?, row.get::<_, i64>(1)? as usize))
+        })?;
+        iter.collect()
+    }
+
+    /// Replace the items in the album, numbering their positions in order
+    pub fn set_items(conn: &Connection, id: i64, items: &[PathBufSql]) -> Result<(), Error> {
+        conn.execute(
+            "DELETE FROM album_item WHERE album_id = :id",
+            named_params! { ":id": id },
+        )?;
+        let mut stmt = conn.prepare(
+            "INSERT INTO album_item (album_id, filepath, position) \
+                VALUES (:id, :filepath, :position)",
+        )?;
+        for (position, filepath) in items.iter().enumerate() {
+            stmt.execute(named_params! {
+                ":id": id,
+                ":filepath": filepath,
+                ":position": position as i64,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<&Row<'_>> for AlbumSql {
+    type Error = Error;
+
+    fn try_from(row: &Row<'_>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            description: row.get(2)?,
+            cover: row.get(3)?,
+            count: row.get(4)?,
+        })
+    }
+}
@@ -12,6 +12,9 @@ use rusqlite::{
     Error, ToSql,
 };
 
+use crate::index::media::Flag;
+use crate::index::people::RegionSource;
+
 #[derive(Debug, From, Into)]
 pub(crate) struct FileFormatSql(pub String);
 
@@ -76,3 +79,50 @@ impl<'a> From<&'a PathBufSql> for &'a Path {
         value.0.as_path()
     }
 }
+
+/// Perceptual hashes use all 64 bits so they are stored as the bit equivalent `i64`
+#[derive(Debug, From, Into)]
+pub(crate) struct PerceptualHashSql(pub u64);
+
+impl ToSql for PerceptualHashSql {
+    fn to_sql(&self) -> Result<ToSqlOutput<'_>, Error> {
+        Ok((self.0 as i64).into())
+    }
+}
+
+impl FromSql for PerceptualHashSql {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        Ok(PerceptualHashSql(value.as_i64()? as u64))
+    }
+}
+
+impl ToSql for Flag {
+    fn to_sql(&self) -> Result<ToSqlOutput<'_>, Error> {
+        Ok(self.as_str().into())
+    }
+}
+
+impl FromSql for Flag {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value
+            .as_str()?
+            .parse()
+            .map_err(|e| FromSqlError::Other(Box::new(e)))
+    }
+}
+
+impl ToSql for RegionSource {
+    fn to_sql(&self) -> Result<ToSqlOutput<'_>, Error> {
+        Ok(self.as_str().into())
+    }
+}
+
+impl FromSql for RegionSource {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "metadata" => Ok(Self::Metadata),
+            "manual" => Ok(Self::Manual),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use chrono::{DateTime, Utc};
+use rusqlite::{named_params, Connection, Error, OptionalExtension, Row};
+
+use super::converters::PathBufSql;
+
+/// Low level type for interacting with event rows
+#[derive(Debug)]
+pub(crate) struct EventSql {
+    pub id: i64,
+    pub name: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// Most common location of the media in the event
+    pub place: Option<String>,
+    /// Number of media in the event
+    pub count: i64,
+}
+
+const SELECT_EVENTS: &str = "SELECT id, name, start, end, place, \
+    (SELECT COUNT(*) FROM event_item WHERE event_id = event.id) \
+    FROM event";
+
+impl EventSql {
+    pub fn create_table(conn: &mut Connection) -> Result<(), Error> {
+        let transaction = conn.transaction()?;
+        transaction.execute_batch(
+            "CREATE TABLE IF NOT EXISTS event (
+                    id INTEGER PRIMARY KEY,
+                    name TEXT NOT NULL,
+                    start TEXT NOT NULL,
+                    end TEXT NOT NULL,
+                    place TEXT,
+                    CHECK(name <> '')
+                );
+            CREATE TABLE IF NOT EXISTS event_item (
+                    event_id INTEGER NOT NULL,
+                    filepath TEXT NOT NULL UNIQUE,
+                    FOREIGN KEY (event_id) REFERENCES event(id),
+                    FOREIGN KEY (filepath) REFERENCES media(filepath)
+                );
+            CREATE INDEX IF NOT EXISTS event_item_event_index ON event_item (event_id);
+            CREATE INDEX IF NOT EXISTS event_start_index ON event (start);
+            -- Recreated so indexes from before it kept event summaries up to date get it too.
+            -- Like event::summarize, it sets the range of the media left and their most common
+            -- location, and removes the event of the deleted media if it was the last one.
+            DROP TRIGGER IF EXISTS event_media_delete;
+            CREATE TRIGGER event_media_delete AFTER DELETE ON media
+            WHEN EXISTS (SELECT 1 FROM event_item WHERE filepath = old.filepath) BEGIN
+                UPDATE event SET
+                    start = COALESCE((SELECT MIN(media.created) FROM event_item
+                        JOIN media ON media.filepath = event_item.filepath
+                        WHERE event_item.event_id = event.id), start),
+                    end = COALESCE((SELECT MAX(media.created) FROM event_item
+                        JOIN media ON media.filepath = event_item.filepath
+                        WHERE event_item.event_id = event.id), end),
+                    place = (SELECT media.location FROM event_item
+                        JOIN media ON media.filepath = event_item.filepath
+                        WHERE event_item.event_id = event.id AND media.location IS NOT NULL
+                        GROUP BY media.location
+                        ORDER BY COUNT(*) DESC, media.location
+                        LIMIT 1)
+                    WHERE id = (SELECT event_id FROM event_item WHERE filepath = old.filepath);
+                DELETE FROM event
+                    WHERE id = (SELECT event_id FROM event_item WHERE filepath = old.filepath)
+                    AND NOT EXISTS (SELECT 1 FROM event_item
+                        WHERE event_id = event.id AND filepath <> old.filepath);
+                DELETE FROM event_item WHERE filepath = old.filepath;
+            END;
+            CREATE TRIGGER IF NOT EXISTS event_media_rename
+            AFTER UPDATE OF filepath ON media BEGIN
+                UPDATE event_item SET filepath = new.filepath WHERE filepath = old.filepath;
+            END;",
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Insert the event without its items and return its id
+    pub fn insert(&self, conn: &Connection) -> Result<i64, Error> {
+        let mut stmt = conn.prepare(
+            "INSERT INTO event (name, start, end, place) VALUES (:name, :start, :end, :place)",
+        )?;
+        stmt.insert(named_params! {
+            ":name": self.name,
+            ":start": self.start,
+            ":end": self.end,
+            ":place": self.place,
+        })
+    }
+
+    /// Update the name, start, end and place of the event with `self.id`
+    pub fn update(&self, conn: &Connection) -> Result<usize, Error> {
+        let mut stmt = conn.prepare(
+            "UPDATE event SET name = :name, start = :start, end = :end, place = :place \
+                WHERE id = :id",
+        )?;
+        stmt.execute(named_params! {
+            ":id": self.id,
+            ":name": self.name,
+            ":start": self.start,
+            ":end": self.end,
+            ":place": self.place,
+        })
+    }
+
+    pub fn rename(conn: &Connection, id: i64, name: &str) -> Result<usize, Error> {
+        conn.execute(
+            "UPDATE event SET name = :name WHERE id = :id",
+            named_params! { ":id": id, ":name": name },
+        )
+    }
+
+    pub fn delete(conn: &Connection, id: i64) -> Result<usize, Error> {
+        conn.execute(
+            "DELETE FROM event_item WHERE event_id = :id",
+            named_params! { ":id": id },
+        )?;
+        conn.execute(
+            "DELETE FROM event WHERE id = :id",
+            named_params! { ":id": id },
+        )
+    }
+
+    pub fn delete_all(conn: &Connection) -> Result<usize, Error> {
+        conn.execute("DELETE FROM event_item", [])?;
+        conn.execute("DELETE FROM event", [])
+    }
+
+    pub fn get_by_id(conn: &Connection, id: i64) -> Result<Option<EventSql>, Error> {
+        let mut stmt = conn.prepare(&format!("{SELECT_EVENTS} WHERE id = :id"))?;
+        stmt.query_row(named_params! { ":id": id }, |row| EventSql::try_from(row))
+            .optional()
+    }
+
+    /// Events ordered by when they started
+    pub fn get_all(conn: &Connection) -> Result<Vec<EventSql>, Error> {
+        let mut stmt = conn.prepare(&format!("{SELECT_EVENTS} ORDER BY start, id"))?;
+        let iter = stmt.query_map([], |row| EventSql::try_from(row))?;
+        iter.collect()
+    }
+
+    /// Paths of the items in the event ordered by created
+    pub fn get_items(conn: &Connection, id: i64) -> Result<Vec<PathBufSql>, Error> {
+        let mut stmt = conn.prepare(
+            "SELECT event_item.filepath FROM event_item \
+                JOIN media ON media.filepath = event_item.filepath \
+                WHERE event_id = :id \
+                ORDER BY media.created, media.filepath",
+        )?;
+        let iter = stmt.query_map(named_params! { ":id": id }, |row| row.get(0))?;
+        iter.collect()
+    }
+
+    /// Id of the event `filepath` is in
+    pub fn get_by_item(conn: &Connection, filepath: &PathBufSql) -> Result<Option<i64>, Error> {
+        let mut stmt =
+            conn.prepare("SELECT event_id FROM event_item WHERE filepath = :filepath")?;
+        stmt.query_row(named_params! { ":filepath": filepath }, |row| row.get(0))
+            .optional()
+    }
+
+    /// Paths of the media in any event with the id of their event
+    pub fn get_all_items(conn: &Connection) -> Result<Vec<(PathBufSql, i64)>, Error> {
+        let mut stmt = conn.prepare("SELECT filepath, event_id FROM event_item")?;
+        let iter = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        iter.collect()
+    }
+
+    /// Add media to the event, moving them out of any other event
+    pub fn add_items(conn: &Connection, id: i64, items: &[PathBufSql]) -> Result<(), Error> {
+        let mut stmt = conn.prepare(
+            "INSERT OR REPLACE INTO event_item (event_id, filepath) VALUES (:id, :filepath)",
+        )?;
+        for filepath in items {
+            stmt.execute(named_params! {
+                ":id": id,
+                ":filepath": filepath,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<&Row<'_>> for EventSql {
+    type Error = Error;
+
+    fn try_from(row: &Row<'_>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            start: row.get(2)?,
+            end: row.get(3)?,
+            place: row.get(4)?,
+            count: row.get(5)?,
+        })
+    }
+}
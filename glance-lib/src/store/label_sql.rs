@@ -5,6 +5,20 @@ use super::converters::PathBufSql;
 
 const COLUMNS: &str = "filepath, label";
 
+/// Separator between the levels of hierarchical labels like `places/portugal/lisbon`
+pub const LABEL_SEPARATOR: char = '/';
+
+/// SQL condition that `column` is the label in `param` or one of its descendants
+///
+/// Descendants start with `{label}/` so they sort between it and `{label}0`, the next character
+/// after the separator, which lets the condition use the index on labels.
+pub(crate) fn label_or_descendant_sql(column: &str, param: &str) -> String {
+    format!(
+        "({column} = {param} OR ({column} > {param} || '{LABEL_SEPARATOR}' \
+            AND {column} < {param} || '0'))"
+    )
+}
+
 /// Low level type for interacting with label rows
 #[derive(Debug)]
 pub(crate) struct LabelSql {
@@ -58,6 +72,20 @@ impl LabelSql {
         })
     }
 
+    /// Insert the label unless the path already has it
+    ///
+    /// Returns the number of rows inserted.
+    pub fn insert_or_ignore(&self, conn: &Connection) -> Result<usize, Error> {
+        let mut stmt = conn.prepare(formatcp!(
+            "INSERT OR IGNORE INTO label ({COLUMNS}) \
+            VALUES (:filepath, :label)"
+        ))?;
+        stmt.execute(named_params! {
+            ":filepath": self.filepath,
+            ":label": self.label,
+        })
+    }
+
     pub fn delete(&self, conn: &Connection) -> Result<usize, Error> {
         let mut stmt = conn.prepare(
             "DELETE FROM label \
@@ -70,6 +98,44 @@ impl LabelSql {
         })
     }
 
+    pub fn delete_path(filepath: &PathBufSql, conn: &Connection) -> Result<usize, Error> {
+        let mut stmt = conn.prepare("DELETE FROM label WHERE filepath = :filepath")?;
+        stmt.execute(named_params! {
+            ":filepath": filepath,
+        })
+    }
+
+    /// Rename a label and its descendants, merging into labels media already have
+    ///
+    /// Returns the number of labels renamed or merged.
+    pub fn rename(conn: &Connection, from: &str, to: &str) -> Result<usize, Error> {
+        let subtree = label_or_descendant_sql("label", ":from");
+        // Media that already have the new label keep it and their old label is dropped
+        let renamed = conn.execute(
+            &format!(
+                "UPDATE OR IGNORE label SET label = :to || substr(label, length(:from) + 1) \
+                    WHERE {subtree}"
+            ),
+            named_params! { ":from": from, ":to": to },
+        )?;
+        let merged = conn.execute(
+            &format!("DELETE FROM label WHERE {subtree}"),
+            named_params! { ":from": from },
+        )?;
+        Ok(renamed + merged)
+    }
+
+    /// Delete a label and its descendants from all media, returning the number of rows deleted
+    pub fn delete_everywhere(conn: &Connection, label: &str) -> Result<usize, Error> {
+        conn.execute(
+            &format!(
+                "DELETE FROM label WHERE {}",
+                label_or_descendant_sql("label", ":label")
+            ),
+            named_params! { ":label": label },
+        )
+    }
+
     pub fn get_all_labels(conn: &Connection) -> Result<Vec<String>, Error> {
         let mut stmt = conn.prepare("SELECT DISTINCT label FROM label ORDER BY label")?;
         let iter = stmt.query_map([], |row| row.get(0))?;
@@ -78,7 +144,7 @@ impl LabelSql {
 }
 
 impl LabelSearch<'_> {
-    pub fn new(conn: &Connection, filter: LabelFilter) -> Result<LabelSearch, Error> {
+    pub fn new(conn: &Connection, filter: LabelFilter) -> Result<LabelSearch<'_>, Error> {
         let statement = match &filter.filepath {
             Some(_) => conn.prepare(formatcp!(
                 "SELECT {COLUMNS} FROM label \
@@ -1,17 +1,28 @@
-use std::{collections::HashMap, path::Path};
+use std::{collections::HashMap, hash::Hash, path::Path};
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
 use const_format::formatcp;
 use rusqlite::{
-    named_params, Connection, Error, ErrorCode, OptionalExtension, Row, Statement, ToSql,
+    named_params,
+    types::{FromSql, Value},
+    Connection, Error, ErrorCode, OptionalExtension, Row, Statement, ToSql,
 };
+use serde::{Deserialize, Serialize};
 
-use super::converters::{FileFormatSql, HashSql, PathBufSql};
+use super::{
+    converters::{FileFormatSql, HashSql, PathBufSql, PerceptualHashSql},
+    label_sql::label_or_descendant_sql,
+    people_sql::media_with_person_sql,
+    place_sql::{media_in_place_sql, KM_PER_DEGREE},
+    search_sql::SearchSql,
+};
+use crate::index::media::Flag;
 
-const IMPORT_DB: &str = "import";
-const COLUMNS: &str = "filepath, size, format, created, modified, location, device, hash";
-const COLUMNS_WITH_PREFIX: &str =
-    "media.filepath, size, format, created, modified, location, device, hash";
+pub(crate) const IMPORT_DB: &str = "import";
+const COLUMNS: &str = "filepath, size, format, created, modified, location, device, hash, \
+    perceptual_hash, rating, flag, latitude, longitude, title, caption, notes, country";
+/// Id of the album named by the `:album` param
+const ALBUM_ID: &str = "(SELECT id FROM album WHERE name = :album)";
 
 /// Low level type for interacting with media rows
 #[derive(Debug)]
@@ -25,20 +36,169 @@ pub(crate) struct MediaSql {
     pub device: Option<String>,
     // pub iso: (),
     pub hash: Option<HashSql>,
+    pub perceptual_hash: Option<PerceptualHashSql>,
+    pub rating: u8,
+    pub flag: Option<Flag>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub title: Option<String>,
+    pub caption: Option<String>,
+    pub notes: Option<String>,
+    pub country: Option<String>,
 }
 
+/// What has been read from the xmp of media
 #[derive(Debug, Default)]
+pub(crate) struct XmpStateSql {
+    /// Modified time of the xmp sidecar when it was last read, `None` if there was none
+    pub modified: Option<DateTime<Utc>>,
+    /// Whether face regions were read, which they were not by older versions of glance
+    pub regions_read: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct MediaFilter {
     pub created_start: Option<DateTime<Utc>>,
     pub created_end: Option<DateTime<Utc>>,
-    pub label: Option<String>,
+    pub label: Option<LabelQuery>,
     pub device: Option<String>,
     pub format: Option<String>,
+    /// Only include media rated at least this many stars
+    pub min_rating: Option<u8>,
+    pub flag: Option<Flag>,
+    /// Only include media taken within this distance of a point
+    pub near: Option<Near>,
+    /// Only include media taken inside this rectangle of latitudes and longitudes
+    pub within: Option<Bounds>,
+    /// Words that must each start a word in the path, location, device, labels, title, caption
+    /// or notes of media
+    ///
+    /// Results are ordered by how well they match instead of by created.
+    pub text: Vec<String>,
+    /// Only include media in this album
+    pub album: Option<String>,
+    /// Only include media with a face region of each of these people
+    pub people: Vec<String>,
+    /// Only include media in the event with this id
+    pub event: Option<i64>,
+    /// Only include media taken inside the place with this name
+    pub place: Option<String>,
+    /// Only include media created on these days of the year in UTC, in any year
+    pub month_day: Option<MonthDayRange>,
+    /// Order of the media, by default their position in `album`, how well they match `text` or
+    /// created, whichever is set first
+    pub sort: Option<SortKey>,
+    /// Reverse the order
+    pub descending: bool,
+    /// Skip this many media
+    pub offset: Option<u64>,
+    /// Return at most this many media
+    pub limit: Option<u64>,
+}
+
+/// What to order media by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortKey {
+    Created,
+    Modified,
+    Size,
+    Path,
+    /// Shuffle, which differs on every search so should not be paginated
+    Random,
+}
+
+/// Circle around a point in decimal degrees
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Near {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub radius_km: f64,
+}
+
+/// Rectangle of latitudes and longitudes in decimal degrees, which crosses the antimeridian
+/// when `west` is more than `east`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Bounds {
+    pub south: f64,
+    pub west: f64,
+    pub north: f64,
+    pub east: f64,
+}
+
+/// Day of the year without a year
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct MonthDay {
+    pub month: u32,
+    pub day: u32,
+}
+
+/// Days of the year from `start` to `end` inclusive, wrapping around the new year when `start`
+/// is after `end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MonthDayRange {
+    pub start: MonthDay,
+    pub end: MonthDay,
+}
+
+impl MonthDay {
+    /// `MM-DD` as produced by `strftime('%m-%d', ...)` in SQL
+    fn to_sql_text(self) -> String {
+        format!("{:02}-{:02}", self.month, self.day)
+    }
+}
+
+impl From<NaiveDate> for MonthDay {
+    fn from(date: NaiveDate) -> Self {
+        Self {
+            month: date.month(),
+            day: date.day(),
+        }
+    }
+}
+
+/// Boolean query over the labels of media
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LabelQuery {
+    /// Media has the label
+    Label(String),
+    /// Media matches every query, true if empty
+    AllOf(Vec<LabelQuery>),
+    /// Media matches at least one query, false if empty
+    AnyOf(Vec<LabelQuery>),
+    /// Media matches none of the queries, true if empty
+    NoneOf(Vec<LabelQuery>),
 }
 
 pub(crate) struct MediaSearch<'conn> {
     statement: Statement<'conn>,
     filter: MediaFilter,
+    /// Names and values of the params generated while compiling `filter`
+    params: Vec<(String, Value)>,
+}
+
+/// First and last created of some media
+pub type CreatedRange = (DateTime<Utc>, DateTime<Utc>);
+
+/// Totals over the media matching a filter
+#[derive(Debug, Default)]
+pub(crate) struct MediaTotals {
+    pub count: i64,
+    pub bytes: u64,
+    pub missing_created: i64,
+    pub missing_location: i64,
+    pub missing_hash: i64,
+}
+
+/// Copies of the same file among the media matching a filter
+#[derive(Debug, Default)]
+pub(crate) struct MediaDuplicateTotals {
+    /// Media whose hash is shared with another media
+    pub count: i64,
+    /// Bytes taken by all but the largest media of each hash
+    pub wasted_bytes: u64,
 }
 
 pub(crate) struct MediaDuplicates<'conn> {
@@ -49,6 +209,10 @@ pub(crate) struct MediaNewFromImport<'conn> {
     statement: Statement<'conn>,
 }
 
+pub(crate) struct MediaWithPerceptualHash<'conn> {
+    statement: Statement<'conn>,
+}
+
 impl MediaSql {
     pub fn create_table(conn: &mut Connection) -> Result<(), Error> {
         let transaction = conn.transaction()?;
@@ -61,19 +225,50 @@ impl MediaSql {
                     modified TEXT,
                     location TEXT,
                     device TEXT,
-                    hash BLOB
+                    hash BLOB,
+                    perceptual_hash INTEGER,
+                    rating INTEGER NOT NULL DEFAULT 0,
+                    flag TEXT,
+                    latitude REAL,
+                    longitude REAL,
+                    title TEXT,
+                    caption TEXT,
+                    notes TEXT,
+                    country TEXT
                 );",
             [],
         )?;
+        add_column_if_missing(&transaction, "media", "perceptual_hash", "INTEGER")?;
+        add_column_if_missing(
+            &transaction,
+            "media",
+            "rating",
+            "INTEGER NOT NULL DEFAULT 0",
+        )?;
+        add_column_if_missing(&transaction, "media", "flag", "TEXT")?;
+        add_column_if_missing(&transaction, "media", "latitude", "REAL")?;
+        add_column_if_missing(&transaction, "media", "longitude", "REAL")?;
+        add_column_if_missing(&transaction, "media", "title", "TEXT")?;
+        add_column_if_missing(&transaction, "media", "caption", "TEXT")?;
+        add_column_if_missing(&transaction, "media", "notes", "TEXT")?;
+        add_column_if_missing(&transaction, "media", "xmp_modified", "TEXT")?;
+        add_column_if_missing(&transaction, "media", "country", "TEXT")?;
+        add_column_if_missing(
+            &transaction,
+            "media",
+            "regions_read",
+            "INTEGER NOT NULL DEFAULT 0",
+        )?;
         transaction.execute("CREATE INDEX IF NOT EXISTS hash_index ON media (hash);", [])?;
         transaction.commit()?;
         Ok(())
     }
 
+    /// Attach the index at `import_path` read-only so importing never modifies it
     pub fn attach_for_import(import_path: &Path, conn: &mut Connection) -> Result<(), Error> {
         conn.execute(
-            &format!("ATTACH DATABASE '{}' AS {IMPORT_DB}", import_path.display()),
-            [],
+            formatcp!("ATTACH DATABASE ?1 AS {IMPORT_DB}"),
+            [read_only_uri(import_path)],
         )?;
         Ok(())
     }
@@ -81,7 +276,9 @@ impl MediaSql {
     pub fn insert(&self, conn: &Connection) -> Result<bool, Error> {
         let mut stmt = conn.prepare(formatcp!(
             "INSERT INTO media ({COLUMNS}) \
-            VALUES (:filepath, :size, :format, :created, :modified, :location, :device, :hash)"
+            VALUES (:filepath, :size, :format, :created, :modified, :location, :device, :hash, \
+            :perceptual_hash, :rating, :flag, :latitude, :longitude, :title, :caption, :notes, \
+            :country)"
         ))?;
         let res = stmt.insert(named_params! {
             ":filepath": self.filepath,
@@ -92,6 +289,15 @@ impl MediaSql {
             ":location": &self.location,
             ":device": &self.device,
             ":hash": self.hash,
+            ":perceptual_hash": self.perceptual_hash,
+            ":rating": self.rating,
+            ":flag": self.flag,
+            ":latitude": self.latitude,
+            ":longitude": self.longitude,
+            ":title": &self.title,
+            ":caption": &self.caption,
+            ":notes": &self.notes,
+            ":country": &self.country,
         });
         Ok(if duplicate_row(&res) {
             false
@@ -101,10 +307,6 @@ impl MediaSql {
         })
     }
 
-    pub fn delete(&self, conn: &Connection) -> Result<usize, Error> {
-        Self::delete_path((&self.filepath).into(), conn)
-    }
-
     pub fn delete_path(path: &Path, conn: &Connection) -> Result<usize, Error> {
         let mut stmt = conn.prepare("DELETE FROM media WHERE filepath = :filepath")?;
         stmt.execute(named_params! {
@@ -128,21 +330,126 @@ impl MediaSql {
         })
     }
 
-    pub fn count(conn: &Connection) -> Result<i64, Error> {
-        let mut stmt = conn.prepare("SELECT count(*) from media")?;
-        stmt.query_row([], |row| row.get(0))
+    pub fn set_perceptual_hash(
+        conn: &Connection,
+        filepath: &PathBufSql,
+        perceptual_hash: &PerceptualHashSql,
+    ) -> Result<usize, Error> {
+        let mut stmt = conn.prepare(
+            "UPDATE media
+            SET perceptual_hash = :perceptual_hash
+            WHERE filepath = :filepath",
+        )?;
+        stmt.execute(named_params! {
+            ":perceptual_hash": perceptual_hash,
+            ":filepath": filepath,
+        })
     }
 
-    pub fn count_by_format(conn: &Connection) -> Result<HashMap<Option<String>, i64>, Error> {
-        count_by(conn, "SELECT format, COUNT(*) from media GROUP BY format")
+    pub fn set_modified(
+        conn: &Connection,
+        filepath: &PathBufSql,
+        modified: &DateTime<Utc>,
+    ) -> Result<usize, Error> {
+        let mut stmt = conn.prepare(
+            "UPDATE media
+            SET modified = :modified
+            WHERE filepath = :filepath",
+        )?;
+        stmt.execute(named_params! {
+            ":modified": modified,
+            ":filepath": filepath,
+        })
     }
 
-    pub fn count_by_device(conn: &Connection) -> Result<HashMap<Option<String>, i64>, Error> {
-        count_by(conn, "SELECT device, COUNT(*) from media GROUP BY device")
+    pub fn set_rating(
+        conn: &Connection,
+        filepath: &PathBufSql,
+        rating: u8,
+    ) -> Result<usize, Error> {
+        let mut stmt = conn.prepare(
+            "UPDATE media
+            SET rating = :rating
+            WHERE filepath = :filepath",
+        )?;
+        stmt.execute(named_params! {
+            ":rating": rating,
+            ":filepath": filepath,
+        })
+    }
+
+    pub fn set_flag(
+        conn: &Connection,
+        filepath: &PathBufSql,
+        flag: Option<Flag>,
+    ) -> Result<usize, Error> {
+        let mut stmt = conn.prepare(
+            "UPDATE media
+            SET flag = :flag
+            WHERE filepath = :filepath",
+        )?;
+        stmt.execute(named_params! {
+            ":flag": flag,
+            ":filepath": filepath,
+        })
     }
 
-    pub fn count_by_year(conn: &Connection) -> Result<HashMap<Option<String>, i64>, Error> {
-        count_by(conn, "SELECT strftime('%Y', created), COUNT(*) FROM media WHERE created IS NOT NULL GROUP BY strftime('%Y', created)")
+    /// Set the title, caption and notes of media, `None` clears them
+    pub fn set_text(
+        conn: &Connection,
+        filepath: &PathBufSql,
+        title: Option<&str>,
+        caption: Option<&str>,
+        notes: Option<&str>,
+    ) -> Result<usize, Error> {
+        let mut stmt = conn.prepare(
+            "UPDATE media
+            SET title = :title, caption = :caption, notes = :notes
+            WHERE filepath = :filepath",
+        )?;
+        stmt.execute(named_params! {
+            ":title": title,
+            ":caption": caption,
+            ":notes": notes,
+            ":filepath": filepath,
+        })
+    }
+
+    /// What has been read from the xmp of media, the default if it is not indexed
+    pub fn get_xmp_state(conn: &Connection, filepath: &PathBufSql) -> Result<XmpStateSql, Error> {
+        let mut stmt = conn
+            .prepare("SELECT xmp_modified, regions_read FROM media WHERE filepath = :filepath")?;
+        Ok(stmt
+            .query_row(
+                named_params! {
+                    ":filepath": filepath,
+                },
+                |row| {
+                    Ok(XmpStateSql {
+                        modified: row.get(0)?,
+                        regions_read: row.get(1)?,
+                    })
+                },
+            )
+            .optional()?
+            .unwrap_or_default())
+    }
+
+    pub fn set_xmp_state(
+        conn: &Connection,
+        filepath: &PathBufSql,
+        state: &XmpStateSql,
+    ) -> Result<usize, Error> {
+        let mut stmt = conn.prepare(
+            "UPDATE media
+            SET xmp_modified = :xmp_modified, regions_read = :regions_read
+            WHERE filepath = :filepath",
+        )?;
+        stmt.execute(named_params! {
+            ":xmp_modified": state.modified,
+            ":regions_read": state.regions_read,
+            ":filepath": filepath,
+        })
     }
 
     pub fn get_by_filepath(
@@ -161,7 +468,6 @@ impl MediaSql {
         .optional()
     }
 
-    #[allow(dead_code)]
     pub fn exists_by_hash(conn: &Connection, hash: HashSql) -> Result<bool, Error> {
         let mut stmt = conn.prepare("SELECT 1 FROM media WHERE hash = :hash")?;
         stmt.exists(named_params! {
@@ -171,42 +477,200 @@ impl MediaSql {
 }
 
 impl MediaSearch<'_> {
-    pub fn new(conn: &Connection, filter: MediaFilter) -> Result<MediaSearch, Error> {
-        let mut sql = match filter.label {
-            Some(_) => formatcp!(
-                "SELECT {COLUMNS_WITH_PREFIX} FROM label \
-                    JOIN media ON media.filepath = label.filepath \
-                    WHERE label = :label",
+    pub fn new(conn: &Connection, filter: MediaFilter) -> Result<MediaSearch<'_>, Error> {
+        let FilterSql {
+            mut sql,
+            ranked,
+            mut params,
+        } = filter.to_sql();
+        let direction = if filter.descending { "DESC" } else { "ASC" };
+        let order = match filter.sort {
+            None if filter.album.is_some() => format!(
+                "(SELECT position FROM album_item \
+                    WHERE album_item.album_id = {ALBUM_ID} \
+                    AND album_item.filepath = media.filepath) {direction}"
             ),
-            None => formatcp!(
-                "SELECT {COLUMNS} FROM media \
-                    WHERE true",
-            ),
-        }
-        .to_string();
-        if filter.device.is_some() {
-            sql.push_str("\nAND device = :device");
-        }
-        if filter.format.is_some() {
-            sql.push_str("\nAND format = :format");
-        }
-        if filter.created_start.is_some() {
-            sql.push_str("\nAND created >= :created_start");
-        }
-        if filter.created_end.is_some() {
-            sql.push_str("\nAND created <= :created_end");
+            None if ranked => format!("media_fts.rank {direction}"),
+            None | Some(SortKey::Created) => {
+                format!("created {direction}, filepath {direction}")
+            }
+            Some(SortKey::Modified) => format!("modified {direction}, filepath {direction}"),
+            Some(SortKey::Size) => format!("size {direction}, filepath {direction}"),
+            Some(SortKey::Path) => format!("filepath {direction}"),
+            Some(SortKey::Random) => "random()".to_string(),
+        };
+        sql = format!("SELECT {COLUMNS} {sql}\nORDER BY {order}");
+        if filter.limit.is_some() || filter.offset.is_some() {
+            sql.push_str("\nLIMIT :limit OFFSET :offset");
+            // A negative limit means no limit
+            let limit = filter.limit.map_or(-1, |limit| limit as i64);
+            params.push((":limit".to_string(), Value::Integer(limit)));
+            let offset = filter.offset.unwrap_or_default() as i64;
+            params.push((":offset".to_string(), Value::Integer(offset)));
         }
-        sql.push_str("\nORDER BY created");
         let statement = conn.prepare(&sql)?;
-        Ok(MediaSearch { statement, filter })
+        Ok(MediaSearch {
+            statement,
+            filter,
+            params,
+        })
+    }
+
+    /// Count the media matching a filter, ignoring its `limit` and `offset`
+    pub fn count(conn: &Connection, filter: &MediaFilter) -> Result<i64, Error> {
+        let FilterSql { sql, params, .. } = filter.to_sql();
+        let mut statement = conn.prepare(&format!("SELECT COUNT(*) {sql}"))?;
+        statement.query_row(filter.all_params(&params).as_slice(), |row| row.get(0))
+    }
+
+    /// Count, size and missing fields of the media matching a filter, ignoring its `limit` and
+    /// `offset`
+    pub fn totals(conn: &Connection, filter: &MediaFilter) -> Result<MediaTotals, Error> {
+        let FilterSql { sql, params, .. } = filter.to_sql();
+        let mut statement = conn.prepare(&format!(
+            "SELECT COUNT(*), COALESCE(SUM(media.size), 0), \
+                COALESCE(SUM(media.created IS NULL), 0), \
+                COALESCE(SUM(media.location IS NULL), 0), \
+                COALESCE(SUM(media.hash IS NULL), 0) \
+                {sql}"
+        ))?;
+        statement.query_row(filter.all_params(&params).as_slice(), |row| {
+            Ok(MediaTotals {
+                count: row.get(0)?,
+                bytes: row.get(1)?,
+                missing_created: row.get(2)?,
+                missing_location: row.get(3)?,
+                missing_hash: row.get(4)?,
+            })
+        })
     }
 
-    pub fn new_with_filter_defaults(conn: &Connection) -> Result<MediaSearch, Error> {
+    /// Duplicates among the media matching a filter, ignoring its `limit` and `offset`
+    pub fn duplicate_totals(
+        conn: &Connection,
+        filter: &MediaFilter,
+    ) -> Result<MediaDuplicateTotals, Error> {
+        let FilterSql { sql, params, .. } = filter.to_sql();
+        let mut statement = conn.prepare(&format!(
+            "SELECT COALESCE(SUM(copies), 0), COALESCE(SUM(bytes - largest), 0) FROM (\
+                SELECT COUNT(*) AS copies, SUM(media.size) AS bytes, MAX(media.size) AS largest \
+                {sql}\nAND media.hash IS NOT NULL \
+                GROUP BY media.hash HAVING COUNT(*) > 1)"
+        ))?;
+        statement.query_row(filter.all_params(&params).as_slice(), |row| {
+            Ok(MediaDuplicateTotals {
+                count: row.get(0)?,
+                wasted_bytes: row.get(1)?,
+            })
+        })
+    }
+
+    /// First and last created of the media matching a filter, `None` when none have created
+    pub fn created_range(
+        conn: &Connection,
+        filter: &MediaFilter,
+    ) -> Result<Option<CreatedRange>, Error> {
+        let FilterSql { sql, params, .. } = filter.to_sql();
+        let mut statement = conn.prepare(&format!(
+            "SELECT MIN(media.created), MAX(media.created) {sql}"
+        ))?;
+        statement.query_row(filter.all_params(&params).as_slice(), |row| {
+            let first: Option<DateTime<Utc>> = row.get(0)?;
+            let last: Option<DateTime<Utc>> = row.get(1)?;
+            Ok(first.zip(last))
+        })
+    }
+
+    /// Latitude and longitude of the geotagged media matching a filter, ignoring its `limit` and
+    /// `offset`
+    pub fn coordinates(conn: &Connection, filter: &MediaFilter) -> Result<Vec<(f64, f64)>, Error> {
+        let FilterSql { sql, params, .. } = filter.to_sql();
+        let mut statement = conn.prepare(&format!(
+            "SELECT media.latitude, media.longitude {sql}\n\
+                AND media.latitude IS NOT NULL AND media.longitude IS NOT NULL"
+        ))?;
+        let iter = statement.query_map(filter.all_params(&params).as_slice(), |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+        iter.collect()
+    }
+
+    /// Count the media matching a filter by the value of `key`
+    pub fn count_by<K: FromSql + Eq + Hash>(
+        conn: &Connection,
+        filter: &MediaFilter,
+        key: &str,
+    ) -> Result<HashMap<K, i64>, Error> {
+        Self::group_by(conn, filter, key, "COUNT(*)", "true")
+    }
+
+    /// Sum the size of the media matching a filter by the value of `key`
+    pub fn bytes_by<K: FromSql + Eq + Hash>(
+        conn: &Connection,
+        filter: &MediaFilter,
+        key: &str,
+    ) -> Result<HashMap<K, i64>, Error> {
+        Self::group_by(conn, filter, key, "SUM(media.size)", "true")
+    }
+
+    /// Count the media matching a filter by a number formatted from created with `strftime`,
+    /// media without created are left out
+    pub fn count_by_created<K: FromSql + Eq + Hash>(
+        conn: &Connection,
+        filter: &MediaFilter,
+        format: &str,
+    ) -> Result<HashMap<K, i64>, Error> {
+        Self::group_by(
+            conn,
+            filter,
+            &format!("CAST(strftime('{format}', media.created) AS INTEGER)"),
+            "COUNT(*)",
+            "media.created IS NOT NULL",
+        )
+    }
+
+    /// Count the media matching a filter with each label, not counting the labels below it
+    pub fn count_by_label(
+        conn: &Connection,
+        filter: &MediaFilter,
+    ) -> Result<HashMap<String, i64>, Error> {
+        let FilterSql { sql, params, .. } = filter.to_sql();
+        let mut statement = conn.prepare(&format!(
+            "SELECT label.label, COUNT(*) FROM label \
+                WHERE label.filepath IN (SELECT media.filepath {sql}) \
+                GROUP BY label.label"
+        ))?;
+        let iter = statement.query_and_then(filter.all_params(&params).as_slice(), |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+        iter.collect()
+    }
+
+    /// Aggregate the media matching a filter and `condition` grouped by `key`, ignoring the
+    /// filter's `limit` and `offset`
+    fn group_by<K: FromSql + Eq + Hash>(
+        conn: &Connection,
+        filter: &MediaFilter,
+        key: &str,
+        aggregate: &str,
+        condition: &str,
+    ) -> Result<HashMap<K, i64>, Error> {
+        let FilterSql { sql, params, .. } = filter.to_sql();
+        let mut statement = conn.prepare(&format!(
+            "SELECT {key}, {aggregate} {sql}\nAND {condition} GROUP BY 1"
+        ))?;
+        let iter = statement.query_and_then(filter.all_params(&params).as_slice(), |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+        iter.collect()
+    }
+
+    pub fn new_with_filter_defaults(conn: &Connection) -> Result<MediaSearch<'_>, Error> {
         Self::new(conn, MediaFilter::default())
     }
 
     pub fn iter(&mut self) -> Result<impl Iterator<Item = Result<MediaSql, Error>> + '_, Error> {
-        let params = self.filter.to_params();
+        let params = self.filter.all_params(&self.params);
         let iter = self
             .statement
             .query_map(params.as_slice(), |row| MediaSql::try_from(row))?;
@@ -214,7 +678,234 @@ impl MediaSearch<'_> {
     }
 }
 
+/// `FROM` and `WHERE` clauses selecting the media matching a filter
+struct FilterSql {
+    sql: String,
+    /// Whether the full text search table is joined so media can be ordered by rank
+    ranked: bool,
+    /// Names and values of the params generated while compiling the filter
+    params: Vec<(String, Value)>,
+}
+
+impl LabelQuery {
+    /// Compile the query into a SQL condition on `media.filepath`
+    ///
+    /// Each label becomes an `EXISTS` subquery which is answered by the unique index on
+    /// `(filepath, label)` and also matches the descendants of the label. The labels are added
+    /// to `params` as named params.
+    fn to_sql(&self, params: &mut Vec<(String, Value)>) -> String {
+        let join = |queries: &[LabelQuery], operator: &str, params: &mut Vec<_>| {
+            queries
+                .iter()
+                .map(|query| query.to_sql(params))
+                .collect::<Vec<_>>()
+                .join(operator)
+        };
+        match self {
+            Self::Label(label) => {
+                let name = format!(":label_{}", params.len());
+                let sql = format!(
+                    "EXISTS (SELECT 1 FROM label \
+                        WHERE label.filepath = media.filepath AND {})",
+                    label_or_descendant_sql("label.label", &name)
+                );
+                params.push((name, Value::Text(label.clone())));
+                sql
+            }
+            Self::AllOf(queries) if queries.is_empty() => "true".to_string(),
+            Self::AllOf(queries) => format!("({})", join(queries, " AND ", params)),
+            Self::AnyOf(queries) if queries.is_empty() => "false".to_string(),
+            Self::AnyOf(queries) => format!("({})", join(queries, " OR ", params)),
+            Self::NoneOf(queries) if queries.is_empty() => "true".to_string(),
+            Self::NoneOf(queries) => format!("NOT ({})", join(queries, " OR ", params)),
+        }
+    }
+}
+
+impl From<String> for LabelQuery {
+    fn from(label: String) -> Self {
+        Self::Label(label)
+    }
+}
+
+impl From<&str> for LabelQuery {
+    fn from(label: &str) -> Self {
+        Self::Label(label.to_string())
+    }
+}
+
 impl MediaFilter {
+    fn to_sql(&self) -> FilterSql {
+        let text = SearchSql::match_expression(&self.text);
+        let ranked = text.is_some();
+        let mut sql = match text {
+            Some(_) => {
+                "FROM media \
+                JOIN media_fts ON media_fts.media_filepath = media.filepath \
+                WHERE media_fts MATCH :text"
+            }
+            None => "FROM media WHERE true",
+        }
+        .to_string();
+        let mut params = Vec::new();
+        if let Some(text) = text {
+            params.push((":text".to_string(), Value::Text(text)));
+        }
+        if let Some(label) = &self.label {
+            sql.push_str("\nAND ");
+            sql.push_str(&label.to_sql(&mut params));
+        }
+        if self.album.is_some() {
+            sql.push_str(formatcp!(
+                "\nAND media.filepath IN \
+                    (SELECT filepath FROM album_item WHERE album_id = {ALBUM_ID})"
+            ));
+        }
+        if self.event.is_some() {
+            sql.push_str(
+                "\nAND media.filepath IN (SELECT filepath FROM event_item WHERE event_id = :event)",
+            );
+        }
+        if self.place.is_some() {
+            sql.push_str("\nAND ");
+            sql.push_str(&media_in_place_sql(":place"));
+        }
+        for person in &self.people {
+            let name = format!(":person_{}", params.len());
+            sql.push_str("\nAND ");
+            sql.push_str(&media_with_person_sql(&name));
+            params.push((name, Value::Text(person.clone())));
+        }
+        if let Some(near) = &self.near {
+            // Compare distances on a plane scaled to the latitude, which is accurate enough for
+            // the radius of a city and only needs arithmetic in SQL
+            let longitude_scale = near.latitude.to_radians().cos();
+            let radius = near.radius_km / KM_PER_DEGREE;
+            sql.push_str(&format!(
+                "\nAND latitude BETWEEN {lat} - {radius} AND {lat} + {radius} \
+                AND (latitude - {lat}) * (latitude - {lat}) \
+                    + {lon} * {lon} * {scale} * {scale} \
+                    <= {radius} * {radius}",
+                lat = ":near_latitude",
+                lon = longitude_difference_sql("longitude", ":near_longitude"),
+                scale = ":near_longitude_scale",
+                radius = ":near_radius",
+            ));
+            params.push((":near_latitude".to_string(), Value::Real(near.latitude)));
+            params.push((":near_longitude".to_string(), Value::Real(near.longitude)));
+            params.push((
+                ":near_longitude_scale".to_string(),
+                Value::Real(longitude_scale),
+            ));
+            params.push((":near_radius".to_string(), Value::Real(radius)));
+        }
+        if let Some(within) = &self.within {
+            let longitude = if within.west <= within.east {
+                "longitude BETWEEN :within_west AND :within_east"
+            } else {
+                "(longitude >= :within_west OR longitude <= :within_east)"
+            };
+            sql.push_str(&format!(
+                "\nAND latitude BETWEEN :within_south AND :within_north AND {longitude}"
+            ));
+            params.push((":within_south".to_string(), Value::Real(within.south)));
+            params.push((":within_west".to_string(), Value::Real(within.west)));
+            params.push((":within_north".to_string(), Value::Real(within.north)));
+            params.push((":within_east".to_string(), Value::Real(within.east)));
+        }
+        if self.device.is_some() {
+            sql.push_str("\nAND device = :device");
+        }
+        if self.format.is_some() {
+            sql.push_str("\nAND format = :format");
+        }
+        if self.created_start.is_some() {
+            sql.push_str("\nAND created >= :created_start");
+        }
+        if self.created_end.is_some() {
+            sql.push_str("\nAND created <= :created_end");
+        }
+        if let Some(month_day) = &self.month_day {
+            let operator = if month_day.start <= month_day.end {
+                "AND"
+            } else {
+                "OR"
+            };
+            sql.push_str(&format!(
+                "\nAND (strftime('%m-%d', created) >= :month_day_start \
+                    {operator} strftime('%m-%d', created) <= :month_day_end)"
+            ));
+            params.push((
+                ":month_day_start".to_string(),
+                Value::Text(month_day.start.to_sql_text()),
+            ));
+            params.push((
+                ":month_day_end".to_string(),
+                Value::Text(month_day.end.to_sql_text()),
+            ));
+        }
+        if self.min_rating.is_some() {
+            sql.push_str("\nAND rating >= :min_rating");
+        }
+        if self.flag.is_some() {
+            sql.push_str("\nAND flag = :flag");
+        }
+        FilterSql {
+            sql,
+            ranked,
+            params,
+        }
+    }
+
+    /// Params of the filter followed by `params` generated by `to_sql`
+    fn all_params<'a>(&'a self, params: &'a [(String, Value)]) -> Vec<(&'a str, &'a dyn ToSql)> {
+        let mut all_params = self.to_params();
+        for (name, value) in params {
+            all_params.push((name.as_str(), value as &dyn ToSql));
+        }
+        all_params
+    }
+
+    /// Combine with another filter so media must match both
+    ///
+    /// Fields that hold a single value are taken from `self` when both filters set them.
+    pub fn and(self, other: MediaFilter) -> MediaFilter {
+        let mut text = self.text;
+        text.extend(other.text);
+        let mut people = self.people;
+        people.extend(other.people);
+        MediaFilter {
+            created_start: self.created_start.or(other.created_start),
+            created_end: self.created_end.or(other.created_end),
+            label: match (self.label, other.label) {
+                (Some(label), Some(other_label)) => {
+                    Some(LabelQuery::AllOf(vec![label, other_label]))
+                }
+                (label, other_label) => label.or(other_label),
+            },
+            device: self.device.or(other.device),
+            format: self.format.or(other.format),
+            min_rating: self.min_rating.or(other.min_rating),
+            flag: self.flag.or(other.flag),
+            near: self.near.or(other.near),
+            within: self.within.or(other.within),
+            text,
+            album: self.album.or(other.album),
+            people,
+            event: self.event.or(other.event),
+            place: self.place.or(other.place),
+            month_day: self.month_day.or(other.month_day),
+            descending: if self.sort.is_some() {
+                self.descending
+            } else {
+                other.descending
+            },
+            sort: self.sort.or(other.sort),
+            offset: self.offset.or(other.offset),
+            limit: self.limit.or(other.limit),
+        }
+    }
+
     /// Convert the media filters into a type that can impl `Params`.
     ///
     /// We cannot impl `Params` directly because it is sealed and we cannot use `named_params`
@@ -227,15 +918,27 @@ impl MediaFilter {
         if let Some(created_end) = &self.created_end {
             result.push((":created_end", created_end as &dyn ToSql))
         }
-        if let Some(label) = &self.label {
-            result.push((":label", label as &dyn ToSql))
-        }
         if let Some(device) = &self.device {
             result.push((":device", device as &dyn ToSql))
         }
         if let Some(format) = &self.format {
             result.push((":format", format as &dyn ToSql))
         }
+        if let Some(min_rating) = &self.min_rating {
+            result.push((":min_rating", min_rating as &dyn ToSql))
+        }
+        if let Some(flag) = &self.flag {
+            result.push((":flag", flag as &dyn ToSql))
+        }
+        if let Some(album) = &self.album {
+            result.push((":album", album as &dyn ToSql))
+        }
+        if let Some(event) = &self.event {
+            result.push((":event", event as &dyn ToSql))
+        }
+        if let Some(place) = &self.place {
+            result.push((":place", place as &dyn ToSql))
+        }
         result
     }
 }
@@ -243,13 +946,17 @@ impl MediaFilter {
 impl<'conn> MediaDuplicates<'conn> {
     pub fn new(conn: &'conn Connection) -> Result<Self, Error> {
         let statement = conn.prepare(
-            "SELECT m.* FROM media m
+            "SELECT m.filepath, m.size, m.format, m.created, m.modified, m.location, m.device,
+                    m.hash, m.perceptual_hash, m.rating, m.flag, m.latitude, m.longitude,
+                    m.title, m.caption, m.notes, m.country
+                    FROM media m
                     JOIN (
                         SELECT hash
                         FROM media
                         GROUP BY hash
                         HAVING COUNT(*) > 1
-                    ) AS duplicates ON m.hash = duplicates.hash;",
+                    ) AS duplicates ON m.hash = duplicates.hash
+                    ORDER BY m.hash, m.filepath;",
         )?;
         Ok(Self { statement })
     }
@@ -263,13 +970,45 @@ impl<'conn> MediaDuplicates<'conn> {
 }
 
 impl<'conn> MediaNewFromImport<'conn> {
+    /// Media in the attached import index whose hash is not in this index
+    ///
+    /// The import index may be from an older version of glance so columns it is missing are
+    /// read as their defaults.
     pub fn new(conn: &'conn Connection) -> Result<Self, Error> {
-        let statement = conn.prepare(
-            "SELECT import.*
-                 FROM import.media AS import
+        let import_columns = table_columns(conn, IMPORT_DB, "media")?;
+        let columns = COLUMNS
+            .split(", ")
+            .map(|column| match column {
+                _ if import_columns.iter().any(|c| c == column) => format!("import.{column}"),
+                "rating" => "0".to_string(),
+                _ => "NULL".to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let statement = conn.prepare(&format!(
+            "SELECT {columns}
+                 FROM {IMPORT_DB}.media AS import
                  LEFT JOIN media ON import.hash = media.hash
-                 WHERE media.hash IS NULL",
-        )?;
+                 WHERE media.hash IS NULL"
+        ))?;
+        Ok(Self { statement })
+    }
+
+    pub fn iter(&mut self) -> Result<impl Iterator<Item = Result<MediaSql, Error>> + '_, Error> {
+        let iter = self
+            .statement
+            .query_map([], |row| MediaSql::try_from(row))?;
+        Ok(iter)
+    }
+}
+
+impl<'conn> MediaWithPerceptualHash<'conn> {
+    pub fn new(conn: &'conn Connection) -> Result<Self, Error> {
+        let statement = conn.prepare(formatcp!(
+            "SELECT {COLUMNS} FROM media \
+                WHERE perceptual_hash IS NOT NULL \
+                ORDER BY filepath"
+        ))?;
         Ok(Self { statement })
     }
 
@@ -294,16 +1033,73 @@ impl TryFrom<&Row<'_>> for MediaSql {
             location: row.get(5)?,
             device: row.get(6)?,
             hash: row.get(7)?,
+            perceptual_hash: row.get(8)?,
+            rating: row.get(9)?,
+            flag: row.get(10)?,
+            latitude: row.get(11)?,
+            longitude: row.get(12)?,
+            title: row.get(13)?,
+            caption: row.get(14)?,
+            notes: row.get(15)?,
+            country: row.get(16)?,
         })
     }
 }
 
+/// SQL for the difference between two longitudes wrapped into [-180, 180] so that points either
+/// side of the antimeridian are close
+pub(crate) fn longitude_difference_sql(a: &str, b: &str) -> String {
+    format!("(({a} - {b}) - 360.0 * round(({a} - {b}) / 360.0))")
+}
+
 fn duplicate_row(res: &Result<i64, rusqlite::Error>) -> bool {
     matches!(res.as_ref().err().and_then(|e| e.sqlite_error_code()), Some(e) if e == ErrorCode::ConstraintViolation)
 }
 
-fn count_by(conn: &Connection, query: &str) -> Result<HashMap<Option<String>, i64>, Error> {
-    let mut stmt = conn.prepare(query)?;
-    let iter = stmt.query_and_then([], |row| Ok((row.get(0)?, row.get(1)?)))?;
-    iter.collect()
+/// Add a column to an existing table
+///
+/// `CREATE TABLE IF NOT EXISTS` does not modify tables created by older versions of glance so new
+/// columns must be added explicitly.
+fn add_column_if_missing(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<(), Error> {
+    if !table_columns(conn, "main", table)?
+        .iter()
+        .any(|c| c == column)
+    {
+        conn.execute(
+            &format!("ALTER TABLE {table} ADD COLUMN {column} {definition}"),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+pub(crate) fn table_columns(
+    conn: &Connection,
+    schema: &str,
+    table: &str,
+) -> Result<Vec<String>, Error> {
+    let mut stmt = conn.prepare(&format!("PRAGMA {schema}.table_info({table})"))?;
+    let columns = stmt
+        .query_map([], |row| row.get(1))?
+        .collect::<Result<_, _>>()?;
+    Ok(columns)
+}
+
+/// SQLite URI that opens the database at `path` read-only
+fn read_only_uri(path: &Path) -> String {
+    let path = path.display().to_string();
+    let mut uri = String::from("file:");
+    for c in path.chars() {
+        match c {
+            '%' | '?' | '#' => uri.push_str(&format!("%{:02X}", c as u32)),
+            _ => uri.push(c),
+        }
+    }
+    uri.push_str("?mode=ro");
+    uri
 }
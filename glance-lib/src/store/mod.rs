@@ -1,5 +1,11 @@
+pub(crate) mod album_sql;
 pub(crate) mod converters;
+pub(crate) mod event_sql;
 pub(crate) mod label_sql;
 pub(crate) mod media_sql;
+pub(crate) mod people_sql;
+pub(crate) mod place_sql;
+pub(crate) mod saved_search_sql;
+pub(crate) mod search_sql;
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,241 @@
+use const_format::formatcp;
+use rusqlite::{named_params, Connection, Error, OptionalExtension, Row};
+
+use super::{converters::PathBufSql, media_sql::IMPORT_DB};
+use crate::index::people::RegionSource;
+
+/// Low level type for interacting with face region rows
+#[derive(Debug)]
+pub(crate) struct FaceRegionSql {
+    pub id: i64,
+    pub filepath: PathBufSql,
+    /// Name of the person in the region, `None` for unknown faces
+    pub person: Option<String>,
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+    pub source: RegionSource,
+}
+
+/// Low level type for people with the number of media they are in
+#[derive(Debug)]
+pub(crate) struct PersonSql {
+    pub name: String,
+    pub count: i64,
+}
+
+/// SQL condition that media shows the person named by `param`
+pub(crate) fn media_with_person_sql(param: &str) -> String {
+    format!(
+        "EXISTS (SELECT 1 FROM face_region \
+            JOIN person ON person.id = face_region.person_id \
+            WHERE face_region.filepath = media.filepath AND person.name = {param})"
+    )
+}
+
+impl FaceRegionSql {
+    pub fn create_table(conn: &mut Connection) -> Result<(), Error> {
+        let transaction = conn.transaction()?;
+        transaction.execute_batch(
+            "CREATE TABLE IF NOT EXISTS person (
+                    id INTEGER PRIMARY KEY,
+                    name TEXT NOT NULL UNIQUE,
+                    CHECK(name <> '')
+                );
+            CREATE TABLE IF NOT EXISTS face_region (
+                    id INTEGER PRIMARY KEY,
+                    filepath TEXT NOT NULL,
+                    person_id INTEGER,
+                    x REAL NOT NULL,
+                    y REAL NOT NULL,
+                    width REAL NOT NULL,
+                    height REAL NOT NULL,
+                    source TEXT NOT NULL,
+                    FOREIGN KEY (filepath) REFERENCES media(filepath),
+                    FOREIGN KEY (person_id) REFERENCES person(id)
+                );
+            CREATE INDEX IF NOT EXISTS face_region_filepath_index ON face_region (filepath);
+            CREATE INDEX IF NOT EXISTS face_region_person_index ON face_region (person_id);
+            CREATE TRIGGER IF NOT EXISTS face_region_media_delete AFTER DELETE ON media BEGIN
+                DELETE FROM face_region WHERE filepath = old.filepath;
+            END;
+            CREATE TRIGGER IF NOT EXISTS face_region_media_rename
+            AFTER UPDATE OF filepath ON media BEGIN
+                UPDATE face_region SET filepath = new.filepath WHERE filepath = old.filepath;
+            END;",
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Insert the region, creating its person if needed, and return its id
+    pub fn insert(&self, conn: &Connection) -> Result<i64, Error> {
+        let person_id = self
+            .person
+            .as_deref()
+            .map(|name| person_id(conn, name))
+            .transpose()?;
+        let mut stmt = conn.prepare(
+            "INSERT INTO face_region (filepath, person_id, x, y, width, height, source) \
+                VALUES (:filepath, :person_id, :x, :y, :width, :height, :source)",
+        )?;
+        stmt.insert(named_params! {
+            ":filepath": self.filepath,
+            ":person_id": person_id,
+            ":x": self.x,
+            ":y": self.y,
+            ":width": self.width,
+            ":height": self.height,
+            ":source": self.source,
+        })
+    }
+
+    /// Update the person and rectangle of the region with `self.id`
+    ///
+    /// Returns the number of rows updated.
+    pub fn update(&self, conn: &Connection) -> Result<usize, Error> {
+        let person_id = self
+            .person
+            .as_deref()
+            .map(|name| person_id(conn, name))
+            .transpose()?;
+        let mut stmt = conn.prepare(
+            "UPDATE face_region \
+                SET person_id = :person_id, x = :x, y = :y, width = :width, height = :height, \
+                    source = :source \
+                WHERE id = :id",
+        )?;
+        stmt.execute(named_params! {
+            ":id": self.id,
+            ":person_id": person_id,
+            ":x": self.x,
+            ":y": self.y,
+            ":width": self.width,
+            ":height": self.height,
+            ":source": self.source,
+        })
+    }
+
+    pub fn delete(conn: &Connection, id: i64) -> Result<usize, Error> {
+        conn.execute(
+            "DELETE FROM face_region WHERE id = :id",
+            named_params! { ":id": id },
+        )
+    }
+
+    /// Delete the regions of media that were read from its metadata, keeping those made by hand
+    pub fn delete_from_metadata(conn: &Connection, filepath: &PathBufSql) -> Result<usize, Error> {
+        conn.execute(
+            "DELETE FROM face_region WHERE filepath = :filepath AND source = :source",
+            named_params! {
+                ":filepath": filepath,
+                ":source": RegionSource::Metadata,
+            },
+        )
+    }
+
+    pub fn get_by_id(conn: &Connection, id: i64) -> Result<Option<FaceRegionSql>, Error> {
+        let mut stmt = conn.prepare(
+            "SELECT face_region.id, filepath, person.name, x, y, width, height, source \
+                FROM face_region LEFT JOIN person ON person.id = face_region.person_id \
+                WHERE face_region.id = :id",
+        )?;
+        stmt.query_row(named_params! { ":id": id }, |row| {
+            FaceRegionSql::try_from(row)
+        })
+        .optional()
+    }
+
+    pub fn get_by_filepath(
+        conn: &Connection,
+        filepath: &PathBufSql,
+    ) -> Result<Vec<FaceRegionSql>, Error> {
+        let mut stmt = conn.prepare(
+            "SELECT face_region.id, filepath, person.name, x, y, width, height, source \
+                FROM face_region LEFT JOIN person ON person.id = face_region.person_id \
+                WHERE filepath = :filepath \
+                ORDER BY x, y",
+        )?;
+        let iter = stmt.query_map(named_params! { ":filepath": filepath }, |row| {
+            FaceRegionSql::try_from(row)
+        })?;
+        iter.collect()
+    }
+
+    /// Regions of media in the attached import index, which has none if it is from a version of
+    /// glance without face regions
+    pub fn get_imported(
+        conn: &Connection,
+        filepath: &PathBufSql,
+    ) -> Result<Vec<FaceRegionSql>, Error> {
+        let has_regions = conn
+            .prepare(formatcp!(
+                "SELECT 1 FROM {IMPORT_DB}.sqlite_master WHERE type = 'table' AND name = 'face_region'"
+            ))?
+            .exists([])?;
+        if !has_regions {
+            return Ok(Vec::new());
+        }
+        let mut stmt = conn.prepare(formatcp!(
+            "SELECT face_region.id, filepath, person.name, x, y, width, height, source \
+                FROM {IMPORT_DB}.face_region AS face_region \
+                LEFT JOIN {IMPORT_DB}.person AS person ON person.id = face_region.person_id \
+                WHERE filepath = :filepath \
+                ORDER BY x, y"
+        ))?;
+        let iter = stmt.query_map(named_params! { ":filepath": filepath }, |row| {
+            FaceRegionSql::try_from(row)
+        })?;
+        iter.collect()
+    }
+}
+
+impl PersonSql {
+    /// People in at least one media ordered by name
+    pub fn get_all(conn: &Connection) -> Result<Vec<PersonSql>, Error> {
+        let mut stmt = conn.prepare(
+            "SELECT person.name, COUNT(DISTINCT face_region.filepath) FROM person \
+                JOIN face_region ON face_region.person_id = person.id \
+                GROUP BY person.id \
+                ORDER BY person.name",
+        )?;
+        let iter = stmt.query_map([], |row| {
+            Ok(PersonSql {
+                name: row.get(0)?,
+                count: row.get(1)?,
+            })
+        })?;
+        iter.collect()
+    }
+}
+
+/// Id of the person with the name, creating them if needed
+fn person_id(conn: &Connection, name: &str) -> Result<i64, Error> {
+    conn.execute(
+        "INSERT OR IGNORE INTO person (name) VALUES (:name)",
+        named_params! { ":name": name },
+    )?;
+    conn.query_row(
+        "SELECT id FROM person WHERE name = :name",
+        named_params! { ":name": name },
+        |row| row.get(0),
+    )
+}
+
+impl TryFrom<&Row<'_>> for FaceRegionSql {
+    type Error = Error;
+
+    fn try_from(row: &Row<'_>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: row.get(0)?,
+            filepath: row.get(1)?,
+            person: row.get(2)?,
+            x: row.get(3)?,
+            y: row.get(4)?,
+            width: row.get(5)?,
+            height: row.get(6)?,
+            source: row.get(7)?,
+        })
+    }
+}
@@ -0,0 +1,99 @@
+use rusqlite::{named_params, Connection, Error, OptionalExtension, Row};
+
+use super::media_sql::longitude_difference_sql;
+
+/// Kilometres per degree of latitude
+pub(crate) const KM_PER_DEGREE: f64 = 111.32;
+
+/// Low level type for interacting with place rows
+#[derive(Debug)]
+pub(crate) struct PlaceSql {
+    pub name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub radius_km: f64,
+}
+
+/// SQL condition that media was taken inside the place named by `param`
+///
+/// Distances are compared on a plane scaled to the latitude of the place like `MediaFilter::near`,
+/// with the scale stored alongside the place so the condition only needs arithmetic.
+pub(crate) fn media_in_place_sql(param: &str) -> String {
+    let longitude = longitude_difference_sql("media.longitude", "place.longitude");
+    format!(
+        "EXISTS (SELECT 1 FROM place WHERE place.name = {param} \
+            AND (media.latitude - place.latitude) * (media.latitude - place.latitude) \
+                + {longitude} * {longitude} * place.longitude_scale * place.longitude_scale \
+                <= (place.radius_km / {KM_PER_DEGREE}) * (place.radius_km / {KM_PER_DEGREE}))"
+    )
+}
+
+impl PlaceSql {
+    pub fn create_table(conn: &mut Connection) -> Result<(), Error> {
+        let transaction = conn.transaction()?;
+        transaction.execute(
+            "CREATE TABLE IF NOT EXISTS place (
+                    name TEXT NOT NULL PRIMARY KEY,
+                    latitude REAL NOT NULL,
+                    longitude REAL NOT NULL,
+                    radius_km REAL NOT NULL,
+                    longitude_scale REAL NOT NULL,
+                    CHECK(name <> '')
+                );",
+            [],
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Insert the place or replace the place with the same name
+    pub fn upsert(&self, conn: &Connection) -> Result<usize, Error> {
+        let mut stmt = conn.prepare(
+            "INSERT OR REPLACE INTO place (name, latitude, longitude, radius_km, longitude_scale) \
+                VALUES (:name, :latitude, :longitude, :radius_km, :longitude_scale)",
+        )?;
+        stmt.execute(named_params! {
+            ":name": self.name,
+            ":latitude": self.latitude,
+            ":longitude": self.longitude,
+            ":radius_km": self.radius_km,
+            ":longitude_scale": self.latitude.to_radians().cos(),
+        })
+    }
+
+    pub fn delete(conn: &Connection, name: &str) -> Result<usize, Error> {
+        conn.execute(
+            "DELETE FROM place WHERE name = :name",
+            named_params! { ":name": name },
+        )
+    }
+
+    pub fn get_by_name(conn: &Connection, name: &str) -> Result<Option<PlaceSql>, Error> {
+        let mut stmt = conn
+            .prepare("SELECT name, latitude, longitude, radius_km FROM place WHERE name = :name")?;
+        stmt.query_row(named_params! { ":name": name }, |row| {
+            PlaceSql::try_from(row)
+        })
+        .optional()
+    }
+
+    pub fn get_all(conn: &Connection) -> Result<Vec<PlaceSql>, Error> {
+        let mut stmt =
+            conn.prepare("SELECT name, latitude, longitude, radius_km FROM place ORDER BY name")?;
+        let iter = stmt.query_map([], |row| PlaceSql::try_from(row))?;
+        iter.collect()
+    }
+}
+
+impl TryFrom<&Row<'_>> for PlaceSql {
+    type Error = Error;
+
+    fn try_from(row: &Row<'_>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            name: row.get(0)?,
+            latitude: row.get(1)?,
+            longitude: row.get(2)?,
+            radius_km: row.get(3)?,
+        })
+    }
+}
@@ -0,0 +1,64 @@
+use rusqlite::{named_params, Connection, Error, OptionalExtension};
+
+/// Low level type for interacting with saved search rows
+#[derive(Debug)]
+pub(crate) struct SavedSearchSql {
+    pub name: String,
+    /// `MediaFilter` serialized as JSON
+    pub filter: String,
+}
+
+impl SavedSearchSql {
+    pub fn create_table(conn: &mut Connection) -> Result<(), Error> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS saved_search (
+                    name TEXT NOT NULL PRIMARY KEY,
+                    filter TEXT NOT NULL,
+                    CHECK(name <> '')
+                );",
+            [],
+        )?;
+        Ok(())
+    }
+
+    /// Insert the saved search, replacing any with the same name
+    pub fn upsert(&self, conn: &Connection) -> Result<usize, Error> {
+        let mut stmt = conn.prepare(
+            "INSERT INTO saved_search (name, filter) VALUES (:name, :filter) \
+                ON CONFLICT (name) DO UPDATE SET filter = excluded.filter",
+        )?;
+        stmt.execute(named_params! {
+            ":name": self.name,
+            ":filter": self.filter,
+        })
+    }
+
+    pub fn delete(name: &str, conn: &Connection) -> Result<usize, Error> {
+        let mut stmt = conn.prepare("DELETE FROM saved_search WHERE name = :name")?;
+        stmt.execute(named_params! {
+            ":name": name,
+        })
+    }
+
+    pub fn get_by_name(name: &str, conn: &Connection) -> Result<Option<SavedSearchSql>, Error> {
+        let mut stmt = conn.prepare("SELECT name, filter FROM saved_search WHERE name = :name")?;
+        stmt.query_row(named_params! { ":name": name }, |row| {
+            Ok(SavedSearchSql {
+                name: row.get(0)?,
+                filter: row.get(1)?,
+            })
+        })
+        .optional()
+    }
+
+    pub fn get_all(conn: &Connection) -> Result<Vec<SavedSearchSql>, Error> {
+        let mut stmt = conn.prepare("SELECT name, filter FROM saved_search ORDER BY name")?;
+        let iter = stmt.query_map([], |row| {
+            Ok(SavedSearchSql {
+                name: row.get(0)?,
+                filter: row.get(1)?,
+            })
+        })?;
+        iter.collect()
+    }
+}
@@ -0,0 +1,143 @@
+//! Full text search over media using an FTS5 table
+//!
+//! `media_fts` has a row for every media row keyed by its filepath in the unindexed
+//! `media_filepath` column, as the rowid of `media` is not stable. Triggers on `media` and `label`
+//! keep it in sync so none of the code writing those tables needs to know about it.
+
+use rusqlite::{Connection, Error};
+
+use super::media_sql::table_columns;
+
+/// Columns of the search table, which is recreated when they change
+const COLUMNS: [&str; 8] = [
+    "media_filepath",
+    "path_text",
+    "location_text",
+    "device_text",
+    "label_text",
+    "title_text",
+    "caption_text",
+    "notes_text",
+];
+const TRIGGERS: [&str; 6] = [
+    "media_fts_insert",
+    "media_fts_delete",
+    "media_fts_update",
+    "label_fts_insert",
+    "label_fts_delete",
+    "label_fts_update",
+];
+
+/// Labels of the media with `{filepath}` separated by spaces
+macro_rules! labels_of {
+    ($filepath:literal) => {
+        concat!(
+            "(SELECT group_concat(label.label, ' ') FROM label WHERE label.filepath = ",
+            $filepath,
+            ")"
+        )
+    };
+}
+
+/// Update the labels of the media with `{filepath}`
+macro_rules! update_labels {
+    ($filepath:literal) => {
+        concat!(
+            "UPDATE media_fts SET label_text = ",
+            labels_of!($filepath),
+            " WHERE media_filepath = ",
+            $filepath,
+            ";"
+        )
+    };
+}
+
+pub(crate) struct SearchSql;
+
+impl SearchSql {
+    /// Create the search table and its triggers, filling it if it is new
+    ///
+    /// A table created by an older version of glance with other columns is recreated. Must be
+    /// called after the `media` and `label` tables are created.
+    pub fn create_table(conn: &mut Connection) -> Result<(), Error> {
+        let transaction = conn.transaction()?;
+        let up_to_date = table_columns(&transaction, "main", "media_fts")? == COLUMNS;
+        if !up_to_date {
+            transaction.execute("DROP TABLE IF EXISTS media_fts", [])?;
+            for trigger in TRIGGERS {
+                transaction.execute(&format!("DROP TRIGGER IF EXISTS {trigger}"), [])?;
+            }
+        }
+        transaction.execute_batch(concat!(
+            "CREATE VIRTUAL TABLE IF NOT EXISTS media_fts USING fts5 (
+                media_filepath UNINDEXED, path_text, location_text, device_text, label_text,
+                title_text, caption_text, notes_text,
+                prefix = '2 3'
+            );
+            CREATE TRIGGER IF NOT EXISTS media_fts_insert AFTER INSERT ON media BEGIN
+                INSERT INTO media_fts (
+                    media_filepath, path_text, location_text, device_text, label_text,
+                    title_text, caption_text, notes_text
+                )
+                VALUES (new.filepath, new.filepath, new.location, new.device, ",
+            labels_of!("new.filepath"),
+            ", new.title, new.caption, new.notes);
+            END;
+            CREATE TRIGGER IF NOT EXISTS media_fts_delete AFTER DELETE ON media BEGIN
+                DELETE FROM media_fts WHERE media_filepath = old.filepath;
+            END;
+            CREATE TRIGGER IF NOT EXISTS media_fts_update
+            AFTER UPDATE OF filepath, location, device, title, caption, notes ON media BEGIN
+                UPDATE media_fts SET media_filepath = new.filepath, path_text = new.filepath,
+                    location_text = new.location, device_text = new.device,
+                    title_text = new.title, caption_text = new.caption, notes_text = new.notes,
+                    label_text = ",
+            labels_of!("new.filepath"),
+            "
+                WHERE media_filepath = old.filepath;
+            END;
+            CREATE TRIGGER IF NOT EXISTS label_fts_insert AFTER INSERT ON label BEGIN ",
+            update_labels!("new.filepath"),
+            " END;
+            CREATE TRIGGER IF NOT EXISTS label_fts_delete AFTER DELETE ON label BEGIN ",
+            update_labels!("old.filepath"),
+            " END;
+            CREATE TRIGGER IF NOT EXISTS label_fts_update AFTER UPDATE ON label BEGIN ",
+            update_labels!("old.filepath"),
+            update_labels!("new.filepath"),
+            " END;"
+        ))?;
+        if !up_to_date {
+            Self::rebuild(&transaction)?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Replace the contents of the search table with the current media and labels
+    pub fn rebuild(conn: &Connection) -> Result<(), Error> {
+        conn.execute_batch(concat!(
+            "DELETE FROM media_fts;
+            INSERT INTO media_fts (
+                media_filepath, path_text, location_text, device_text, label_text,
+                title_text, caption_text, notes_text
+            )
+            SELECT filepath, filepath, location, device, ",
+            labels_of!("media.filepath"),
+            ", title, caption, notes FROM media;"
+        ))
+    }
+
+    /// FTS5 query matching media with every word as a prefix of a word in any column
+    ///
+    /// Words are quoted so they never contain FTS5 syntax. Words without any letters or digits
+    /// would be an empty phrase, which is a syntax error, so they are skipped.
+    pub fn match_expression(words: &[String]) -> Option<String> {
+        let phrases: Vec<_> = words
+            .iter()
+            .filter(|word| word.chars().any(char::is_alphanumeric))
+            .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+            .collect();
+        (!phrases.is_empty()).then(|| phrases.join(" "))
+    }
+}
@@ -0,0 +1,155 @@
+//! User settings persisted between runs of glance-ui
+
+use std::{fs, io, path::Path};
+
+use displaydoc::Display;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub culling: CullingConfig,
+}
+
+/// Hotkeys used while culling
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CullingConfig {
+    pub hotkeys: Vec<Hotkey>,
+}
+
+/// Binding of a number key to an action
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Hotkey {
+    /// Number key from 1 to 9
+    pub key: u8,
+    pub action: HotkeyAction,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HotkeyAction {
+    /// Add the label if the media does not have it, otherwise remove it
+    ToggleLabel(String),
+    /// Set the star rating, or clear it if the media already has this rating
+    SetRating(u8),
+}
+
+impl Default for CullingConfig {
+    fn default() -> Self {
+        Self {
+            hotkeys: ["keep", "maybe", "favorite"]
+                .into_iter()
+                .zip(1..)
+                .map(|(label, key)| Hotkey {
+                    key,
+                    action: HotkeyAction::ToggleLabel(label.to_string()),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl CullingConfig {
+    pub fn hotkey(&self, key: u8) -> Option<&HotkeyAction> {
+        self.hotkeys
+            .iter()
+            .find(|hotkey| hotkey.key == key)
+            .map(|hotkey| &hotkey.action)
+    }
+}
+
+impl Config {
+    /// Read the config at `path`, or the default config if it does not exist
+    ///
+    /// Nothing is written until the config is saved.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Ok(toml::from_str(&contents)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error, Display)]
+pub enum ConfigError {
+    /// io: {0}
+    Io(#[from] io::Error),
+    /// toml deserialize: {0}
+    TomlDeserialize(#[from] toml::de::Error),
+    /// toml serialize: {0}
+    TomlSerialize(#[from] toml::ser::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// Empty directory for the files of a test
+    fn test_dir(test: &str) -> PathBuf {
+        let directory = std::env::temp_dir()
+            .join(format!("glance-ui-{}", std::process::id()))
+            .join(test);
+        if directory.exists() {
+            fs::remove_dir_all(&directory).unwrap();
+        }
+        directory
+    }
+
+    #[test]
+    fn load_missing_test() {
+        let path = test_dir("load_missing_test").join("ui.toml");
+        let config = Config::load(&path).unwrap();
+        assert_eq!(config, Config::default());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn save_and_load_test() {
+        let path = test_dir("save_and_load_test")
+            .join("nested")
+            .join("ui.toml");
+        let config = Config {
+            culling: CullingConfig {
+                hotkeys: vec![
+                    Hotkey {
+                        key: 1,
+                        action: HotkeyAction::ToggleLabel("trips/lisbon".to_string()),
+                    },
+                    Hotkey {
+                        key: 5,
+                        action: HotkeyAction::SetRating(5),
+                    },
+                ],
+            },
+        };
+        config.save(&path).unwrap();
+        assert_eq!(Config::load(&path).unwrap(), config);
+    }
+
+    #[test]
+    fn load_partial_test() {
+        let path = test_dir("load_partial_test").join("ui.toml");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "").unwrap();
+        assert_eq!(Config::load(&path).unwrap(), Config::default());
+
+        fs::write(&path, "[culling]\nhotkeys = []\n").unwrap();
+        assert!(Config::load(&path).unwrap().culling.hotkeys.is_empty());
+
+        fs::write(&path, "[culling]\nhotkeys = 1\n").unwrap();
+        assert!(Config::load(&path).is_err());
+    }
+}
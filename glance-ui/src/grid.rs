@@ -0,0 +1,202 @@
+//! Virtualized grid of thumbnails
+//!
+//! The grid is split into rows of tiles with a header row whenever the month media was taken
+//! changes. Only the position of each row is stored so that drawing can skip straight to the
+//! rows in view no matter how much media there is.
+
+use std::{
+    collections::HashSet,
+    ops::Range,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use chrono::Local;
+use glance_lib::index::{media::Media, thumbnail::ThumbnailCache};
+use slog::{warn, Logger};
+
+/// Space between tiles and rows
+pub const SPACING: f32 = 4.0;
+pub const HEADER_HEIGHT: f32 = 24.0;
+
+pub enum GridRow {
+    /// Title of the section started by this row
+    Header(String),
+    /// Indices into the media being shown
+    Tiles(Range<usize>),
+}
+
+pub struct GridLayout {
+    columns: usize,
+    tile_size: f32,
+    rows: Vec<GridRow>,
+    /// Offset of the top of each row from the top of the grid
+    offsets: Vec<f32>,
+    height: f32,
+}
+
+impl GridLayout {
+    pub fn new(sections: &[Section], columns: usize, tile_size: f32) -> Self {
+        let columns = columns.max(1);
+        let mut rows = Vec::new();
+        for section in sections {
+            rows.push(GridRow::Header(section.title.clone()));
+            for row_start in section.range.clone().step_by(columns) {
+                rows.push(GridRow::Tiles(
+                    row_start..(row_start + columns).min(section.range.end),
+                ));
+            }
+        }
+
+        let mut offsets = Vec::with_capacity(rows.len());
+        let mut height = 0.0;
+        for row in &rows {
+            offsets.push(height);
+            height += match row {
+                GridRow::Header(_) => HEADER_HEIGHT,
+                GridRow::Tiles(_) => tile_size,
+            } + SPACING;
+        }
+
+        Self {
+            columns,
+            tile_size,
+            rows,
+            offsets,
+            height,
+        }
+    }
+
+    /// Whether this layout can be reused for the given column count and tile size
+    pub fn matches(&self, columns: usize, tile_size: f32) -> bool {
+        self.columns == columns.max(1) && self.tile_size == tile_size
+    }
+
+    pub fn height(&self) -> f32 {
+        self.height
+    }
+
+    /// Rows overlapping the vertical range `top..bottom` along with their offset
+    pub fn visible_rows(&self, top: f32, bottom: f32) -> impl Iterator<Item = (f32, &GridRow)> {
+        // The first visible row is the last one starting at or above `top`
+        let first = self
+            .offsets
+            .partition_point(|offset| *offset <= top)
+            .saturating_sub(1);
+        self.offsets[first..]
+            .iter()
+            .copied()
+            .zip(&self.rows[first..])
+            .take_while(move |(offset, _)| *offset < bottom)
+    }
+
+    /// Offset of the row containing the media at `idx`
+    pub fn offset_of(&self, idx: usize) -> Option<f32> {
+        self.rows
+            .iter()
+            .position(|row| matches!(row, GridRow::Tiles(range) if range.contains(&idx)))
+            .map(|row| self.offsets[row])
+    }
+}
+
+/// A run of consecutive media taken in the same month
+pub struct Section {
+    title: String,
+    range: Range<usize>,
+}
+
+/// Split media into sections by the month they were taken
+///
+/// Sections are kept separate from the layout because they only change with the media while the
+/// layout changes whenever the grid is resized.
+pub fn sections(media_vec: &[Media]) -> Vec<Section> {
+    let mut sections = Vec::<Section>::new();
+    for (idx, media) in media_vec.iter().enumerate() {
+        let title = match media.created {
+            Some(created) => created.with_timezone(&Local).format("%B %Y").to_string(),
+            None => "Unknown date".to_string(),
+        };
+        match sections.last_mut() {
+            Some(section) if section.title == title => section.range.end = idx + 1,
+            _ => sections.push(Section {
+                title,
+                range: idx..idx + 1,
+            }),
+        }
+    }
+    sections
+}
+
+/// Generates thumbnails on a background thread
+///
+/// Only the most recently requested media are generated so that scrolling quickly past media
+/// does not queue up work for tiles that are no longer visible.
+pub struct ThumbnailLoader {
+    wanted: Arc<Mutex<Vec<Media>>>,
+    /// Media that thumbnails could not be generated for, such as videos
+    failed: Arc<Mutex<HashSet<PathBuf>>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl ThumbnailLoader {
+    pub fn new(cache: ThumbnailCache, size: u32, logger: Logger) -> Self {
+        let wanted = Arc::new(Mutex::new(Vec::<Media>::new()));
+        let failed = Arc::new(Mutex::new(HashSet::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+        {
+            let wanted = Arc::clone(&wanted);
+            let failed = Arc::clone(&failed);
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let next = wanted.lock().unwrap().pop();
+                    match next {
+                        Some(media) => {
+                            if let Err(e) = cache.get(&media, size) {
+                                warn!(logger, "failed to generate thumbnail";
+                                    "path" => media.filepath.display(),
+                                    "error" => %e,
+                                );
+                                failed.lock().unwrap().insert(media.filepath);
+                            }
+                        }
+                        None => thread::sleep(Duration::from_millis(50)),
+                    }
+                }
+            });
+        }
+        Self {
+            wanted,
+            failed,
+            stop,
+        }
+    }
+
+    /// Replace the media waiting for thumbnails
+    ///
+    /// Media are generated in the order they are given. Media that previously failed are skipped.
+    pub fn request(&self, mut media_vec: Vec<Media>) {
+        {
+            let failed = self.failed.lock().unwrap();
+            media_vec.retain(|media| !failed.contains(&media.filepath));
+        }
+        // Popped from the end
+        media_vec.reverse();
+        *self.wanted.lock().unwrap() = media_vec;
+    }
+
+    pub fn has_failed(&self, media: &Media) -> bool {
+        self.failed.lock().unwrap().contains(&media.filepath)
+    }
+}
+
+impl Drop for ThumbnailLoader {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,61 @@
+//! Bounds the number of image textures kept in memory
+//!
+//! egui keeps every image it has loaded until told to forget it. Each frame the images drawn are
+//! recorded and once there are more than the capacity the least recently drawn are forgotten.
+
+use std::collections::HashMap;
+
+use eframe::egui;
+
+pub struct LoadedImages {
+    capacity: usize,
+    /// Frame each image uri was last drawn in
+    last_shown: HashMap<String, u64>,
+    frame: u64,
+}
+
+impl LoadedImages {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            last_shown: HashMap::new(),
+            frame: 0,
+        }
+    }
+
+    /// Record that the image at `uri` was drawn this frame
+    pub fn show(&mut self, uri: &str) {
+        match self.last_shown.get_mut(uri) {
+            Some(frame) => *frame = self.frame,
+            None => {
+                self.last_shown.insert(uri.to_string(), self.frame);
+            }
+        }
+    }
+
+    /// Forget the least recently drawn images over capacity and start a new frame
+    ///
+    /// Images drawn in the current frame are never forgotten.
+    pub fn evict(&mut self, ctx: &egui::Context) {
+        if self.last_shown.len() > self.capacity {
+            let mut by_age = self
+                .last_shown
+                .iter()
+                .filter(|(_, frame)| **frame != self.frame)
+                .map(|(uri, frame)| (*frame, uri.clone()))
+                .collect::<Vec<_>>();
+            by_age.sort_unstable();
+            let excess = self.last_shown.len() - self.capacity;
+            for (_, uri) in by_age.into_iter().take(excess) {
+                ctx.forget_image(&uri);
+                self.last_shown.remove(&uri);
+            }
+        }
+        self.frame += 1;
+    }
+
+    /// Stop tracking all images, used after egui has been told to forget everything
+    pub fn clear(&mut self) {
+        self.last_shown.clear();
+    }
+}
@@ -1,15 +1,75 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
-use std::collections::VecDeque;
+mod config;
+mod grid;
+mod loaded_images;
+mod map;
+mod timeline;
 
-use chrono::{Local, NaiveDate, Utc};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use chrono::{Datelike, Local, NaiveDate, TimeDelta, Utc};
+use config::{Config, Hotkey, HotkeyAction};
+use directories::ProjectDirs;
 use eframe::egui;
-use egui::{Color32, Vec2, Widget};
-use glance_lib::index::media::{stats_from_media, Media, MediaFilter};
+use egui::{Align2, Color32, FontId, Pos2, Rect, Sense, Stroke, Vec2, Widget};
+use glance_lib::index::album::Album;
+use glance_lib::index::dedupe::{DuplicateAction, KeepPolicy, ResolveDuplicatesConfig};
+use glance_lib::index::label::LabelNode;
+use glance_lib::index::media::{self, Bounds, Flag, LabelQuery, Media, MediaFilter, MediaText};
+use glance_lib::index::memories::{MemoriesConfig, MemoryYear};
+use glance_lib::index::people::{FaceRegion, Person, Rectangle};
+use glance_lib::index::saved_search::SavedSearch;
+use glance_lib::index::thumbnail::{ThumbnailCache, DEFAULT_SIZE};
 use glance_lib::index::{AddDirectoryConfig, Index, Stats};
+use grid::{GridLayout, GridRow, Section, ThumbnailLoader, HEADER_HEIGHT, SPACING};
+use loaded_images::LoadedImages;
+use map::{MapAction, MapView};
 use slog::{warn, Logger};
 use sloggers::terminal::TerminalLoggerBuilder;
 use sloggers::Build;
+use timeline::{last_day_of_month, Timeline, TimelineAction};
+
+fn thumbnail_cache_directory() -> String {
+    ProjectDirs::from("", "", "glance")
+        .map(|proj_dirs| proj_dirs.cache_dir().join("thumbnails"))
+        .unwrap_or_else(|| "thumbnails".into())
+        .display()
+        .to_string()
+}
+
+/// Journal of resolved duplicates, kept out of the indexed directories
+fn dedupe_journal_path() -> PathBuf {
+    ProjectDirs::from("", "", "glance")
+        .map(|proj_dirs| proj_dirs.data_dir().join("dedupe.jsonl"))
+        .unwrap_or_else(|| "glance-dedupe.jsonl".into())
+}
+
+fn config_path() -> PathBuf {
+    ProjectDirs::from("", "", "glance")
+        .map(|proj_dirs| proj_dirs.config_dir().join("ui.toml"))
+        .unwrap_or_else(|| "glance-ui.toml".into())
+}
+
+/// Media loaded at a time so large libraries are not read into memory at once
+const MEDIA_PAGE_SIZE: u64 = 10_000;
+/// Most thumbnails shown for each year of memories
+const MEMORY_THUMBNAILS: usize = 6;
+
+/// Number keys that can be bound to culling actions
+const HOTKEYS: [egui::Key; 9] = [
+    egui::Key::Num1,
+    egui::Key::Num2,
+    egui::Key::Num3,
+    egui::Key::Num4,
+    egui::Key::Num5,
+    egui::Key::Num6,
+    egui::Key::Num7,
+    egui::Key::Num8,
+    egui::Key::Num9,
+];
 
 fn main() -> Result<(), eframe::Error> {
     env_logger::init(); // Log to stderr (if you run with `RUST_LOG=debug`).
@@ -32,60 +92,721 @@ struct GlanceUi {
     index: Option<Index>,
     media_vec: Vec<Media>,
     current_media_idx: Option<usize>,
-    previously_seen_images: VecDeque<String>,
+    full_images: LoadedImages,
+    thumbnails: LoadedImages,
     filter_by_date: bool,
     start_date: NaiveDate,
+    /// Last day included by the date filter
     end_date: NaiveDate,
+    /// Media per month across the whole index
+    timeline: Timeline,
     index_stats: Option<Stats>,
     filtered_stats: Option<Stats>,
     filtered_stats_string: Option<String>,
     picked_path: Option<String>,
     add_directory_config: AddDirectoryConfig,
     label_to_add: String,
-    label_to_filter: Option<String>,
-    all_labels: Vec<String>,
+    /// How each label filters media, labels that are missing are ignored
+    label_modes: BTreeMap<String, LabelMode>,
+    label_tree: Vec<LabelNode>,
     device_to_filter: Option<String>,
     format_to_filter: Option<String>,
     logger: Logger,
     rotation: u8,
+    show_duplicates: bool,
+    duplicate_groups: Vec<Vec<Media>>,
+    duplicate_group_idx: usize,
+    duplicate_keep_idx: usize,
+    duplicate_removal: DuplicateRemoval,
+    /// Labels of each copy in the current duplicate set
+    duplicate_labels: Vec<Vec<String>>,
+    /// Whether deleting the other copies of the current duplicate set is waiting to be confirmed
+    confirm_duplicate_delete: bool,
+    quarantine_path: Option<String>,
+    show_grid: bool,
+    grid_tile_size: f32,
+    grid_sections: Vec<Section>,
+    grid_layout: Option<GridLayout>,
+    scroll_grid_to_current: bool,
+    /// Indices into `media_vec` of the selected media
+    selected: BTreeSet<usize>,
+    /// Media the last plain or ctrl click was on, shift clicks select from here
+    selection_anchor: Option<usize>,
+    batch_label: String,
+    thumbnail_loader: Option<ThumbnailLoader>,
+    /// Media drawn this frame without a thumbnail, requested from the loader at the end of it
+    missing_thumbnails: RefCell<Vec<Media>>,
+    config: Config,
+    culling: bool,
+    /// Labels of the media at this path, loaded once for the culling overlay
+    culling_labels: Option<(PathBuf, Vec<String>)>,
+    /// Action bound to each number key while the hotkeys are being edited
+    hotkey_edits: Vec<Option<HotkeyAction>>,
+    /// Media whose text is being edited in the Image Info window
+    text_edit_path: Option<PathBuf>,
+    title_edit: String,
+    caption_edit: String,
+    notes_edit: String,
+    min_rating_to_filter: u8,
+    flag_to_filter: Option<Flag>,
+    /// Query in the search box, combined with the other filters
+    search_query: String,
+    search_error: Option<String>,
+    /// Filter `media_vec` was loaded with
+    media_filter: MediaFilter,
+    /// Number of media matching `media_filter`, which may be more than are loaded
+    media_total: i64,
+    saved_searches: Vec<SavedSearch>,
+    /// Name of the saved search combined with the other filters
+    saved_search: Option<String>,
+    save_search_name: String,
+    albums: Vec<Album>,
+    /// Name of the album to show in album order
+    album_to_filter: Option<String>,
+    people: Vec<Person>,
+    person_to_filter: Option<String>,
+    /// Media whose face regions are loaded
+    face_regions_path: Option<PathBuf>,
+    /// Face regions of the current media with the name being edited for each
+    face_regions: Vec<(FaceRegion, String)>,
+    /// Whether dragging on the image draws a new face region
+    drawing_region: bool,
+    /// Corner the new face region is being dragged from, in fractions of the image
+    region_drag_start: Option<Pos2>,
+    /// Name of the person in the next drawn face region
+    new_region_person: String,
+    /// Media taken around today in earlier years
+    memories: Vec<MemoryYear>,
+    memories_config: MemoriesConfig,
+    show_memories: bool,
+    show_map: bool,
+    map: MapView,
+    /// Latitude and longitude of the media matching the filters other than `within_to_filter`
+    map_points: Vec<(f64, f64)>,
+    /// Area selected on the map
+    within_to_filter: Option<Bounds>,
+}
+
+/// Change to a face region of the current media made in the UI
+enum RegionEdit {
+    Add(Rectangle),
+    /// Save the edited name of the region at this index in `face_regions`
+    Rename(usize),
+    Delete(i64),
+}
+
+/// How a label filters media
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LabelMode {
+    /// Media must have every label in this mode
+    All,
+    /// Media must have at least one label in this mode
+    Any,
+    /// Media must have none of the labels in this mode
+    None,
+}
+
+impl LabelMode {
+    fn name(mode: Option<LabelMode>) -> &'static str {
+        match mode {
+            Some(LabelMode::All) => "all of",
+            Some(LabelMode::Any) => "any of",
+            Some(LabelMode::None) => "none of",
+            None => "ignore",
+        }
+    }
+}
+
+/// What to do with the copies of a duplicate set that are not kept
+#[derive(Debug, Default, PartialEq)]
+enum DuplicateRemoval {
+    #[default]
+    Quarantine,
+    Delete,
 }
 
 impl GlanceUi {
     fn new() -> Self {
+        let logger = TerminalLoggerBuilder::new().build().unwrap();
+        let config = Config::load(&config_path()).unwrap_or_else(|e| {
+            warn!(logger, "failed to load config"; "error" => %e);
+            Config::default()
+        });
+        let hotkey_edits = (1..=HOTKEYS.len() as u8)
+            .map(|key| config.culling.hotkey(key).cloned())
+            .collect();
         Self {
             index: Default::default(),
             media_vec: Default::default(),
             current_media_idx: Default::default(),
-            previously_seen_images: Default::default(),
+            full_images: LoadedImages::new(10),
+            thumbnails: LoadedImages::new(1000),
             filter_by_date: Default::default(),
             start_date: NaiveDate::from_ymd_opt(2008, 1, 1).unwrap(),
             end_date: NaiveDate::from_ymd_opt(2025, 1, 1).unwrap(),
+            timeline: Default::default(),
             index_stats: Default::default(),
             filtered_stats: Default::default(),
             filtered_stats_string: Default::default(),
             picked_path: Default::default(),
             add_directory_config: Default::default(),
             label_to_add: Default::default(),
-            label_to_filter: Default::default(),
-            all_labels: Default::default(),
+            label_modes: Default::default(),
+            label_tree: Default::default(),
             device_to_filter: Default::default(),
             format_to_filter: Default::default(),
-            logger: TerminalLoggerBuilder::new().build().unwrap(),
+            logger,
             rotation: Default::default(),
+            show_duplicates: Default::default(),
+            duplicate_groups: Default::default(),
+            duplicate_group_idx: Default::default(),
+            duplicate_keep_idx: Default::default(),
+            duplicate_removal: Default::default(),
+            duplicate_labels: Default::default(),
+            confirm_duplicate_delete: Default::default(),
+            quarantine_path: Default::default(),
+            show_grid: Default::default(),
+            grid_tile_size: 128.0,
+            grid_sections: Default::default(),
+            grid_layout: Default::default(),
+            scroll_grid_to_current: Default::default(),
+            selected: Default::default(),
+            selection_anchor: Default::default(),
+            batch_label: Default::default(),
+            thumbnail_loader: Default::default(),
+            missing_thumbnails: Default::default(),
+            config,
+            culling: Default::default(),
+            culling_labels: Default::default(),
+            hotkey_edits,
+            text_edit_path: Default::default(),
+            title_edit: Default::default(),
+            caption_edit: Default::default(),
+            notes_edit: Default::default(),
+            min_rating_to_filter: Default::default(),
+            flag_to_filter: Default::default(),
+            search_query: Default::default(),
+            search_error: Default::default(),
+            media_filter: Default::default(),
+            media_total: Default::default(),
+            saved_searches: Default::default(),
+            saved_search: Default::default(),
+            save_search_name: Default::default(),
+            albums: Default::default(),
+            album_to_filter: Default::default(),
+            people: Default::default(),
+            person_to_filter: Default::default(),
+            face_regions_path: Default::default(),
+            face_regions: Default::default(),
+            drawing_region: Default::default(),
+            region_drag_start: Default::default(),
+            new_region_person: Default::default(),
+            memories: Default::default(),
+            memories_config: Default::default(),
+            show_memories: Default::default(),
+            show_map: Default::default(),
+            map: Default::default(),
+            map_points: Default::default(),
+            within_to_filter: Default::default(),
         }
     }
 
     fn change_index(&mut self) {
         if let Some(path) = &self.picked_path {
+            let thumbnail_cache = ThumbnailCache::new(thumbnail_cache_directory());
+            self.thumbnail_loader = Some(ThumbnailLoader::new(
+                thumbnail_cache.clone(),
+                DEFAULT_SIZE,
+                self.logger.clone(),
+            ));
             self.index = Some(
                 Index::new(format!("{}/glance.db", path))
                     .expect("to be able to initialize index")
-                    .with_logger(self.logger.clone()),
+                    .with_logger(self.logger.clone())
+                    .with_thumbnail_cache(thumbnail_cache),
             );
         }
+        self.saved_search = None;
+        self.update_saved_searches();
+        self.album_to_filter = None;
+        self.update_albums();
+        self.person_to_filter = None;
+        self.within_to_filter = None;
+        self.face_regions_path = None;
+        self.update_people();
+        self.update_timeline();
+        self.update_media();
+        self.update_memories();
+        self.show_memories = !self.memories.is_empty();
+        self.prewarm_thumbnails();
+    }
+
+    fn prewarm_thumbnails(&self) {
+        if let Some(index) = &self.index {
+            if let Err(e) = index.prewarm_thumbnails_in_background(vec![DEFAULT_SIZE]) {
+                warn!(self.logger, "failed to prewarm thumbnails"; "error" => %e);
+            }
+        }
+    }
+
+    /// Uri of the thumbnail for `media` falling back to the full image
+    /// Uri of the cached thumbnail of `media`
+    ///
+    /// Thumbnails are never generated here. Missing ones are queued for the loader and `None` is
+    /// returned until they exist, or for good if they could not be generated.
+    fn thumbnail_uri(&self, media: &Media) -> Option<String> {
+        let path = self
+            .index
+            .as_ref()?
+            .thumbnail_cache()?
+            .path(media, DEFAULT_SIZE);
+        if path.exists() {
+            return Some(format!("file://{}", path.display()));
+        }
+        if self
+            .thumbnail_loader
+            .as_ref()
+            .is_some_and(|loader| !loader.has_failed(media))
+        {
+            self.missing_thumbnails.borrow_mut().push(media.clone());
+        }
+        None
+    }
+
+    /// Draw the thumbnail at `uri` no taller than `size`, or a tile with the file name without one
+    fn thumbnail_ui(&mut self, ui: &mut egui::Ui, media: &Media, uri: Option<String>, size: f32) {
+        match uri {
+            Some(uri) => {
+                self.thumbnails.show(&uri);
+                ui.add(egui::Image::new(uri).max_height(size));
+            }
+            None => {
+                let (rect, _) = ui.allocate_exact_size(Vec2::splat(size), Sense::hover());
+                ui.painter().rect_filled(rect, 2.0, Color32::DARK_GRAY);
+                let name = media
+                    .filepath
+                    .file_name()
+                    .map(|name| name.to_string_lossy())
+                    .unwrap_or_default();
+                ui.put(rect, egui::Label::new(name).truncate(true));
+            }
+        }
+    }
+
+    /// Draw the rows of the grid that are in view, requesting any missing thumbnails
+    fn grid_ui(&mut self, ui: &mut egui::Ui) {
+        let tile_size = self.grid_tile_size;
+        let columns = ((ui.available_width() + SPACING) / (tile_size + SPACING)).max(1.0) as usize;
+        let layout = match self.grid_layout.take() {
+            Some(layout) if layout.matches(columns, tile_size) => layout,
+            _ => GridLayout::new(&self.grid_sections, columns, tile_size),
+        };
+        let mut scroll_area = egui::ScrollArea::vertical().auto_shrink(false);
+        if self.scroll_grid_to_current {
+            if let Some(offset) = self.current_media_idx.and_then(|idx| layout.offset_of(idx)) {
+                scroll_area = scroll_area.vertical_scroll_offset(offset);
+            }
+            self.scroll_grid_to_current = false;
+        }
+
+        scroll_area.show_viewport(ui, |ui, viewport| {
+            ui.set_height(layout.height());
+            let origin = ui.max_rect().min;
+            for (offset, row) in layout.visible_rows(viewport.min.y, viewport.max.y) {
+                match row {
+                    GridRow::Header(title) => {
+                        let rect = Rect::from_min_size(
+                            origin + Vec2::new(0.0, offset),
+                            Vec2::new(ui.max_rect().width(), HEADER_HEIGHT),
+                        );
+                        ui.put(rect, egui::Label::new(egui::RichText::new(title).heading()));
+                    }
+                    GridRow::Tiles(range) => {
+                        for (column, idx) in range.clone().enumerate() {
+                            let media = &self.media_vec[idx];
+                            let rect = Rect::from_min_size(
+                                origin + Vec2::new(column as f32 * (tile_size + SPACING), offset),
+                                Vec2::splat(tile_size),
+                            );
+                            let response = match self.thumbnail_uri(media) {
+                                Some(uri) => {
+                                    self.thumbnails.show(&uri);
+                                    ui.put(rect, egui::Image::new(uri).sense(Sense::click()))
+                                }
+                                None => {
+                                    let failed = self
+                                        .thumbnail_loader
+                                        .as_ref()
+                                        .is_none_or(|loader| loader.has_failed(media));
+                                    ui.painter().rect_filled(rect, 2.0, Color32::DARK_GRAY);
+                                    if failed {
+                                        let name = media
+                                            .filepath
+                                            .file_name()
+                                            .map(|name| name.to_string_lossy())
+                                            .unwrap_or_default();
+                                        ui.put(rect, egui::Label::new(name).truncate(true));
+                                    }
+                                    ui.interact(rect, ui.id().with(idx), Sense::click())
+                                }
+                            };
+                            if self.selected.contains(&idx) {
+                                ui.painter().rect_filled(
+                                    rect,
+                                    2.0,
+                                    Color32::from_rgba_unmultiplied(0, 120, 255, 60),
+                                );
+                            }
+                            if self.current_media_idx == Some(idx) {
+                                ui.painter().rect_stroke(
+                                    rect,
+                                    2.0,
+                                    Stroke::new(3.0, Color32::LIGHT_BLUE),
+                                );
+                            }
+                            if response.clicked() {
+                                let modifiers = ui.input(|i| i.modifiers);
+                                self.select(idx, modifiers);
+                            }
+                        }
+                    }
+                }
+            }
+        });
+
+        self.grid_layout = Some(layout);
+    }
+
+    /// Update the selection for a click on the media at `idx`
+    ///
+    /// A plain click selects only that media, ctrl toggles it, and shift selects the range from
+    /// the last clicked media.
+    fn select(&mut self, idx: usize, modifiers: egui::Modifiers) {
+        if modifiers.shift {
+            let anchor = self.selection_anchor.unwrap_or(idx);
+            if !modifiers.command {
+                self.selected.clear();
+            }
+            self.selected.extend(anchor.min(idx)..=anchor.max(idx));
+        } else if modifiers.command {
+            if !self.selected.remove(&idx) {
+                self.selected.insert(idx);
+            }
+            self.selection_anchor = Some(idx);
+        } else {
+            self.selected.clear();
+            self.selected.insert(idx);
+            self.selection_anchor = Some(idx);
+        }
+        self.current_media_idx = Some(idx);
+    }
+
+    /// Add or remove `batch_label` on every selected media
+    fn label_selection(&mut self, add: bool) {
+        if let Some(index) = &mut self.index {
+            let paths = self
+                .selected
+                .iter()
+                .filter_map(|idx| self.media_vec.get(*idx))
+                .map(|media| &media.filepath);
+            let result = if add {
+                index.add_label_many(paths, self.batch_label.clone())
+            } else {
+                index.delete_label_many(paths, self.batch_label.clone())
+            };
+            if let Err(e) = result {
+                warn!(self.logger, "failed to label selection";
+                    "label" => &self.batch_label,
+                    "error" => %e,
+                );
+            }
+        }
+        self.update_labels();
+    }
+
+    /// Add `label` to the current media if it does not have it, otherwise remove it
+    fn toggle_label(&mut self, label: String) {
+        let path = self
+            .current_media_idx
+            .and_then(|idx| self.media_vec.get(idx))
+            .map(|media| media.filepath.clone());
+        if let (Some(index), Some(path)) = (&self.index, path) {
+            let result = match index.get_labels(&path) {
+                Ok(labels) if labels.contains(&label) => index.delete_label(&path, label),
+                Ok(_) => index.add_label(&path, label),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                warn!(self.logger, "failed to toggle label"; "error" => %e);
+            }
+        }
+        self.update_labels();
+    }
+
+    /// Set the rating of the current media
+    fn set_rating(&mut self, rating: u8) {
+        if let (Some(index), Some(media)) = (
+            &self.index,
+            self.current_media_idx
+                .and_then(|idx| self.media_vec.get_mut(idx)),
+        ) {
+            match index.set_rating(&media.filepath, rating) {
+                Ok(()) => media.rating = rating,
+                Err(e) => warn!(self.logger, "failed to set rating"; "error" => %e),
+            }
+        }
+    }
+
+    /// Set the flag of the current media
+    fn set_flag(&mut self, flag: Option<Flag>) {
+        if let (Some(index), Some(media)) = (
+            &self.index,
+            self.current_media_idx
+                .and_then(|idx| self.media_vec.get_mut(idx)),
+        ) {
+            match index.set_flag(&media.filepath, flag) {
+                Ok(()) => media.flag = flag,
+                Err(e) => warn!(self.logger, "failed to set flag"; "error" => %e),
+            }
+        }
+    }
+
+    /// Set the title, caption and notes of the current media from the Image Info window
+    fn save_media_text(&mut self) {
+        let non_empty = |text: &String| (!text.trim().is_empty()).then(|| text.trim().to_string());
+        let text = MediaText {
+            title: non_empty(&self.title_edit),
+            caption: non_empty(&self.caption_edit),
+            notes: non_empty(&self.notes_edit),
+        };
+        if let (Some(index), Some(media)) = (
+            &self.index,
+            self.current_media_idx
+                .and_then(|idx| self.media_vec.get_mut(idx)),
+        ) {
+            match index.set_media_text(&media.filepath, &text) {
+                Ok(()) => {
+                    media.title = text.title;
+                    media.caption = text.caption;
+                    media.notes = text.notes;
+                }
+                Err(e) => warn!(self.logger, "failed to set text"; "error" => %e),
+            }
+        }
+    }
+
+    fn update_people(&mut self) {
+        if let Some(index) = &self.index {
+            match index.get_people() {
+                Ok(people) => self.people = people,
+                Err(e) => warn!(self.logger, "failed to get people"; "error" => %e),
+            }
+        }
+    }
+
+    fn update_memories(&mut self) {
+        if let Some(index) = &self.index {
+            let today = Local::now().date_naive();
+            match index.get_memories(today, &self.memories_config) {
+                Ok(memories) => self.memories = memories,
+                Err(e) => warn!(self.logger, "failed to get memories"; "error" => %e),
+            }
+        }
+    }
+
+    /// Search for the memories from `years_ago` years before today
+    fn show_memory_year(&mut self, years_ago: i32) {
+        let today = Local::now().date_naive();
+        let year = today.year() - years_ago;
+        let day = today
+            .with_year(year)
+            .or_else(|| NaiveDate::from_ymd_opt(year, 2, 28))
+            .expect("February 28th to exist");
+        let window = TimeDelta::days(self.memories_config.window_days.into());
+        self.search_query = format!(
+            "taken:{}..{}",
+            (day - window).format("%Y-%m-%d"),
+            (day + window).format("%Y-%m-%d")
+        );
         self.update_media();
     }
 
+    fn update_face_regions(&mut self, path: PathBuf) {
+        if let Some(index) = &self.index {
+            match index.get_face_regions(&path) {
+                Ok(regions) => {
+                    self.face_regions = regions
+                        .into_iter()
+                        .map(|region| {
+                            let person = region.person.clone().unwrap_or_default();
+                            (region, person)
+                        })
+                        .collect();
+                }
+                Err(e) => warn!(self.logger, "failed to get face regions"; "error" => %e),
+            }
+        }
+        self.face_regions_path = Some(path);
+    }
+
+    fn edit_face_region(&mut self, path: PathBuf, edit: RegionEdit) {
+        let Some(index) = &self.index else {
+            return;
+        };
+        let result = match edit {
+            RegionEdit::Add(rectangle) => index
+                .add_face_region(&path, Some(&self.new_region_person), rectangle)
+                .map(|_| ()),
+            RegionEdit::Rename(i) => {
+                let (region, person) = &self.face_regions[i];
+                index.set_face_region(region.id, Some(person), region.rectangle)
+            }
+            RegionEdit::Delete(id) => index.delete_face_region(id),
+        };
+        if let Err(e) = result {
+            warn!(self.logger, "failed to edit face region"; "error" => %e);
+        }
+        self.update_face_regions(path);
+        self.update_people();
+    }
+
+    /// Set `flag` on the current media, or clear it if the media already has it
+    fn toggle_flag(&mut self, flag: Flag) {
+        let current = self
+            .current_media_idx
+            .and_then(|idx| self.media_vec.get(idx))
+            .and_then(|media| media.flag);
+        self.set_flag((current != Some(flag)).then_some(flag));
+    }
+
+    /// Apply the culling hotkeys pressed this frame to the current media
+    fn handle_culling_keys(&mut self, ctx: &egui::Context) {
+        if ctx.wants_keyboard_input() {
+            return;
+        }
+        for (key, egui_key) in (1..).zip(HOTKEYS) {
+            if ctx.input(|i| i.key_pressed(egui_key)) {
+                match self.config.culling.hotkey(key).cloned() {
+                    Some(HotkeyAction::ToggleLabel(label)) => self.toggle_label(label),
+                    Some(HotkeyAction::SetRating(rating)) => {
+                        let current = self
+                            .current_media_idx
+                            .and_then(|idx| self.media_vec.get(idx))
+                            .map(|media| media.rating);
+                        self.set_rating(if current == Some(rating) { 0 } else { rating });
+                    }
+                    None => {}
+                }
+            }
+        }
+        if ctx.input(|i| i.key_pressed(egui::Key::P)) {
+            self.toggle_flag(Flag::Pick);
+        }
+        if ctx.input(|i| i.key_pressed(egui::Key::X)) {
+            self.toggle_flag(Flag::Reject);
+        }
+    }
+
+    /// Load the labels of the current media for the culling overlay if they are not loaded
+    fn update_culling_labels(&mut self) {
+        let Some(path) = self
+            .current_media_idx
+            .and_then(|idx| self.media_vec.get(idx))
+            .map(|media| media.filepath.clone())
+        else {
+            return;
+        };
+        if self
+            .culling_labels
+            .as_ref()
+            .is_some_and(|(loaded, _)| *loaded == path)
+        {
+            return;
+        }
+        if let Some(index) = &self.index {
+            match index.get_labels(&path) {
+                Ok(labels) => self.culling_labels = Some((path, labels)),
+                Err(e) => warn!(self.logger, "failed to get labels"; "error" => %e),
+            }
+        }
+    }
+
+    /// Overlay showing the current media and which hotkeys apply to it
+    fn culling_hud(&self, ctx: &egui::Context) {
+        let Some((idx, media)) = self
+            .current_media_idx
+            .and_then(|idx| self.media_vec.get(idx).map(|media| (idx, media)))
+        else {
+            return;
+        };
+        let labels = self
+            .culling_labels
+            .as_ref()
+            .filter(|(path, _)| *path == media.filepath)
+            .map(|(_, labels)| labels.as_slice())
+            .unwrap_or_default();
+        let key_text = |key: String, label: &str, active: bool| {
+            let text = egui::RichText::new(format!("{key} {label}"));
+            if active {
+                text.strong().color(Color32::LIGHT_GREEN)
+            } else {
+                text.weak()
+            }
+        };
+        egui::Area::new("culling_hud")
+            .anchor(egui::Align2::CENTER_TOP, [0.0, 8.0])
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.label(format!(
+                        "{} of {}: {}",
+                        idx + 1,
+                        self.media_vec.len(),
+                        media.filepath.display()
+                    ));
+                    ui.label(format!("Rating: {}", stars(media.rating)));
+                    ui.horizontal(|ui| {
+                        for hotkey in &self.config.culling.hotkeys {
+                            let key = hotkey.key.to_string();
+                            match &hotkey.action {
+                                HotkeyAction::ToggleLabel(label) => {
+                                    let active = labels.contains(label);
+                                    ui.label(key_text(key, label, active));
+                                }
+                                HotkeyAction::SetRating(rating) => {
+                                    let active = media.rating == *rating;
+                                    ui.label(key_text(key, &stars(*rating), active));
+                                }
+                            }
+                        }
+                        let picked = media.flag == Some(Flag::Pick);
+                        ui.label(key_text("P".to_string(), "pick", picked));
+                        let rejected = media.flag == Some(Flag::Reject);
+                        ui.label(key_text("X".to_string(), "reject", rejected));
+                    });
+                    if !labels.is_empty() {
+                        ui.label(format!("Labels: {}", labels.join(", ")));
+                    }
+                });
+            });
+    }
+
+    /// Bind the number keys to the edited actions and persist them
+    fn save_hotkeys(&mut self) {
+        self.config.culling.hotkeys = (1..)
+            .zip(&self.hotkey_edits)
+            .filter_map(|(key, action)| {
+                let action = action.clone()?;
+                match &action {
+                    HotkeyAction::ToggleLabel(label) if label.is_empty() => None,
+                    _ => Some(Hotkey { key, action }),
+                }
+            })
+            .collect();
+        if let Err(e) = self.config.save(&config_path()) {
+            warn!(self.logger, "failed to save config"; "error" => %e);
+        }
+    }
+
     fn add_directory(&mut self) {
         if let Some(index) = &mut self.index {
             if let Some(path) = &self.picked_path {
@@ -94,10 +815,22 @@ impl GlanceUi {
                     .expect("to be able to add directory");
             }
         }
+        self.update_timeline();
         self.update_media();
+        self.prewarm_thumbnails();
     }
 
     fn update_media(&mut self) {
+        let query = match self.search_query.parse::<MediaFilter>() {
+            Ok(query) => {
+                self.search_error = None;
+                query
+            }
+            Err(e) => {
+                self.search_error = Some(e.to_string());
+                return;
+            }
+        };
         let media_filter = MediaFilter {
             created_start: if self.filter_by_date {
                 Some(chrono::DateTime::from_naive_utc_and_offset(
@@ -109,54 +842,287 @@ impl GlanceUi {
             },
             created_end: if self.filter_by_date {
                 Some(chrono::DateTime::from_naive_utc_and_offset(
-                    self.end_date.and_hms_opt(0, 0, 0).unwrap(),
+                    self.end_date
+                        .and_hms_nano_opt(23, 59, 59, 999_999_999)
+                        .unwrap(),
                     Utc,
                 ))
             } else {
                 None
             },
-            label: self.label_to_filter.clone(),
+            label: self.label_query(),
             device: self.device_to_filter.clone(),
             format: self.format_to_filter.clone(),
+            min_rating: (self.min_rating_to_filter > 0).then_some(self.min_rating_to_filter),
+            flag: self.flag_to_filter,
+            album: self.album_to_filter.clone(),
+            people: self.person_to_filter.clone().into_iter().collect(),
+            within: self.within_to_filter,
+            ..Default::default()
         };
+        let saved = self
+            .saved_searches
+            .iter()
+            .find(|saved_search| Some(&saved_search.name) == self.saved_search.as_ref())
+            .map(|saved_search| saved_search.filter.clone())
+            .unwrap_or_default();
+        // Fields set by the query take precedence over the same fields in the Filters window
+        self.media_filter = query.and(media_filter).and(saved);
 
         self.update_labels();
         if let Some(index) = &self.index {
             self.media_vec = index
-                .get_media_with_filter(media_filter)
+                .get_media_with_filter(MediaFilter {
+                    limit: Some(MEDIA_PAGE_SIZE),
+                    ..self.media_filter.clone()
+                })
                 .expect("get media to work");
+            self.media_total = index
+                .count_media_with_filter(&self.media_filter)
+                .expect("count media to work");
             self.current_media_idx = if !self.media_vec.is_empty() {
                 Some(0)
             } else {
                 None
             };
+            self.grid_sections = grid::sections(&self.media_vec);
+            self.grid_layout = None;
+            self.selected.clear();
+            self.selection_anchor = None;
             self.index_stats = index.stats().ok();
-            self.filtered_stats = stats_from_media(&self.media_vec).ok();
+            self.filtered_stats = index.stats_with_filter(&self.media_filter).ok();
             self.filtered_stats_string = self
                 .filtered_stats
                 .as_ref()
                 .map(|s| serde_json::to_string_pretty(&s).unwrap());
+            // The whole map stays visible while an area of it filters the media
+            match index.get_coordinates(&MediaFilter {
+                within: None,
+                ..self.media_filter.clone()
+            }) {
+                Ok(points) => self.map_points = points,
+                Err(e) => warn!(self.logger, "failed to get coordinates"; "error" => %e),
+            }
+        }
+    }
+
+    /// Count the media in each month and default the date filter to the range of created
+    fn update_timeline(&mut self) {
+        let Some(index) = &self.index else {
+            return;
+        };
+        match index.count_by_created_month(&MediaFilter::default()) {
+            Ok(counts) => self.timeline = Timeline::new(&counts),
+            Err(e) => warn!(self.logger, "failed to count media by month"; "error" => %e),
+        }
+        if !self.filter_by_date {
+            match index.created_range(&MediaFilter::default()) {
+                Ok(Some((first, last))) => {
+                    self.start_date = first.date_naive();
+                    self.end_date = last.date_naive();
+                }
+                Ok(None) => {}
+                Err(e) => warn!(self.logger, "failed to get range of created"; "error" => %e),
+            }
+        }
+    }
+
+    /// Show the first media taken in a month, filtering by the month when none of the loaded
+    /// media are from it
+    fn jump_to_month(&mut self, month: NaiveDate) {
+        let end = last_day_of_month(month);
+        let in_month = |media: &Media| {
+            media
+                .created
+                .is_some_and(|created| (month..=end).contains(&created.date_naive()))
+        };
+        if !self.media_vec.iter().any(in_month) {
+            self.filter_by_date = true;
+            self.start_date = month;
+            self.end_date = end;
+            self.update_media();
+        }
+        let first = self
+            .media_vec
+            .iter()
+            .enumerate()
+            .filter(|(_, media)| in_month(media))
+            .min_by_key(|(_, media)| media.created);
+        if let Some((idx, _)) = first {
+            self.current_media_idx = Some(idx);
+            self.scroll_grid_to_current = true;
+        }
+    }
+
+    /// Load the next page of media matching the current filter
+    fn load_more_media(&mut self) {
+        if let Some(index) = &self.index {
+            let more = index
+                .get_media_with_filter(MediaFilter {
+                    offset: Some(self.media_vec.len() as u64),
+                    limit: Some(MEDIA_PAGE_SIZE),
+                    ..self.media_filter.clone()
+                })
+                .expect("get media to work");
+            self.media_vec.extend(more);
+            if self.current_media_idx.is_none() && !self.media_vec.is_empty() {
+                self.current_media_idx = Some(0);
+            }
+            self.grid_sections = grid::sections(&self.media_vec);
+            self.grid_layout = None;
+        }
+    }
+
+    fn label_query(&self) -> Option<LabelQuery> {
+        let labels = |mode| {
+            self.label_modes
+                .iter()
+                .filter(|(_, m)| **m == mode)
+                .map(|(label, _)| label.as_str().into())
+                .collect::<Vec<_>>()
+        };
+        let mut queries = labels(LabelMode::All);
+        let any = labels(LabelMode::Any);
+        if !any.is_empty() {
+            queries.push(LabelQuery::AnyOf(any));
         }
+        let none = labels(LabelMode::None);
+        if !none.is_empty() {
+            queries.push(LabelQuery::NoneOf(none));
+        }
+        (!queries.is_empty()).then_some(LabelQuery::AllOf(queries))
+    }
+
+    /// The label to export, only when filtering by exactly one label
+    fn label_to_export(&self) -> Option<&String> {
+        match self.label_modes.iter().collect::<Vec<_>>().as_slice() {
+            [(label, LabelMode::All)] => Some(label),
+            _ => None,
+        }
+    }
+
+    fn update_saved_searches(&mut self) {
+        if let Some(index) = &self.index {
+            match index.get_saved_searches() {
+                Ok(saved_searches) => self.saved_searches = saved_searches,
+                Err(e) => warn!(self.logger, "failed to get saved searches"; "error" => %e),
+            }
+        }
+    }
+
+    fn update_albums(&mut self) {
+        if let Some(index) = &self.index {
+            match index.get_albums() {
+                Ok(albums) => self.albums = albums,
+                Err(e) => warn!(self.logger, "failed to get albums"; "error" => %e),
+            }
+        }
+    }
+
+    /// Save the current filters and switch to the saved search in place of them
+    fn save_search(&mut self) {
+        let Some(index) = &self.index else {
+            return;
+        };
+        let name = self.save_search_name.trim().to_string();
+        if let Err(e) = index.save_search(&name, self.media_filter.clone()) {
+            warn!(self.logger, "failed to save search"; "name" => &name, "error" => %e);
+            return;
+        }
+        self.search_query.clear();
+        self.filter_by_date = false;
+        self.label_modes.clear();
+        self.device_to_filter = None;
+        self.format_to_filter = None;
+        self.min_rating_to_filter = 0;
+        self.flag_to_filter = None;
+        self.album_to_filter = None;
+        self.person_to_filter = None;
+        self.within_to_filter = None;
+        self.saved_search = Some(name);
+        self.save_search_name.clear();
+        self.update_saved_searches();
+        self.update_media();
     }
 
     fn update_labels(&mut self) {
+        self.culling_labels = None;
         if let Some(index) = &self.index {
-            if let Ok(all_labels) = index.get_all_labels() {
-                self.all_labels = all_labels;
+            if let Ok(label_tree) = index.get_label_tree() {
+                self.label_tree = label_tree;
             }
         }
     }
 
-    fn add_previously_seen_image(&mut self, path: String, ctx: &egui::Context) {
-        if self.previously_seen_images.contains(&path) {
+    fn update_duplicates(&mut self) {
+        if let Some(index) = &self.index {
+            match index.duplicate_groups() {
+                Ok(duplicate_groups) => self.duplicate_groups = duplicate_groups,
+                Err(e) => warn!(self.logger, "failed to get duplicates"; "error" => %e),
+            }
+        }
+        self.duplicate_group_idx = self
+            .duplicate_group_idx
+            .min(self.duplicate_groups.len().saturating_sub(1));
+        self.duplicate_keep_idx = 0;
+        self.update_duplicate_labels();
+    }
+
+    fn update_duplicate_labels(&mut self) {
+        self.duplicate_labels.clear();
+        let (Some(index), Some(group)) = (
+            &self.index,
+            self.duplicate_groups.get(self.duplicate_group_idx),
+        ) else {
             return;
+        };
+        for media in group {
+            match index.get_labels(&media.filepath) {
+                Ok(labels) => self.duplicate_labels.push(labels),
+                Err(e) => {
+                    warn!(self.logger, "failed to get labels"; "error" => %e);
+                    self.duplicate_labels.push(Vec::new());
+                }
+            }
         }
+    }
 
-        self.previously_seen_images.push_front(path);
-        if self.previously_seen_images.len() > 10 {
-            let to_evict = self.previously_seen_images.pop_back().unwrap();
-            ctx.forget_image(format!("file://{}", to_evict).as_str());
+    /// Keep the chosen copy of the current duplicate set and remove the rest
+    fn resolve_duplicate_group(&mut self) {
+        let kept = self
+            .duplicate_groups
+            .get(self.duplicate_group_idx)
+            .and_then(|group| group.get(self.duplicate_keep_idx))
+            .map(|media| media.filepath.clone());
+        let action = match self.duplicate_removal {
+            DuplicateRemoval::Quarantine => self
+                .quarantine_path
+                .as_ref()
+                .map(|path| DuplicateAction::Quarantine(path.into())),
+            DuplicateRemoval::Delete => Some(DuplicateAction::Delete),
+        };
+        if let (Some(index), Some(kept), Some(action)) = (&mut self.index, kept, action) {
+            let journal = dedupe_journal_path();
+            if let Some(parent) = journal.parent() {
+                if let Err(e) = std::fs::create_dir_all(parent) {
+                    warn!(self.logger, "failed to create journal directory"; "error" => %e);
+                    return;
+                }
+            }
+            let config = ResolveDuplicatesConfig {
+                keep: KeepPolicy::Oldest,
+                action,
+                journal: Some(journal),
+            };
+            if let Err(e) = index.resolve_duplicates_keeping(&kept, &config) {
+                warn!(self.logger, "failed to resolve duplicates";
+                    "kept" => kept.display(),
+                    "error" => %e,
+                );
+            }
         }
+        self.update_media();
+        self.update_duplicates();
     }
 }
 
@@ -169,6 +1135,8 @@ impl eframe::App for GlanceUi {
                         if let Some(path) = rfd::FileDialog::new().pick_folder() {
                             self.picked_path = Some(path.display().to_string());
                             ctx.forget_all_images();
+                            self.full_images.clear();
+                            self.thumbnails.clear();
                             self.change_index()
                         }
                     }
@@ -195,6 +1163,10 @@ impl eframe::App for GlanceUi {
                         &mut self.add_directory_config.calculate_nearest_city,
                         "calculate nearest city",
                     );
+                    ui.checkbox(
+                        &mut self.add_directory_config.perceptual_hash,
+                        "perceptual hash",
+                    );
                 });
             });
 
@@ -204,15 +1176,7 @@ impl eframe::App for GlanceUi {
                         if ui.button("Previous").clicked()
                             || ctx.input(|i| i.key_pressed(egui::Key::ArrowLeft))
                         {
-                            if let Some(idx) = self.current_media_idx {
-                                if let Some(media) = self.media_vec.get(idx) {
-                                    self.add_previously_seen_image(
-                                        media.filepath.display().to_string(),
-                                        ctx,
-                                    );
-                                }
-                            }
-
+                            self.scroll_grid_to_current = true;
                             self.current_media_idx =
                                 self.current_media_idx
                                     .map(|idx| if idx == 0 { 0 } else { idx - 1 });
@@ -221,15 +1185,7 @@ impl eframe::App for GlanceUi {
                         if ui.button("Next").clicked()
                             || ctx.input(|i| i.key_pressed(egui::Key::ArrowRight))
                         {
-                            if let Some(idx) = self.current_media_idx {
-                                if let Some(media) = self.media_vec.get(idx) {
-                                    self.add_previously_seen_image(
-                                        media.filepath.display().to_string(),
-                                        ctx,
-                                    );
-                                }
-                            }
-
+                            self.scroll_grid_to_current = true;
                             self.current_media_idx = self.current_media_idx.map(|idx| {
                                 if idx == self.media_vec.len() - 1 {
                                     idx
@@ -249,10 +1205,373 @@ impl eframe::App for GlanceUi {
 
                     if ui.button("Clear Cache").clicked() {
                         ctx.forget_all_images();
+                        self.full_images.clear();
+                        self.thumbnails.clear();
+                    }
+
+                    if ui.button("Grid").clicked() {
+                        self.show_grid = !self.show_grid;
+                        self.scroll_grid_to_current = true;
+                    }
+
+                    if ui.button("Map").clicked() {
+                        self.show_map = !self.show_map;
+                    }
+
+                    if ui.button("Review Duplicates").clicked() {
+                        self.show_duplicates = true;
+                        self.update_duplicates();
                     }
+
+                    ui.checkbox(&mut self.culling, "Culling mode");
+                    ui.collapsing("Hotkeys", |ui| {
+                        for (key, action) in (1..).zip(&mut self.hotkey_edits) {
+                            ui.horizontal(|ui| {
+                                ui.label(format!("{key}"));
+                                hotkey_editor(ui, key, action);
+                            });
+                        }
+                        ui.label("P toggles pick and X toggles reject");
+                        if ui.button("Save").clicked() {
+                            self.save_hotkeys();
+                        }
+                    });
                 });
 
+                let mut show_duplicates = self.show_duplicates;
+                egui::Window::new("Duplicates")
+                    .open(&mut show_duplicates)
+                    .show(ctx, |ui| {
+                        if self.duplicate_groups.is_empty() {
+                            ui.label("No duplicates");
+                            return;
+                        }
+
+                        ui.horizontal(|ui| {
+                            if ui.button("Previous").clicked() && self.duplicate_group_idx > 0 {
+                                self.duplicate_group_idx -= 1;
+                                self.duplicate_keep_idx = 0;
+                                self.confirm_duplicate_delete = false;
+                                self.update_duplicate_labels();
+                            }
+                            ui.label(format!(
+                                "Set {} of {}",
+                                self.duplicate_group_idx + 1,
+                                self.duplicate_groups.len()
+                            ));
+                            if ui.button("Next").clicked()
+                                && self.duplicate_group_idx + 1 < self.duplicate_groups.len()
+                            {
+                                self.duplicate_group_idx += 1;
+                                self.duplicate_keep_idx = 0;
+                                self.confirm_duplicate_delete = false;
+                                self.update_duplicate_labels();
+                            }
+                        });
+
+                        let group = &self.duplicate_groups[self.duplicate_group_idx];
+                        let group = group.clone();
+                        let thumbnail_uris = group
+                            .iter()
+                            .map(|media| self.thumbnail_uri(media))
+                            .collect::<Vec<_>>();
+                        ui.columns(group.len(), |columns| {
+                            for (i, ((media, thumbnail_uri), ui)) in
+                                group.iter().zip(thumbnail_uris).zip(columns).enumerate()
+                            {
+                                self.thumbnail_ui(ui, media, thumbnail_uri, 200.0);
+                                ui.radio_value(&mut self.duplicate_keep_idx, i, "Keep");
+                                ui.label(format!("Path: {}", media.filepath.display()));
+                                ui.label(format!("Size: {}", media.size.0));
+                                if let Some(created_date) = media.created {
+                                    ui.label(format!(
+                                        "Taken: {}",
+                                        created_date.with_timezone(&Local)
+                                    ));
+                                }
+                                if let Some(labels) = self.duplicate_labels.get(i) {
+                                    ui.label(format!("Labels: {}", labels.join(", ")));
+                                }
+                            }
+                        });
+
+                        ui.separator();
+                        ui.horizontal(|ui| {
+                            ui.radio_value(
+                                &mut self.duplicate_removal,
+                                DuplicateRemoval::Quarantine,
+                                "Quarantine others",
+                            );
+                            ui.radio_value(
+                                &mut self.duplicate_removal,
+                                DuplicateRemoval::Delete,
+                                "Delete others",
+                            );
+                        });
+                        if self.duplicate_removal == DuplicateRemoval::Quarantine {
+                            ui.horizontal(|ui| {
+                                if ui.button("Quarantine folder…").clicked() {
+                                    if let Some(path) = rfd::FileDialog::new().pick_folder() {
+                                        self.quarantine_path = Some(path.display().to_string());
+                                    }
+                                }
+                                if let Some(path) = &self.quarantine_path {
+                                    ui.label(path);
+                                }
+                            });
+                        }
+                        let can_apply = self.duplicate_removal == DuplicateRemoval::Delete
+                            || self.quarantine_path.is_some();
+                        if self.confirm_duplicate_delete
+                            && self.duplicate_removal == DuplicateRemoval::Delete
+                        {
+                            ui.colored_label(
+                                Color32::RED,
+                                format!(
+                                    "Delete {} other copies from disk? This cannot be undone.",
+                                    group.len() - 1
+                                ),
+                            );
+                            ui.horizontal(|ui| {
+                                if ui.button("Delete").clicked() {
+                                    self.confirm_duplicate_delete = false;
+                                    self.resolve_duplicate_group();
+                                }
+                                if ui.button("Cancel").clicked() {
+                                    self.confirm_duplicate_delete = false;
+                                }
+                            });
+                        } else if ui
+                            .add_enabled(can_apply, egui::Button::new("Apply"))
+                            .clicked()
+                        {
+                            if self.duplicate_removal == DuplicateRemoval::Delete {
+                                self.confirm_duplicate_delete = true;
+                            } else {
+                                self.resolve_duplicate_group();
+                            }
+                        }
+                    });
+                self.show_duplicates = show_duplicates;
+
+                let mut show_grid = self.show_grid;
+                egui::Window::new("Grid")
+                    .open(&mut show_grid)
+                    .default_size([640.0, 480.0])
+                    .show(ctx, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                egui::Slider::new(&mut self.grid_tile_size, 64.0..=256.0)
+                                    .text("tile size"),
+                            );
+                            if ui.button("Select All").clicked() {
+                                self.selected.extend(0..self.media_vec.len());
+                            }
+                            if ui.button("Select None").clicked() {
+                                self.selected.clear();
+                            }
+                        });
+                        self.grid_ui(ui);
+                    });
+                self.show_grid = show_grid;
+
+                if !self.selected.is_empty() {
+                    egui::Window::new("Selection").show(ctx, |ui| {
+                        ui.label(format!("{} selected", self.selected.len()));
+                        ui.text_edit_singleline(&mut self.batch_label);
+                        ui.horizontal(|ui| {
+                            let has_label = !self.batch_label.is_empty();
+                            if ui
+                                .add_enabled(has_label, egui::Button::new("Add Label"))
+                                .clicked()
+                            {
+                                self.label_selection(true);
+                            }
+                            if ui
+                                .add_enabled(has_label, egui::Button::new("Delete Label"))
+                                .clicked()
+                            {
+                                self.label_selection(false);
+                            }
+                        });
+                    });
+                }
+
+                egui::Window::new("Timeline")
+                    .default_width(640.0)
+                    .show(ctx, |ui| {
+                        if self.timeline.is_empty() {
+                            ui.label("No media with a date taken");
+                            return;
+                        }
+                        let selected = self
+                            .filter_by_date
+                            .then_some((self.start_date, self.end_date));
+                        match self.timeline.ui(ui, selected) {
+                            Some(TimelineAction::Brush { start, end }) => {
+                                self.filter_by_date = true;
+                                self.start_date = start;
+                                self.end_date = end;
+                                self.update_media();
+                            }
+                            Some(TimelineAction::Jump(month)) => self.jump_to_month(month),
+                            None => {}
+                        }
+                        ui.label("Drag to filter by date, click to jump to a month");
+                        if self.filter_by_date && ui.button("Clear dates").clicked() {
+                            self.filter_by_date = false;
+                            self.update_media();
+                        }
+                    });
+
+                let mut show_map = self.show_map;
+                egui::Window::new("Map")
+                    .open(&mut show_map)
+                    .default_size([640.0, 400.0])
+                    .show(ctx, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.checkbox(&mut self.map.selecting, "Select area");
+                            ui.label(format!("{} media with a location", self.map_points.len()));
+                            if self.within_to_filter.is_some()
+                                && ui.button("Clear area").clicked()
+                            {
+                                self.within_to_filter = None;
+                                self.update_media();
+                            }
+                        });
+                        match self.map.ui(ui, &self.map_points, self.within_to_filter) {
+                            Some(MapAction::Select(bounds)) => {
+                                self.map.selecting = false;
+                                self.within_to_filter = Some(bounds);
+                                self.update_media();
+                            }
+                            Some(MapAction::Open(bounds)) => {
+                                self.within_to_filter = Some(bounds);
+                                self.update_media();
+                                self.scroll_grid_to_current = true;
+                            }
+                            None => {}
+                        }
+                    });
+                self.show_map = show_map;
+
                 egui::Window::new("Filters").show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        let response = ui.text_edit_singleline(&mut self.search_query);
+                        if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                            self.update_media();
+                        }
+                        ui.label("search").on_hover_text(
+                            "label:beach -label:blurry device:\"Canon EOS 40D\" \
+                            taken:2019..2021 on:12-25 rating:3 flag:pick near:48.1,11.5,5km within:35,-10,60,30",
+                        );
+                    });
+                    if let Some(search_error) = &self.search_error {
+                        ui.colored_label(Color32::RED, search_error);
+                    }
+                    ui.horizontal(|ui| {
+                        let mut changed = false;
+                        egui::ComboBox::from_label("saved search")
+                            .selected_text(self.saved_search.as_deref().unwrap_or("none"))
+                            .show_ui(ui, |ui| {
+                                changed |= ui
+                                    .selectable_value(&mut self.saved_search, None, "none")
+                                    .changed();
+                                for saved_search in &self.saved_searches {
+                                    changed |= ui
+                                        .selectable_value(
+                                            &mut self.saved_search,
+                                            Some(saved_search.name.clone()),
+                                            &saved_search.name,
+                                        )
+                                        .changed();
+                                }
+                            });
+                        if let (Some(index), Some(name)) = (&self.index, &self.saved_search) {
+                            if ui.button("delete").clicked() {
+                                if let Err(e) = index.delete_saved_search(name) {
+                                    warn!(self.logger, "failed to delete saved search";
+                                        "name" => name,
+                                        "error" => %e,
+                                    );
+                                }
+                                self.saved_search = None;
+                                self.update_saved_searches();
+                                changed = true;
+                            }
+                        }
+                        if changed {
+                            self.update_media();
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        let mut changed = false;
+                        egui::ComboBox::from_label("album")
+                            .selected_text(self.album_to_filter.as_deref().unwrap_or("none"))
+                            .show_ui(ui, |ui| {
+                                changed |= ui
+                                    .selectable_value(&mut self.album_to_filter, None, "none")
+                                    .changed();
+                                for album in &self.albums {
+                                    changed |= ui
+                                        .selectable_value(
+                                            &mut self.album_to_filter,
+                                            Some(album.name.clone()),
+                                            format!("{} ({})", album.name, album.count),
+                                        )
+                                        .changed();
+                                }
+                            });
+                        if changed {
+                            self.update_media();
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        let mut changed = false;
+                        egui::ComboBox::from_label("person")
+                            .selected_text(self.person_to_filter.as_deref().unwrap_or("anyone"))
+                            .show_ui(ui, |ui| {
+                                changed |= ui
+                                    .selectable_value(&mut self.person_to_filter, None, "anyone")
+                                    .changed();
+                                for person in &self.people {
+                                    changed |= ui
+                                        .selectable_value(
+                                            &mut self.person_to_filter,
+                                            Some(person.name.clone()),
+                                            format!("{} ({})", person.name, person.count),
+                                        )
+                                        .changed();
+                                }
+                            });
+                        if changed {
+                            self.update_media();
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        ui.text_edit_singleline(&mut self.save_search_name);
+                        if ui
+                            .add_enabled(
+                                !self.save_search_name.trim().is_empty(),
+                                egui::Button::new("save search"),
+                            )
+                            .clicked()
+                        {
+                            self.save_search();
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label(format!(
+                            "{} of {} media loaded",
+                            self.media_vec.len(),
+                            self.media_total
+                        ));
+                        if (self.media_vec.len() as i64) < self.media_total
+                            && ui.button("load more").clicked()
+                        {
+                            self.load_more_media();
+                        }
+                    });
                     if ui
                         .checkbox(&mut self.filter_by_date, "Filter by date")
                         .changed()
@@ -287,37 +1606,21 @@ impl eframe::App for GlanceUi {
                         });
                     }
 
-                    ui.horizontal(|ui| {
-                        egui::ComboBox::from_label("label")
-                            .selected_text(match &self.label_to_filter {
-                                Some(label) => label,
-                                None => "all",
-                            })
-                            .show_ui(ui, |ui| {
-                                if ui
-                                    .selectable_value(&mut self.label_to_filter, None, "all")
-                                    .changed()
-                                {
-                                    self.update_media();
-                                }
-                                for label in self.all_labels.clone() {
-                                    if ui
-                                        .selectable_value(
-                                            &mut self.label_to_filter,
-                                            Some(label.clone()),
-                                            label,
-                                        )
-                                        .changed()
-                                    {
-                                        self.update_media();
-                                    }
-                                }
-                            });
+                    egui::CollapsingHeader::new("labels").show(ui, |ui| {
+                        let mut changed =
+                            label_tree_ui(ui, &self.label_tree, &mut self.label_modes);
+                        if !self.label_modes.is_empty() && ui.button("clear").clicked() {
+                            self.label_modes.clear();
+                            changed = true;
+                        }
+                        if changed {
+                            self.update_media();
+                        }
                     });
 
                     if let Some(index) = &self.index {
                         if let Some(picked_path) = &self.picked_path {
-                            if let Some(label) = &self.label_to_filter {
+                            if let Some(label) = self.label_to_export() {
                                 if ui.button("export label").clicked() {
                                     if let Err(e) = index.export_images_with_label(
                                         picked_path.clone(),
@@ -400,12 +1703,60 @@ impl eframe::App for GlanceUi {
                                 }
                             });
                     });
+
+                    if ui
+                        .add(
+                            egui::Slider::new(
+                                &mut self.min_rating_to_filter,
+                                0..=media::MAX_RATING,
+                            )
+                            .text("minimum rating"),
+                        )
+                        .changed()
+                    {
+                        self.update_media();
+                    }
+
+                    ui.horizontal(|ui| {
+                        egui::ComboBox::from_label("flag")
+                            .selected_text(match self.flag_to_filter {
+                                Some(flag) => flag.as_str(),
+                                None => "all",
+                            })
+                            .show_ui(ui, |ui| {
+                                for (flag, text) in [
+                                    (None, "all"),
+                                    (Some(Flag::Pick), "pick"),
+                                    (Some(Flag::Reject), "reject"),
+                                ] {
+                                    if ui
+                                        .selectable_value(&mut self.flag_to_filter, flag, text)
+                                        .changed()
+                                    {
+                                        self.update_media();
+                                    }
+                                }
+                            });
+                    });
                 });
             }
 
             if let Some(idx) = self.current_media_idx {
+                let path = self.media_vec[idx].filepath.clone();
+                if self.face_regions_path.as_ref() != Some(&path) {
+                    self.update_face_regions(path.clone());
+                }
                 let media = self.media_vec.get(idx).unwrap();
-                let path = media.filepath.clone();
+                let mut rating = media.rating;
+                let mut flag = media.flag;
+                if self.text_edit_path.as_ref() != Some(&path) {
+                    self.text_edit_path = Some(path.clone());
+                    self.title_edit = media.title.clone().unwrap_or_default();
+                    self.caption_edit = media.caption.clone().unwrap_or_default();
+                    self.notes_edit = media.notes.clone().unwrap_or_default();
+                }
+                let mut save_text = false;
+                let mut region_edit = None;
 
                 egui::Window::new("Image Info").show(ctx, |ui| {
                     ui.label(format!("Path: {}", path.display()));
@@ -422,7 +1773,52 @@ impl eframe::App for GlanceUi {
                     if let Some(hash) = &media.hash {
                         ui.label(format!("Hash: {}", hash));
                     }
+                    ui.horizontal(|ui| {
+                        ui.label("Rating:");
+                        for star in 1..=media::MAX_RATING {
+                            let filled = star <= rating;
+                            if ui
+                                .selectable_label(filled, if filled { "★" } else { "☆" })
+                                .clicked()
+                            {
+                                rating = if rating == star { 0 } else { star };
+                            }
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Flag:");
+                        ui.radio_value(&mut flag, None, "none");
+                        ui.radio_value(&mut flag, Some(Flag::Pick), "pick");
+                        ui.radio_value(&mut flag, Some(Flag::Reject), "reject");
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Title:");
+                        ui.text_edit_singleline(&mut self.title_edit);
+                    });
+                    ui.label("Caption:");
+                    ui.text_edit_multiline(&mut self.caption_edit);
+                    ui.label("Notes:");
+                    ui.text_edit_multiline(&mut self.notes_edit);
+                    let edited = [
+                        (&self.title_edit, &media.title),
+                        (&self.caption_edit, &media.caption),
+                        (&self.notes_edit, &media.notes),
+                    ]
+                    .iter()
+                    .any(|(edit, text)| edit.trim() != text.as_deref().unwrap_or_default());
+                    save_text = ui
+                        .add_enabled(edited, egui::Button::new("Save text"))
+                        .clicked();
                 });
+                if rating != media.rating {
+                    self.set_rating(rating);
+                }
+                if flag != self.media_vec[idx].flag {
+                    self.set_flag(flag);
+                }
+                if save_text {
+                    self.save_media_text();
+                }
 
                 if let Some(index) = &mut self.index {
                     egui::Window::new("Labels").show(ctx, |ui| {
@@ -456,9 +1852,10 @@ impl eframe::App for GlanceUi {
                                         "error" => %e,
                                     );
                                 }
-                                if let Ok(all_labels) = index.get_all_labels() {
-                                    self.all_labels = all_labels;
+                                if let Ok(label_tree) = index.get_label_tree() {
+                                    self.label_tree = label_tree;
                                 }
+                                self.culling_labels = None;
                             }
                             ui.horizontal(|ui| {
                                 ui.label("Labels: ");
@@ -471,24 +1868,164 @@ impl eframe::App for GlanceUi {
                 }
 
                 egui::Window::new("Image").show(ctx, |ui| {
-                    let mut image = egui::widgets::Image::new(format!("file://{}", path.display()));
+                    let uri = format!("file://{}", path.display());
+                    self.full_images.show(&uri);
+                    let mut image = egui::widgets::Image::new(uri);
                     if self.rotation != 0 {
                         image = image.rotate(
                             self.rotation as f32 * std::f32::consts::PI / 2.0,
                             Vec2::splat(0.5),
                         );
                     }
-                    image.ui(ui);
+                    let image_rect = image.ui(ui).rect;
+                    // Regions are stored for the unrotated image
+                    if self.rotation == 0 {
+                        let to_screen = |x: f64, y: f64| {
+                            image_rect.min + Vec2::new(x as f32, y as f32) * image_rect.size()
+                        };
+                        let painter = ui.painter_at(image_rect);
+                        for (region, _) in &self.face_regions {
+                            let Rectangle {
+                                x,
+                                y,
+                                width,
+                                height,
+                            } = region.rectangle;
+                            let min = to_screen(x, y);
+                            painter.rect_stroke(
+                                Rect::from_min_max(min, to_screen(x + width, y + height)),
+                                0.0,
+                                Stroke::new(2.0, Color32::YELLOW),
+                            );
+                            if let Some(person) = &region.person {
+                                painter.text(
+                                    min,
+                                    Align2::LEFT_BOTTOM,
+                                    person,
+                                    FontId::proportional(14.0),
+                                    Color32::YELLOW,
+                                );
+                            }
+                        }
+                        if self.drawing_region {
+                            let response =
+                                ui.interact(image_rect, ui.id().with("draw_region"), Sense::drag());
+                            let to_image =
+                                |pos: Pos2| ((pos - image_rect.min) / image_rect.size()).to_pos2();
+                            if response.drag_started() {
+                                self.region_drag_start =
+                                    response.interact_pointer_pos().map(to_image);
+                            }
+                            let end = response.interact_pointer_pos().map(to_image);
+                            if let (Some(start), Some(end)) = (self.region_drag_start, end) {
+                                let drawn = Rect::from_two_pos(start, end);
+                                painter.rect_stroke(
+                                    Rect::from_min_max(
+                                        image_rect.min + drawn.min.to_vec2() * image_rect.size(),
+                                        image_rect.min + drawn.max.to_vec2() * image_rect.size(),
+                                    ),
+                                    0.0,
+                                    Stroke::new(2.0, Color32::LIGHT_BLUE),
+                                );
+                                if response.drag_released() {
+                                    self.region_drag_start = None;
+                                    region_edit = Some(RegionEdit::Add(Rectangle {
+                                        x: drawn.min.x as f64,
+                                        y: drawn.min.y as f64,
+                                        width: drawn.width() as f64,
+                                        height: drawn.height() as f64,
+                                    }));
+                                }
+                            }
+                        }
+                    }
 
                     ui.horizontal(|ui| {
                         for i in 1..10 {
-                            if let Some(next_media) = self.media_vec.get(i + idx) {
-                                ui.image(format!("file://{}", next_media.filepath.display()));
+                            if let Some(next_media) = self.media_vec.get(i + idx).cloned() {
+                                let uri = self.thumbnail_uri(&next_media);
+                                self.thumbnail_ui(ui, &next_media, uri, 96.0);
                             }
                         }
                     });
                 });
 
+                egui::Window::new("People").show(ctx, |ui| {
+                    for (i, (region, person)) in self.face_regions.iter_mut().enumerate() {
+                        ui.horizontal(|ui| {
+                            ui.text_edit_singleline(person);
+                            let edited = person.trim() != region.person.as_deref().unwrap_or("");
+                            if ui.add_enabled(edited, egui::Button::new("save")).clicked() {
+                                region_edit = Some(RegionEdit::Rename(i));
+                            }
+                            if ui.button("delete").clicked() {
+                                region_edit = Some(RegionEdit::Delete(region.id));
+                            }
+                        });
+                    }
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut self.drawing_region, "Draw region")
+                            .on_hover_text("Drag on the image to add a face region");
+                        ui.text_edit_singleline(&mut self.new_region_person);
+                    });
+                });
+                if let Some(edit) = region_edit {
+                    self.edit_face_region(path, edit);
+                }
+
+                let mut show_memories = self.show_memories;
+                let mut memory_year_to_show = None;
+                egui::Window::new("On This Day")
+                    .open(&mut show_memories)
+                    .show(ctx, |ui| {
+                        ui.horizontal(|ui| {
+                            let response = ui.add(
+                                egui::Slider::new(&mut self.memories_config.window_days, 0..=30)
+                                    .text("days around today"),
+                            );
+                            if response.drag_released() || response.lost_focus() {
+                                self.update_memories();
+                            }
+                        });
+                        if self.memories.is_empty() {
+                            ui.label("No memories");
+                        }
+                        let memories = self
+                            .memories
+                            .iter()
+                            .map(|year| {
+                                let media = year
+                                    .media
+                                    .iter()
+                                    .take(MEMORY_THUMBNAILS)
+                                    .map(|media| (media.clone(), self.thumbnail_uri(media)))
+                                    .collect::<Vec<_>>();
+                                (year.years_ago, year.year, year.media.len(), media)
+                            })
+                            .collect::<Vec<_>>();
+                        egui::ScrollArea::vertical().show(ui, |ui| {
+                            for (years_ago, year, count, thumbnails) in memories {
+                                ui.horizontal(|ui| {
+                                    let years = if years_ago == 1 { "year" } else { "years" };
+                                    ui.strong(format!("{years_ago} {years} ago ({year})"));
+                                    ui.label(format!("{count} media"));
+                                    if ui.button("Show").clicked() {
+                                        memory_year_to_show = Some(years_ago);
+                                    }
+                                });
+                                ui.horizontal(|ui| {
+                                    for (media, uri) in thumbnails {
+                                        self.thumbnail_ui(ui, &media, uri, 96.0);
+                                    }
+                                });
+                            }
+                        });
+                    });
+                self.show_memories = show_memories;
+                if let Some(years_ago) = memory_year_to_show {
+                    self.show_memory_year(years_ago);
+                }
+
                 egui::Window::new("Stats").show(ctx, |ui| {
                     if let Some(stats_string) = self.filtered_stats_string.clone() {
                         ui.label(stats_string);
@@ -496,5 +2033,103 @@ impl eframe::App for GlanceUi {
                 });
             }
         });
+
+        if self.culling && self.index.is_some() {
+            self.handle_culling_keys(ctx);
+            self.update_culling_labels();
+            self.culling_hud(ctx);
+        }
+
+        let missing = self.missing_thumbnails.take();
+        if !missing.is_empty() {
+            // Check back for the thumbnails being generated
+            ctx.request_repaint_after(std::time::Duration::from_millis(100));
+        }
+        if let Some(loader) = &self.thumbnail_loader {
+            loader.request(missing);
+        }
+        self.full_images.evict(ctx);
+        self.thumbnails.evict(ctx);
+    }
+}
+
+/// Rating as filled and empty stars
+fn stars(rating: u8) -> String {
+    (1..=media::MAX_RATING)
+        .map(|star| if star <= rating { '★' } else { '☆' })
+        .collect()
+}
+
+/// Show a mode combobox for every label, nesting children under their parent
+///
+/// Returns whether any mode changed.
+fn label_tree_ui(
+    ui: &mut egui::Ui,
+    nodes: &[LabelNode],
+    label_modes: &mut BTreeMap<String, LabelMode>,
+) -> bool {
+    let mut changed = false;
+    for node in nodes {
+        let mut mode = label_modes.get(&node.label).copied();
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source(("label", &node.label))
+                .selected_text(LabelMode::name(mode))
+                .show_ui(ui, |ui| {
+                    for option in [
+                        None,
+                        Some(LabelMode::All),
+                        Some(LabelMode::Any),
+                        Some(LabelMode::None),
+                    ] {
+                        changed |= ui
+                            .selectable_value(&mut mode, option, LabelMode::name(option))
+                            .changed();
+                    }
+                });
+            ui.label(&node.name);
+        });
+        match mode {
+            Some(mode) => label_modes.insert(node.label.clone(), mode),
+            None => label_modes.remove(&node.label),
+        };
+        if !node.children.is_empty() {
+            ui.indent(("label children", &node.label), |ui| {
+                changed |= label_tree_ui(ui, &node.children, label_modes);
+            });
+        }
+    }
+    changed
+}
+
+/// Choose the action bound to a number key
+fn hotkey_editor(ui: &mut egui::Ui, key: u8, action: &mut Option<HotkeyAction>) {
+    let selected_text = match action {
+        None => "unbound",
+        Some(HotkeyAction::ToggleLabel(_)) => "label",
+        Some(HotkeyAction::SetRating(_)) => "rating",
+    };
+    egui::ComboBox::from_id_source(("hotkey", key))
+        .selected_text(selected_text)
+        .show_ui(ui, |ui| {
+            if ui.selectable_label(action.is_none(), "unbound").clicked() {
+                *action = None;
+            }
+            let is_label = matches!(action, Some(HotkeyAction::ToggleLabel(_)));
+            if ui.selectable_label(is_label, "label").clicked() && !is_label {
+                *action = Some(HotkeyAction::ToggleLabel(String::new()));
+            }
+            let is_rating = matches!(action, Some(HotkeyAction::SetRating(_)));
+            if ui.selectable_label(is_rating, "rating").clicked() && !is_rating {
+                *action = Some(HotkeyAction::SetRating(key.min(media::MAX_RATING)));
+            }
+        });
+    match action {
+        Some(HotkeyAction::ToggleLabel(label)) => {
+            ui.text_edit_singleline(label);
+        }
+        Some(HotkeyAction::SetRating(rating)) => {
+            ui.add(egui::Slider::new(rating, 1..=media::MAX_RATING));
+        }
+        None => {}
     }
 }
@@ -0,0 +1,294 @@
+//! Map of where media were taken over an offline basemap
+//!
+//! The basemap is a mask of land generated by `scripts/land_mask.py` and drawn in an
+//! equirectangular projection, so a point on screen is a linear function of its longitude and
+//! latitude. Media are clustered by cells of the map at the current zoom so any number of them
+//! can be drawn.
+
+use std::collections::HashMap;
+
+use egui::{
+    Align2, Color32, ColorImage, FontId, Pos2, Rect, Sense, Stroke, TextureHandle, TextureOptions,
+    Vec2,
+};
+use glance_lib::index::media::Bounds;
+
+/// Land of the whole world as a binary PBM, first row at 90° north and first column at 180° west
+const LAND_MASK: &[u8] = include_bytes!("../assets/land.pbm");
+const WATER: Color32 = Color32::from_rgb(170, 200, 225);
+const LAND: Color32 = Color32::from_rgb(225, 220, 200);
+const MARKER: Color32 = Color32::from_rgb(230, 110, 40);
+/// Width and height of the cells media are clustered by
+const CLUSTER_SIZE: f32 = 40.0;
+/// Most pixels per degree
+const MAX_ZOOM: f32 = 20_000.0;
+/// Degrees between lines of latitude and longitude
+const GRATICULE: f32 = 30.0;
+/// Degrees the bounds of a cluster are widened by so rounding never leaves out media on its edges
+const CELL_PADDING: f64 = 1e-9;
+
+/// What was done to the map this frame
+pub enum MapAction {
+    /// A rectangle was drawn around these bounds
+    Select(Bounds),
+    /// The cluster of media inside these bounds was clicked
+    Open(Bounds),
+}
+
+#[derive(Default)]
+pub struct MapView {
+    /// Longitude and latitude at the center of the view
+    center: Pos2,
+    /// Pixels per degree, at least enough to fill the width of the view with the world
+    zoom: f32,
+    /// Whether dragging draws a rectangle to select instead of panning
+    pub selecting: bool,
+    /// Longitude and latitude the rectangle being drawn started at
+    selection_start: Option<Pos2>,
+    land: Option<TextureHandle>,
+}
+
+/// Cluster of media in a cell of the map
+struct Cluster {
+    count: usize,
+    /// Sum of the longitudes of the media for their mean
+    longitude: f64,
+    /// Sum of the latitudes of the media for their mean
+    latitude: f64,
+}
+
+impl MapView {
+    /// Draw `points` of latitude and longitude with `selected` outlined
+    pub fn ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        points: &[(f64, f64)],
+        selected: Option<Bounds>,
+    ) -> Option<MapAction> {
+        let (rect, response) = ui.allocate_exact_size(ui.available_size(), Sense::click_and_drag());
+        self.zoom = self.zoom.clamp(rect.width() / 360.0, MAX_ZOOM);
+        let projection = Projection {
+            rect,
+            center: self.center,
+            zoom: self.zoom,
+        };
+        let mut action = None;
+
+        if let Some(hover) = response.hover_pos() {
+            let scroll = ui.input(|i| i.scroll_delta.y);
+            if scroll != 0.0 {
+                // Zoom around the pointer so the point under it stays put
+                let before = projection.to_degrees(hover);
+                self.zoom =
+                    (self.zoom * (scroll / 200.0).exp()).clamp(rect.width() / 360.0, MAX_ZOOM);
+                let after = Projection {
+                    zoom: self.zoom,
+                    ..projection
+                }
+                .to_degrees(hover);
+                self.center += before - after;
+            }
+        }
+        if self.selecting {
+            if response.drag_started() {
+                let origin = ui.input(|i| i.pointer.press_origin());
+                self.selection_start = origin.map(|pos| projection.to_degrees(pos));
+            }
+            if response.drag_released() {
+                if let (Some(start), Some(end)) = (
+                    self.selection_start.take(),
+                    ui.input(|i| i.pointer.interact_pos()),
+                ) {
+                    action = Some(MapAction::Select(bounds(start, projection.to_degrees(end))));
+                }
+            }
+        } else if response.dragged() {
+            let delta = response.drag_delta() / self.zoom;
+            self.center += Vec2::new(-delta.x, delta.y);
+        }
+        self.center.x = self.center.x.clamp(-180.0, 180.0);
+        self.center.y = self.center.y.clamp(-90.0, 90.0);
+        let projection = Projection {
+            rect,
+            center: self.center,
+            zoom: self.zoom,
+        };
+
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 0.0, WATER);
+        let land = self.land.get_or_insert_with(|| {
+            let image = land_image(LAND_MASK).unwrap_or_else(|| ColorImage::new([1, 1], WATER));
+            ui.ctx().load_texture("land", image, TextureOptions::LINEAR)
+        });
+        painter.image(
+            land.id(),
+            Rect::from_two_pos(
+                projection.to_screen(Pos2::new(-180.0, 90.0)),
+                projection.to_screen(Pos2::new(180.0, -90.0)),
+            ),
+            Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0)),
+            Color32::WHITE,
+        );
+        let graticule = Stroke::new(1.0, Color32::from_white_alpha(60));
+        for step in 0..=(360.0 / GRATICULE) as i32 {
+            let longitude = -180.0 + step as f32 * GRATICULE;
+            painter.line_segment(
+                [
+                    projection.to_screen(Pos2::new(longitude, 90.0)),
+                    projection.to_screen(Pos2::new(longitude, -90.0)),
+                ],
+                graticule,
+            );
+        }
+        for step in 0..=(180.0 / GRATICULE) as i32 {
+            let latitude = -90.0 + step as f32 * GRATICULE;
+            painter.line_segment(
+                [
+                    projection.to_screen(Pos2::new(-180.0, latitude)),
+                    projection.to_screen(Pos2::new(180.0, latitude)),
+                ],
+                graticule,
+            );
+        }
+
+        // Cells are computed in f64 like the coordinates in the index so their bounds contain
+        // every media clustered in them
+        let cell_degrees = f64::from(CLUSTER_SIZE / self.zoom);
+        let mut clusters: HashMap<(i32, i32), Cluster> = HashMap::new();
+        for &(latitude, longitude) in points {
+            let cell = (
+                (longitude / cell_degrees).floor() as i32,
+                (latitude / cell_degrees).floor() as i32,
+            );
+            let cluster = clusters.entry(cell).or_insert(Cluster {
+                count: 0,
+                longitude: 0.0,
+                latitude: 0.0,
+            });
+            cluster.count += 1;
+            cluster.longitude += longitude;
+            cluster.latitude += latitude;
+        }
+        let pointer = response.hover_pos();
+        let mut hovered = None;
+        for (&(x, y), cluster) in &clusters {
+            let mean = Pos2::new(
+                (cluster.longitude / cluster.count as f64) as f32,
+                (cluster.latitude / cluster.count as f64) as f32,
+            );
+            let center = projection.to_screen(mean);
+            if !rect.expand(CLUSTER_SIZE).contains(center) {
+                continue;
+            }
+            let radius = 5.0 + 3.0 * (cluster.count as f32).log2();
+            painter.circle(center, radius, MARKER, Stroke::new(1.5, Color32::WHITE));
+            if cluster.count > 1 {
+                painter.text(
+                    center,
+                    Align2::CENTER_CENTER,
+                    cluster.count.to_string(),
+                    FontId::proportional(11.0),
+                    Color32::WHITE,
+                );
+            }
+            if pointer.is_some_and(|pointer| pointer.distance(center) <= radius) {
+                hovered = Some((cluster.count, cell_bounds((x, y), cell_degrees)));
+            }
+        }
+
+        let selection = Stroke::new(2.0, ui.visuals().selection.stroke.color);
+        if let Some(selected) = selected {
+            let corners = Rect::from_two_pos(
+                projection.to_screen(Pos2::new(selected.west as f32, selected.north as f32)),
+                projection.to_screen(Pos2::new(selected.east as f32, selected.south as f32)),
+            );
+            painter.rect_stroke(corners, 0.0, selection);
+        }
+        if let (Some(start), Some(pointer)) = (self.selection_start, pointer) {
+            painter.rect_stroke(
+                Rect::from_two_pos(projection.to_screen(start), pointer),
+                0.0,
+                selection,
+            );
+        }
+
+        if !self.selecting && response.clicked() {
+            if let Some((_, bounds)) = hovered {
+                action = Some(MapAction::Open(bounds));
+            }
+        }
+        if let Some((count, _)) = hovered {
+            response.on_hover_text(format!("{count} media, click to view"));
+        }
+        action
+    }
+}
+
+/// Conversion between longitude and latitude and points on the screen
+#[derive(Clone, Copy)]
+struct Projection {
+    rect: Rect,
+    center: Pos2,
+    zoom: f32,
+}
+
+impl Projection {
+    fn to_screen(self, degrees: Pos2) -> Pos2 {
+        let offset = (degrees - self.center) * self.zoom;
+        self.rect.center() + Vec2::new(offset.x, -offset.y)
+    }
+
+    fn to_degrees(self, screen: Pos2) -> Pos2 {
+        let offset = (screen - self.rect.center()) / self.zoom;
+        self.center + Vec2::new(offset.x, -offset.y)
+    }
+}
+
+/// Bounds of the rectangle between two corners in longitude and latitude
+fn bounds(a: Pos2, b: Pos2) -> Bounds {
+    let longitude = |x: f32| f64::from(x.clamp(-180.0, 180.0));
+    let latitude = |y: f32| f64::from(y.clamp(-90.0, 90.0));
+    Bounds {
+        south: latitude(a.y.min(b.y)),
+        west: longitude(a.x.min(b.x)),
+        north: latitude(a.y.max(b.y)),
+        east: longitude(a.x.max(b.x)),
+    }
+}
+
+/// Bounds of the cell at `(x, y)` in a grid of cells `cell_degrees` wide and high
+fn cell_bounds((x, y): (i32, i32), cell_degrees: f64) -> Bounds {
+    let longitude = |x: i32| (f64::from(x) * cell_degrees).clamp(-180.0, 180.0);
+    let latitude = |y: i32| (f64::from(y) * cell_degrees).clamp(-90.0, 90.0);
+    Bounds {
+        south: latitude(y) - CELL_PADDING,
+        west: longitude(x) - CELL_PADDING,
+        north: latitude(y + 1) + CELL_PADDING,
+        east: longitude(x + 1) + CELL_PADDING,
+    }
+}
+
+/// Decode a binary PBM into an image with land in `LAND` and the rest transparent
+fn land_image(pbm: &[u8]) -> Option<ColorImage> {
+    let mut parts = pbm.splitn(4, |byte| byte.is_ascii_whitespace());
+    if parts.next()? != b"P4" {
+        return None;
+    }
+    let mut dimension =
+        || -> Option<usize> { std::str::from_utf8(parts.next()?).ok()?.parse().ok() };
+    let width = dimension()?;
+    let height = dimension()?;
+    let bits = parts.next()?;
+    let row_bytes = width.div_ceil(8);
+    if bits.len() < row_bytes * height {
+        return None;
+    }
+    let pixels = (0..height)
+        .flat_map(|y| (0..width).map(move |x| bits[y * row_bytes + x / 8] & (0x80 >> (x % 8))))
+        .map(|bit| if bit != 0 { LAND } else { Color32::TRANSPARENT })
+        .collect();
+    Some(ColorImage {
+        size: [width, height],
+        pixels,
+    })
+}
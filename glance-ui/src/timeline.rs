@@ -0,0 +1,160 @@
+//! Histogram of how much media was taken each month
+//!
+//! Dragging across the bars brushes a range of months and clicking a bar picks its month. Months
+//! without media between the first and last month are drawn as gaps so the bars are to scale.
+
+use std::collections::BTreeMap;
+
+use chrono::{Datelike, Months, NaiveDate};
+use egui::{Align2, Color32, FontId, Pos2, Rect, Sense, Vec2};
+
+/// Height of the tallest bar
+const BAR_HEIGHT: f32 = 80.0;
+/// Space below the bars for the years
+const LABEL_HEIGHT: f32 = 14.0;
+/// Least space between the labels of years
+const LABEL_SPACING: f32 = 36.0;
+
+/// What was done to the timeline this frame
+pub enum TimelineAction {
+    /// Dragged across the months from `start` to `end`, both inclusive
+    Brush { start: NaiveDate, end: NaiveDate },
+    /// Clicked the bar of the month starting on this day
+    Jump(NaiveDate),
+}
+
+#[derive(Default)]
+pub struct Timeline {
+    /// First day of every month from the first to the last month with media and its count
+    months: Vec<(NaiveDate, i64)>,
+    max_count: i64,
+    /// Bars the current drag started and ended on
+    brush: Option<(usize, usize)>,
+}
+
+impl Timeline {
+    /// Timeline from the count of media keyed by the first day of each month
+    pub fn new(counts: &BTreeMap<NaiveDate, i64>) -> Self {
+        let mut months = Vec::new();
+        if let (Some((&first, _)), Some((&last, _))) =
+            (counts.first_key_value(), counts.last_key_value())
+        {
+            let mut month = Some(first);
+            while let Some(current) = month.filter(|month| *month <= last) {
+                months.push((current, counts.get(&current).copied().unwrap_or_default()));
+                month = current.checked_add_months(Months::new(1));
+            }
+        }
+        let max_count = months
+            .iter()
+            .map(|(_, count)| *count)
+            .max()
+            .unwrap_or_default();
+        Self {
+            months,
+            max_count,
+            brush: None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.months.is_empty()
+    }
+
+    /// Draw the bars with the months overlapping `selected` highlighted
+    pub fn ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        selected: Option<(NaiveDate, NaiveDate)>,
+    ) -> Option<TimelineAction> {
+        let size = Vec2::new(ui.available_width(), BAR_HEIGHT + LABEL_HEIGHT);
+        let (rect, response) = ui.allocate_exact_size(size, Sense::click_and_drag());
+        if self.months.is_empty() {
+            return None;
+        }
+        let bar_width = rect.width() / self.months.len() as f32;
+        let last_idx = self.months.len() - 1;
+        let month_at = |pos: Pos2| {
+            let idx = ((pos.x - rect.left()) / bar_width).floor().max(0.0) as usize;
+            idx.min(last_idx)
+        };
+
+        if response.drag_started() {
+            let origin = ui.input(|i| i.pointer.press_origin());
+            self.brush = origin.map(|pos| (month_at(pos), month_at(pos)));
+        }
+        if response.dragged() {
+            if let (Some((start, _)), Some(pos)) = (self.brush, response.interact_pointer_pos()) {
+                self.brush = Some((start, month_at(pos)));
+            }
+        }
+
+        let painter = ui.painter_at(rect);
+        let gap = if bar_width > 3.0 { 1.0 } else { 0.0 };
+        let mut last_label_x = f32::NEG_INFINITY;
+        for (idx, (month, count)) in self.months.iter().enumerate() {
+            let brushed = self
+                .brush
+                .is_some_and(|(start, end)| (start.min(end)..=start.max(end)).contains(&idx));
+            let in_selected = selected
+                .is_some_and(|(start, end)| *month <= end && last_day_of_month(*month) >= start);
+            let color = if brushed {
+                ui.visuals().selection.stroke.color
+            } else if in_selected {
+                ui.visuals().selection.bg_fill
+            } else {
+                Color32::GRAY
+            };
+            let left = rect.left() + idx as f32 * bar_width;
+            if *count > 0 {
+                let height = (BAR_HEIGHT * *count as f32 / self.max_count as f32).max(1.0);
+                let bottom = rect.top() + BAR_HEIGHT;
+                painter.rect_filled(
+                    Rect::from_min_max(
+                        Pos2::new(left, bottom - height),
+                        Pos2::new(left + bar_width - gap, bottom),
+                    ),
+                    0.0,
+                    color,
+                );
+            }
+            if (idx == 0 || month.month() == 1) && left - last_label_x >= LABEL_SPACING {
+                painter.text(
+                    Pos2::new(left, rect.top() + BAR_HEIGHT + 1.0),
+                    Align2::LEFT_TOP,
+                    month.year().to_string(),
+                    FontId::proportional(10.0),
+                    ui.visuals().text_color(),
+                );
+                last_label_x = left;
+            }
+        }
+
+        let mut action = None;
+        if response.clicked() {
+            self.brush = None;
+            if let Some(pos) = response.interact_pointer_pos() {
+                action = Some(TimelineAction::Jump(self.months[month_at(pos)].0));
+            }
+        } else if response.drag_released() {
+            if let Some((start, end)) = self.brush.take() {
+                action = Some(TimelineAction::Brush {
+                    start: self.months[start.min(end)].0,
+                    end: last_day_of_month(self.months[start.max(end)].0),
+                });
+            }
+        }
+        if let Some(pos) = response.hover_pos() {
+            let (month, count) = self.months[month_at(pos)];
+            response.on_hover_text(format!("{}: {count}", month.format("%B %Y")));
+        }
+        action
+    }
+}
+
+pub fn last_day_of_month(first: NaiveDate) -> NaiveDate {
+    first
+        .checked_add_months(Months::new(1))
+        .and_then(|next| next.pred_opt())
+        .unwrap_or(first)
+}
@@ -0,0 +1,175 @@
+use std::collections::{hash_map::Entry, HashMap};
+
+/// A [BK-tree](https://en.wikipedia.org/wiki/BK-tree) over 64 bit hashes using the hamming
+/// distance as the metric
+///
+/// Each key is stored with an associated value so that callers can map matches back to the item
+/// that produced the hash.
+#[derive(Debug)]
+pub struct BkTree<V> {
+    root: Option<Node<V>>,
+}
+
+#[derive(Debug)]
+struct Node<V> {
+    key: u64,
+    value: V,
+    children: HashMap<u32, Node<V>>,
+}
+
+impl<V> Default for BkTree<V> {
+    fn default() -> Self {
+        Self { root: None }
+    }
+}
+
+impl<V> BkTree<V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, key: u64, value: V) {
+        let mut node = match &mut self.root {
+            Some(root) => root,
+            None => {
+                self.root = Some(Node::new(key, value));
+                return;
+            }
+        };
+        loop {
+            let distance = hamming_distance(node.key, key);
+            match node.children.entry(distance) {
+                Entry::Occupied(entry) => node = entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    entry.insert(Node::new(key, value));
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Find all values whose key is within `threshold` of `key`
+    pub fn find(&self, key: u64, threshold: u32) -> Vec<(u32, &V)> {
+        let mut result = Vec::new();
+        let mut stack = Vec::new();
+        if let Some(root) = &self.root {
+            stack.push(root);
+        }
+        while let Some(node) = stack.pop() {
+            let distance = hamming_distance(node.key, key);
+            if distance <= threshold {
+                result.push((distance, &node.value));
+            }
+            // By the triangle inequality only children within this range can match
+            let low = distance.saturating_sub(threshold);
+            let high = distance + threshold;
+            stack.extend(
+                node.children
+                    .iter()
+                    .filter(|(d, _)| (low..=high).contains(*d))
+                    .map(|(_, child)| child),
+            );
+        }
+        result
+    }
+}
+
+impl<V> Node<V> {
+    fn new(key: u64, value: V) -> Self {
+        Self {
+            key,
+            value,
+            children: HashMap::new(),
+        }
+    }
+}
+
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted<'a>(mut matches: Vec<(u32, &&'a str)>) -> Vec<(u32, &'a str)> {
+        matches.sort();
+        matches.into_iter().map(|(d, v)| (d, *v)).collect()
+    }
+
+    #[test]
+    fn hamming_distance_test() {
+        assert_eq!(hamming_distance(0, 0), 0);
+        assert_eq!(hamming_distance(0b1010, 0b0110), 2);
+        assert_eq!(hamming_distance(0, u64::MAX), 64);
+    }
+
+    #[test]
+    fn find_empty_test() {
+        let tree: BkTree<&str> = BkTree::new();
+        assert!(tree.find(0, 64).is_empty());
+    }
+
+    #[test]
+    fn find_test() {
+        let mut tree = BkTree::new();
+        tree.insert(0b0000, "zero");
+        tree.insert(0b0001, "one bit");
+        tree.insert(0b0011, "two bits");
+        tree.insert(0b1111, "four bits");
+        tree.insert(u64::MAX, "all bits");
+
+        assert_eq!(sorted(tree.find(0, 0)), vec![(0, "zero")]);
+        assert_eq!(
+            sorted(tree.find(0, 2)),
+            vec![(0, "zero"), (1, "one bit"), (2, "two bits")]
+        );
+        assert_eq!(
+            sorted(tree.find(0b0111, 1)),
+            vec![(1, "four bits"), (1, "two bits")]
+        );
+        assert_eq!(sorted(tree.find(u64::MAX, 3)), vec![(0, "all bits")]);
+        assert_eq!(tree.find(0, 64).len(), 5);
+    }
+
+    #[test]
+    fn duplicate_keys_test() {
+        let mut tree = BkTree::new();
+        tree.insert(42, "first");
+        tree.insert(42, "second");
+        assert_eq!(sorted(tree.find(42, 0)), vec![(0, "first"), (0, "second")]);
+    }
+
+    /// Matches from the tree agree with a linear scan
+    #[test]
+    fn find_matches_linear_scan_test() {
+        // Deterministic pseudo random keys
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let keys: Vec<u64> = (0..500)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state
+            })
+            .collect();
+        let mut tree = BkTree::new();
+        for (i, key) in keys.iter().enumerate() {
+            tree.insert(*key, i);
+        }
+        for query in keys.iter().step_by(50) {
+            for threshold in [0, 8, 24, 32] {
+                let mut found: Vec<usize> = tree
+                    .find(*query, threshold)
+                    .into_iter()
+                    .map(|(_, i)| *i)
+                    .collect();
+                found.sort();
+                let expected: Vec<usize> = (0..keys.len())
+                    .filter(|i| hamming_distance(keys[*i], *query) <= threshold)
+                    .collect();
+                assert_eq!(found, expected);
+            }
+        }
+    }
+}
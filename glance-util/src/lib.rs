@@ -1,3 +1,4 @@
+pub mod bk_tree;
 pub mod canonicalized_path_buf;
 pub mod hash_map_with_unknown;
 
@@ -0,0 +1,80 @@
+#!/usr/bin/env python3
+"""Generate the offline basemap of glance-ui from the Natural Earth land polygons.
+
+Every cell of an equirectangular grid whose center is inside a land polygon is land. The mask is
+written as a binary PBM with the first row at 90 degrees north and the first column at 180
+degrees west.
+
+The polygons are the 1:110m land of Natural Earth, which is in the public domain, for example
+https://raw.githubusercontent.com/nvkelso/natural-earth-vector/master/geojson/ne_110m_land.geojson
+"""
+
+import argparse
+import json
+
+# Cells per degree
+RESOLUTION = 2
+
+
+def polygons(geometry):
+    """Polygons of a Polygon or MultiPolygon, each a list of closed rings of [lon, lat]"""
+    if geometry["type"] == "Polygon":
+        return [geometry["coordinates"]]
+    if geometry["type"] == "MultiPolygon":
+        return geometry["coordinates"]
+    return []
+
+
+def fill_polygon(land, polygon, width, height):
+    """Set the cells whose centers are inside the polygon, treating inner rings as holes"""
+    edges = []
+    for ring in polygon:
+        for (lon1, lat1), (lon2, lat2) in zip(ring, ring[1:]):
+            if lat1 != lat2:
+                edges.append((lon1, lat1, lon2, lat2))
+    for y in range(height):
+        latitude = 90.0 - (y + 0.5) / RESOLUTION
+        crossings = sorted(
+            lon1 + (latitude - lat1) * (lon2 - lon1) / (lat2 - lat1)
+            for lon1, lat1, lon2, lat2 in edges
+            if (lat1 <= latitude) != (lat2 <= latitude)
+        )
+        # Crossings alternate between entering and leaving the polygon
+        for west, east in zip(crossings[::2], crossings[1::2]):
+            first = max(0, round((west + 180.0) * RESOLUTION))
+            last = min(width, round((east + 180.0) * RESOLUTION))
+            for x in range(first, last):
+                land[y][x] = True
+
+
+def main():
+    parser = argparse.ArgumentParser(description="Generate the land mask used by the map view.")
+    parser.add_argument("land", help="Path to ne_110m_land.geojson from Natural Earth")
+    parser.add_argument(
+        "output",
+        nargs="?",
+        default="glance-ui/assets/land.pbm",
+        help="Path to write the PBM to",
+    )
+    args = parser.parse_args()
+
+    width = 360 * RESOLUTION
+    height = 180 * RESOLUTION
+    land = [[False] * width for _ in range(height)]
+    with open(args.land, encoding="utf-8") as f:
+        for feature in json.load(f)["features"]:
+            for polygon in polygons(feature["geometry"]):
+                fill_polygon(land, polygon, width, height)
+
+    with open(args.output, "wb") as f:
+        f.write(f"P4\n{width} {height}\n".encode())
+        for row in land:
+            packed = bytearray((width + 7) // 8)
+            for x, is_land in enumerate(row):
+                if is_land:
+                    packed[x // 8] |= 0x80 >> (x % 8)
+            f.write(packed)
+
+
+if __name__ == "__main__":
+    main()